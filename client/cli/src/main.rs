@@ -2,16 +2,18 @@ use core::fmt;
 use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Debug;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::process::exit;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bitcoin::{secp256k1, Address, Network, Transaction};
 use clap::{Parser, Subcommand};
 use fedimint_api::config::{ClientConfig, ModuleGenRegistry};
 use fedimint_api::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_api::db::Database;
 use fedimint_api::module::registry::ModuleDecoderRegistry;
@@ -22,23 +24,25 @@ use fedimint_core::config::load_from_file;
 use fedimint_core::modules::ln::common::LightningDecoder;
 use fedimint_core::modules::ln::contracts::ContractId;
 use fedimint_core::modules::ln::LightningGen;
-use fedimint_core::modules::smolfs::{SmolFSConfigGenerator, SmolFSEntry, SmolFSInput};
+use fedimint_core::modules::smolfs::common::{EntryMeta, SmolFSDecoder, VersionMeta};
+use fedimint_core::modules::smolfs::SmolFSConfigGenerator;
 use fedimint_core::modules::wallet::common::WalletDecoder;
 use fedimint_core::modules::wallet::txoproof::TxOutProof;
 use fedimint_core::modules::wallet::WalletGen;
 use fedimint_mint::common::MintDecoder;
 use fedimint_mint::MintGen;
 use mint_client::api::{
-    FederationApiExt, GlobalFederationApi, IFederationApi, SmolFSFederationApi, WsFederationApi,
-    WsFederationConnect,
+    FederationApiExt, GlobalFederationApi, IFederationApi, WsFederationApi, WsFederationConnect,
 };
-use mint_client::mint::SpendableNote;
+use mint_client::mint::{MintClientError, SpendableNote};
 use mint_client::query::EventuallyConsistent;
+use mint_client::smolfs::capability::ReadToken;
+use mint_client::smolfs::SmolFSClientError;
 use mint_client::utils::{
     from_hex, parse_bitcoin_amount, parse_ecash, parse_fedimint_amount, parse_node_pub_key,
     serialize_ecash,
 };
-use mint_client::{module_decode_stubs, Client, UserClientConfig};
+use mint_client::{module_decode_stubs, Client, ClientError, UserClientConfig};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use tracing::info;
@@ -48,9 +52,41 @@ use tracing_subscriber::EnvFilter;
 #[serde(rename_all(serialize = "snake_case"))]
 #[serde(untagged)]
 enum CliOutput {
-    SmolFS {
-        success: bool,
+    SmolfsPut {
+        key: String,
+        size: u64,
+        tx_id: TransactionId,
     },
+
+    SmolfsGet {
+        key: String,
+        size: u64,
+        file: PathBuf,
+    },
+
+    SmolfsList {
+        entries: BTreeMap<String, EntryMeta>,
+    },
+
+    SmolfsRm {
+        key: String,
+        tx_id: TransactionId,
+    },
+
+    SmolfsHistory {
+        key: String,
+        versions: BTreeMap<u64, VersionMeta>,
+    },
+
+    SmolfsShare {
+        key: String,
+        token: String,
+        expiry: u64,
+    },
+
+    /// Data was written to stdout already, nothing is printed
+    Stdout,
+
     VersionHash {
         hash: String,
     },
@@ -151,6 +187,7 @@ enum CliErrorKind {
     InsufficientBalance,
     SerializationError,
     GeneralFailure,
+    NotFound,
 }
 
 #[derive(Serialize)]
@@ -218,9 +255,10 @@ enum CommandNoWorkdir {
 }
 #[derive(Subcommand)]
 enum Command {
-    Smol {
-        pubkey: String,
-        backup: String,
+    /// Store, read and share files in the federation
+    Smolfs {
+        #[clap(subcommand)]
+        command: SmolfsCommand,
     },
     /// Print the latest git commit hash this bin. was build with
     VersionHash,
@@ -333,6 +371,60 @@ enum Command {
     WipeNotes,
 }
 
+#[derive(Subcommand)]
+enum SmolfsCommand {
+    /// Encrypt and store a file, or stdin if no file is given, under `key`
+    Put {
+        key: String,
+        file: Option<PathBuf>,
+        /// Store the file unencrypted so anyone can read it
+        #[clap(long)]
+        public: bool,
+    },
+
+    /// Read and decrypt the entry `key` into a file, or stdout if no file is given
+    Get {
+        key: String,
+        file: Option<PathBuf>,
+        /// Read a retained older version instead of the current one
+        #[clap(long)]
+        version: Option<u64>,
+        /// Read a public entry, which isn't encrypted
+        #[clap(long)]
+        public: bool,
+    },
+
+    /// List entries whose key starts with `prefix`
+    Ls {
+        #[clap(default_value = "")]
+        prefix: String,
+    },
+
+    /// Delete the entry `key`, its retained versions can still be restored until the
+    /// federation's tombstone retention passed
+    Rm { key: String },
+
+    /// Show the retained versions of the entry `key`
+    History { key: String },
+
+    /// Create a read token for the entry `key` that lets anyone holding it read the entry
+    Share {
+        key: String,
+        /// Number of seconds the token stays valid
+        #[clap(long = "expires-in", default_value = "86400")]
+        expires_in: u64,
+        /// Number of times each guardian serves the entry for the token
+        #[clap(long = "max-reads")]
+        max_reads: Option<u64>,
+    },
+
+    /// Read an entry shared with `share` into a file, or stdout if no file is given
+    Open {
+        token: ReadToken,
+        file: Option<PathBuf>,
+    },
+}
+
 trait ErrorHandler<T, E> {
     fn or_terminate(self, err: CliErrorKind, msg: &str) -> T;
     fn transform<F>(self, success: F, err: CliErrorKind, msg: &str) -> CliResult
@@ -434,6 +526,7 @@ async fn main() {
             (LEGACY_HARDCODED_INSTANCE_ID_LN, LightningDecoder.into()),
             (LEGACY_HARDCODED_INSTANCE_ID_MINT, MintDecoder.into()),
             (LEGACY_HARDCODED_INSTANCE_ID_WALLET, WalletDecoder.into()),
+            (LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, SmolFSDecoder.into()),
         ]);
 
         let module_gens = ModuleGenRegistry::from(vec![
@@ -448,6 +541,7 @@ async fn main() {
         let cli_result = handle_command(cli, client, rng).await;
 
         match cli_result {
+            Ok(CliOutput::Stdout) => {}
             Ok(output) => {
                 println!("{}", output);
             }
@@ -466,11 +560,7 @@ async fn handle_command(
 ) -> CliResult {
     let mut task_group = TaskGroup::new();
     match cli.command {
-        Command::Smol { pubkey, backup } => {
-            let _a = SmolFSInput(Box::new(SmolFSEntry { pubkey, backup }));
-
-            Ok(CliOutput::SmolFS { success: true })
-        }
+        Command::Smolfs { command } => handle_smolfs_command(command, client, rng).await,
         Command::Api { method, arg } => {
             let a = format!("{method} {arg}");
             println!("{a}");
@@ -762,3 +852,154 @@ async fn handle_command(
         },
     }
 }
+
+async fn handle_smolfs_command(
+    command: SmolfsCommand,
+    client: Client<UserClientConfig>,
+    mut rng: rand::rngs::OsRng,
+) -> CliResult {
+    let smolfs = client.smolfs_client();
+    match command {
+        SmolfsCommand::Put { key, file, public } => {
+            let bytes = read_input(file)?;
+            let result = if public {
+                client
+                    .smolfs_write_public(key.clone(), bytes.clone(), &mut rng)
+                    .await
+            } else {
+                client.smolfs_write(key.clone(), &bytes, &mut rng).await
+            };
+            let tx_id = result.map_err(|e| smolfs_error(e, "failed to store entry"))?;
+            Ok(CliOutput::SmolfsPut {
+                key,
+                size: bytes.len() as u64,
+                tx_id,
+            })
+        }
+        SmolfsCommand::Get {
+            key,
+            file,
+            version,
+            public,
+        } => {
+            let result = match (version, public) {
+                (Some(version), false) => smolfs.get_version(key.clone(), version).await,
+                (Some(version), true) => match smolfs.download_version(key.clone(), version).await {
+                    Ok(Some(download)) => download.read_to_end().await.map(Some),
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                },
+                (None, false) => smolfs.read_file(key.clone()).await,
+                (None, true) => match smolfs.download(key.clone()).await {
+                    Ok(Some(download)) => download.read_to_end().await.map(Some),
+                    Ok(None) => Ok(None),
+                    Err(e) => Err(e),
+                },
+            };
+            let bytes = result
+                .map_err(|e| smolfs_error(e.into(), "failed to read entry"))?
+                .ok_or_else(|| CliError::from(CliErrorKind::NotFound, "entry not found", None))?;
+            write_output(key, &bytes, file)
+        }
+        SmolfsCommand::Ls { prefix } => smolfs
+            .list(prefix)
+            .await
+            .map(|entries| CliOutput::SmolfsList { entries })
+            .map_err(|e| smolfs_error(e.into(), "failed to list entries")),
+        SmolfsCommand::Rm { key } => {
+            let tx_id = client
+                .smolfs_delete(key.clone(), &mut rng)
+                .await
+                .map_err(|e| smolfs_error(e, "failed to delete entry"))?;
+            Ok(CliOutput::SmolfsRm { key, tx_id })
+        }
+        SmolfsCommand::History { key } => smolfs
+            .versions(key.clone())
+            .await
+            .map(|versions| CliOutput::SmolfsHistory { key, versions })
+            .map_err(|e| smolfs_error(e.into(), "failed to fetch versions")),
+        SmolfsCommand::Share {
+            key,
+            expires_in,
+            max_reads,
+        } => {
+            let expiry = SystemTime::now() + Duration::from_secs(expires_in);
+            let token = smolfs.create_read_token(key.clone(), expiry, max_reads);
+            Ok(CliOutput::SmolfsShare {
+                key,
+                token: token.to_string(),
+                expiry: token.capability.capability.expiry,
+            })
+        }
+        SmolfsCommand::Open { token, file } => {
+            let key = token.capability.capability.key.clone();
+            let bytes = smolfs
+                .read_with_token(&token)
+                .await
+                .map_err(|e| smolfs_error(e.into(), "failed to read shared entry"))?
+                .ok_or_else(|| CliError::from(CliErrorKind::NotFound, "entry not found", None))?;
+            write_output(key, &bytes, file)
+        }
+    }
+}
+
+/// Reads all of `file`, or stdin if no file is given
+fn read_input(file: Option<PathBuf>) -> Result<Vec<u8>, CliError> {
+    let mut bytes = vec![];
+    let result = match file {
+        Some(path) => std::fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)),
+        None => std::io::stdin().read_to_end(&mut bytes),
+    };
+    result.map(|_| bytes).map_err(|e| {
+        CliError::from(
+            CliErrorKind::IOError,
+            "failed to read input",
+            Some(e.into()),
+        )
+    })
+}
+
+/// Writes `bytes` read from the entry `key` to `file`, or stdout if no file is given
+fn write_output(key: String, bytes: &[u8], file: Option<PathBuf>) -> CliResult {
+    let result = match &file {
+        Some(path) => std::fs::write(path, bytes),
+        None => std::io::stdout().write_all(bytes),
+    };
+    result.map_err(|e| {
+        CliError::from(
+            CliErrorKind::IOError,
+            "failed to write output",
+            Some(e.into()),
+        )
+    })?;
+    Ok(match file {
+        Some(file) => CliOutput::SmolfsGet {
+            key,
+            size: bytes.len() as u64,
+            file,
+        },
+        None => CliOutput::Stdout,
+    })
+}
+
+/// Maps errors of smolfs operations to the matching [`CliErrorKind`]
+fn smolfs_error(err: ClientError, message: &str) -> CliError {
+    let kind = match &err {
+        ClientError::MintClientError(MintClientError::InsufficientBalance(..)) => {
+            CliErrorKind::InsufficientBalance
+        }
+        ClientError::SmolFSClientError(SmolFSClientError::ApiError(_))
+        | ClientError::SmolFSClientError(SmolFSClientError::NotEnoughGuardians) => {
+            CliErrorKind::NetworkError
+        }
+        ClientError::SmolFSClientError(SmolFSClientError::UnknownVersion(_)) => {
+            CliErrorKind::NotFound
+        }
+        ClientError::SmolFSClientError(SmolFSClientError::Encryption(_))
+        | ClientError::SmolFSClientError(SmolFSClientError::EntryChanged) => {
+            CliErrorKind::SerializationError
+        }
+        _ => CliErrorKind::GeneralFederationError,
+    };
+    CliError::from(kind, message, Some(Box::new(err)))
+}
@@ -11,16 +11,23 @@ use bitcoin_hashes::sha256::Hash as Sha256Hash;
 use fedimint_api::config::{ClientConfig, ConfigResponse};
 use fedimint_api::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::task::sleep;
 use fedimint_api::task::{RwLock, RwLockWriteGuard};
-use fedimint_api::{dyn_newtype_define, NumPeers, OutPoint, PeerId, TransactionId};
+use fedimint_api::{dyn_newtype_define, Amount, NumPeers, OutPoint, PeerId, TransactionId};
 use fedimint_core::epoch::{SerdeEpochHistory, SignedEpochOutcome};
 use fedimint_core::modules::ln::contracts::incoming::IncomingContractOffer;
 use fedimint_core::modules::ln::contracts::ContractId;
 use fedimint_core::modules::ln::{ContractAccount, LightningGateway};
+use fedimint_core::modules::smolfs::common::{
+    BlobResponse, CapabilityReadRequest, ChallengeRequest, ChallengeResponse, ChangeEvent,
+    EntryMeta, FetchVersionRequest, VersionMeta, WatchRequest,
+};
+use fedimint_core::modules::smolfs::upload::{
+    ReadRequest, UploadFinalizeRequest, UploadPushRequest, UploadStartRequest,
+};
 use fedimint_core::modules::wallet::PegOutFees;
 use fedimint_core::outcome::legacy::TryIntoOutcome;
 use fedimint_core::outcome::{self, TransactionStatus};
@@ -37,6 +44,7 @@ use jsonrpsee_core::Error as JsonRpcError;
 use jsonrpsee_wasm_client::{Client as WsClient, WasmClientBuilder as WsClientBuilder};
 #[cfg(not(target_family = "wasm"))]
 use jsonrpsee_ws_client::{WsClient, WsClientBuilder};
+use secp256k1::schnorr;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
@@ -690,8 +698,64 @@ where
 #[cfg_attr(target_family = "wasm", async_trait(? Send))]
 #[cfg_attr(not(target_family = "wasm"), async_trait)]
 pub trait SmolFSFederationApi {
-    async fn fetch_backups_by_pubkey(&self, pubkey: String) -> FederationResult<Option<String>>;
+    async fn fetch_backups_by_pubkey(
+        &self,
+        pubkey: String,
+    ) -> FederationResult<Option<BlobResponse>>;
     async fn put_backups_by_pubkey(&self, params: Vec<String>) -> FederationResult<Option<String>>;
+    async fn fetch_entry_meta(&self, key: String) -> FederationResult<Option<EntryMeta>>;
+    /// Storage balance prepaid for `owner`
+    async fn fetch_prepaid_balance(
+        &self,
+        owner: secp256k1::XOnlyPublicKey,
+    ) -> FederationResult<Amount>;
+    /// Lists the entries whose key starts with `prefix`
+    async fn list_entries(&self, prefix: String) -> FederationResult<BTreeMap<String, EntryMeta>>;
+    /// Lists the retained versions of an entry by version number
+    async fn fetch_entry_versions(
+        &self,
+        key: String,
+    ) -> FederationResult<BTreeMap<u64, VersionMeta>>;
+    async fn fetch_entry_version(
+        &self,
+        request: FetchVersionRequest,
+    ) -> FederationResult<Option<BlobResponse>>;
+    /// Long-polls for the next change to the watched entries, see [`WatchRequest`]
+    async fn smolfs_watch(&self, request: WatchRequest) -> FederationResult<Option<ChangeEvent>>;
+    /// Reads a range of an entry, see [`ReadRequest`]
+    async fn read_entry_range(
+        &self,
+        request: ReadRequest,
+    ) -> FederationResult<Option<BlobResponse>>;
+    /// Reads a range of an entry using a read capability, see [`CapabilityReadRequest`]
+    async fn read_with_capability(
+        &self,
+        request: CapabilityReadRequest,
+    ) -> FederationResult<Option<BlobResponse>>;
+    /// Asks a single guardian to prove it holds an entry, see [`ChallengeRequest`]
+    async fn smolfs_challenge(
+        &self,
+        peer: PeerId,
+        request: &ChallengeRequest,
+    ) -> MemberResult<Option<ChallengeResponse>>;
+    /// Opens an upload session with a single guardian, returning the session id
+    async fn smolfs_upload_start(
+        &self,
+        peer: PeerId,
+        request: &UploadStartRequest,
+    ) -> MemberResult<u64>;
+    /// Pushes a range of bytes to an upload session, returning the bytes received so far
+    async fn smolfs_upload_push(
+        &self,
+        peer: PeerId,
+        request: &UploadPushRequest,
+    ) -> MemberResult<u64>;
+    /// Completes an upload session, returning the guardian's receipt for the payload
+    async fn smolfs_upload_finalize(
+        &self,
+        peer: PeerId,
+        request: &UploadFinalizeRequest,
+    ) -> MemberResult<schnorr::Signature>;
 }
 
 #[cfg_attr(target_family = "wasm", async_trait(? Send))]
@@ -700,20 +764,166 @@ impl<T: ?Sized> SmolFSFederationApi for T
 where
     T: IFederationApi + Send + Sync + 'static,
 {
-    async fn fetch_backups_by_pubkey(&self, pubkey: String) -> FederationResult<Option<String>> {
+    async fn fetch_backups_by_pubkey(
+        &self,
+        pubkey: String,
+    ) -> FederationResult<Option<BlobResponse>> {
         self.request_eventually_consistent(
-            "/module/3/smolfsget".to_string(),
+            format!("/module/{}/smolfsget", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
             erased_single_param(&pubkey),
         )
         .await
     }
     async fn put_backups_by_pubkey(&self, params: Vec<String>) -> FederationResult<Option<String>> {
         self.request_eventually_consistent(
-            "/module/3/smolfsput".to_string(),
+            format!("/module/{}/smolfsput", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
             erased_multi_param(&params),
         )
         .await
     }
+
+    async fn fetch_entry_meta(&self, key: String) -> FederationResult<Option<EntryMeta>> {
+        self.request_eventually_consistent(
+            format!("/module/{}/entry_meta", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+            erased_single_param(&key),
+        )
+        .await
+    }
+
+    async fn fetch_prepaid_balance(
+        &self,
+        owner: secp256k1::XOnlyPublicKey,
+    ) -> FederationResult<Amount> {
+        self.request_eventually_consistent(
+            format!(
+                "/module/{}/prepaid_balance",
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS
+            ),
+            erased_single_param(&owner),
+        )
+        .await
+    }
+
+    async fn list_entries(&self, prefix: String) -> FederationResult<BTreeMap<String, EntryMeta>> {
+        self.request_eventually_consistent(
+            format!("/module/{}/list", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+            erased_single_param(&prefix),
+        )
+        .await
+    }
+
+    async fn fetch_entry_versions(
+        &self,
+        key: String,
+    ) -> FederationResult<BTreeMap<u64, VersionMeta>> {
+        self.request_eventually_consistent(
+            format!("/module/{}/versions", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+            erased_single_param(&key),
+        )
+        .await
+    }
+
+    async fn fetch_entry_version(
+        &self,
+        request: FetchVersionRequest,
+    ) -> FederationResult<Option<BlobResponse>> {
+        self.request_eventually_consistent(
+            format!(
+                "/module/{}/fetch_version",
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS
+            ),
+            erased_single_param(&request),
+        )
+        .await
+    }
+
+    async fn smolfs_watch(&self, request: WatchRequest) -> FederationResult<Option<ChangeEvent>> {
+        self.request_eventually_consistent(
+            format!("/module/{}/watch", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+            erased_single_param(&request),
+        )
+        .await
+    }
+
+    async fn read_entry_range(
+        &self,
+        request: ReadRequest,
+    ) -> FederationResult<Option<BlobResponse>> {
+        self.request_eventually_consistent(
+            format!("/module/{}/read", LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+            erased_single_param(&request),
+        )
+        .await
+    }
+
+    async fn read_with_capability(
+        &self,
+        request: CapabilityReadRequest,
+    ) -> FederationResult<Option<BlobResponse>> {
+        self.request_eventually_consistent(
+            format!(
+                "/module/{}/read_capability",
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS
+            ),
+            erased_single_param(&request),
+        )
+        .await
+    }
+
+    async fn smolfs_challenge(
+        &self,
+        peer: PeerId,
+        request: &ChallengeRequest,
+    ) -> MemberResult<Option<ChallengeResponse>> {
+        request_member(self, peer, "challenge", erased_single_param(request)).await
+    }
+
+    async fn smolfs_upload_start(
+        &self,
+        peer: PeerId,
+        request: &UploadStartRequest,
+    ) -> MemberResult<u64> {
+        request_member(self, peer, "upload_start", erased_single_param(request)).await
+    }
+
+    async fn smolfs_upload_push(
+        &self,
+        peer: PeerId,
+        request: &UploadPushRequest,
+    ) -> MemberResult<u64> {
+        request_member(self, peer, "upload_push", erased_single_param(request)).await
+    }
+
+    async fn smolfs_upload_finalize(
+        &self,
+        peer: PeerId,
+        request: &UploadFinalizeRequest,
+    ) -> MemberResult<schnorr::Signature> {
+        request_member(self, peer, "upload_finalize", erased_single_param(request)).await
+    }
+}
+
+/// Calls a smolfs endpoint of a single guardian, used where every guardian has to be talked to
+/// individually instead of merging their responses
+async fn request_member<T, Ret>(
+    api: &T,
+    peer: PeerId,
+    endpoint: &str,
+    params: Vec<Value>,
+) -> MemberResult<Ret>
+where
+    T: IFederationApi + ?Sized,
+    Ret: serde::de::DeserializeOwned,
+{
+    let response = api
+        .request_raw(
+            peer,
+            &format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_SMOLFS}/{endpoint}"),
+            &params,
+        )
+        .await
+        .map_err(MemberError::Rpc)?;
+    serde_json::from_value(response).map_err(|e| MemberError::ResponseDeserialization(e.into()))
 }
 
 /// Mint API client that will try to run queries against all `members` expecting equal
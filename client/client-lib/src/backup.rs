@@ -0,0 +1,49 @@
+//! Backup of the state of all module clients
+//!
+//! Only part of the client state can be recovered from the federation alone, lightning contracts
+//! and peg-in tweaks are lost with the device. A [`ClientBackup`] bundles the mint, lightning and
+//! wallet client state into one document, which [`crate::Client::backup_all`] encrypts and stores
+//! in smolfs. The entry's key is derived from the client secret, so
+//! [`crate::Client::restore_all`] finds it again on a fresh device.
+//!
+//! Restoring merges the lightning and wallet state into the database: entries we already have are
+//! kept as they are and only the missing ones are added, so restoring an older backup never loses
+//! payments or peg-ins made since. Ecash is the exception, the mint client recovers the notes at
+//! the current epoch from the federation and replaces the ones it had.
+
+use fedimint_api::db::{DatabaseKey, DatabaseKeyPrefixConst, DatabaseTransaction};
+use fedimint_api::encoding::{Decodable, Encodable};
+use secp256k1::XOnlyPublicKey;
+
+use crate::ln::LnClientBackup;
+use crate::mint::backup::PlaintextEcashBackup;
+use crate::wallet::WalletClientBackup;
+
+/// Version of the [`ClientBackup`] format written by this client
+pub const CLIENT_BACKUP_VERSION: u32 = 0;
+
+/// State of all module clients needed to rebuild their databases
+#[derive(Debug, Encodable, Decodable)]
+pub struct ClientBackup {
+    /// Format version, encoded first so it can be checked before decoding the rest
+    pub version: u32,
+    /// Snapshot ecash recovery starts from
+    pub mint: PlaintextEcashBackup,
+    pub ln: LnClientBackup,
+    pub wallet: WalletClientBackup,
+}
+
+/// Smolfs entry the backup of the client with the smolfs owner key `owner` is stored under
+pub fn backup_key(owner: &XOnlyPublicKey) -> String {
+    format!("backup/{owner}")
+}
+
+/// Inserts `value` under `key` unless the database already has an entry there
+pub(crate) async fn insert_missing<K>(dbtx: &mut DatabaseTransaction<'_>, key: &K, value: &K::Value)
+where
+    K: DatabaseKey + DatabaseKeyPrefixConst,
+{
+    if dbtx.get_value(key).await.expect("DB error").is_none() {
+        dbtx.insert_new_entry(key, value).await.expect("DB error");
+    }
+}
@@ -0,0 +1,186 @@
+//! Simple key/value store abstraction for applications built on the client
+//!
+//! [`KvStore`] is implemented by [`Client`] on top of smolfs and by [`fake::MemKvStore`], which
+//! keeps everything in memory for unit tests.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use fedimint_api::config::ClientConfig;
+use fedimint_api::TransactionId;
+use fedimint_core::modules::smolfs::SmolFSPayload;
+use fedimint_core::outcome::TransactionStatus;
+use rand::rngs::OsRng;
+use thiserror::Error;
+
+use crate::api::{FederationError, GlobalFederationApi, SmolFSFederationApi};
+use crate::smolfs::SmolFSClientError;
+use crate::{Client, ClientError};
+
+pub mod fake;
+
+/// Value stored under a key together with the version it was read at
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct KvEntry {
+    pub value: Vec<u8>,
+    /// Counts up with every write of the key and continues after the last written version once
+    /// a deleted key is written again, pass it to [`KvStore::put`] or [`KvStore::delete`] to only
+    /// overwrite what was read
+    pub version: u64,
+}
+
+/// Async key/value store
+///
+/// Writes can be made conditional on the version the key is currently at to detect concurrent
+/// writers, passing `None` as the expected version overwrites whatever is stored.
+#[cfg_attr(target_family = "wasm", async_trait(? Send))]
+#[cfg_attr(not(target_family = "wasm"), async_trait)]
+pub trait KvStore {
+    /// Reads the value currently stored under `key`
+    async fn get(&self, key: &str) -> Result<Option<KvEntry>>;
+
+    /// Stores `value` under `key`, failing with [`KvError::Conflict`] unless the key is at
+    /// `expected_version`
+    async fn put(&self, key: &str, value: Vec<u8>, expected_version: Option<u64>) -> Result<()>;
+
+    /// Removes `key`, failing with [`KvError::Conflict`] unless it's at `expected_version`
+    ///
+    /// Deleting a key that doesn't exist without an expected version does nothing.
+    async fn delete(&self, key: &str, expected_version: Option<u64>) -> Result<()>;
+
+    /// Lists the keys starting with `prefix` together with their current version
+    async fn list(&self, prefix: &str) -> Result<BTreeMap<String, u64>>;
+}
+
+/// Stores values in smolfs entries
+///
+/// Values are encrypted with keys derived from the client secret and writes are signed with the
+/// client's owner key, so other users can neither read nor overwrite them. Versions are the
+/// versions of the entries and expected versions are checked by the federation when applying the
+/// write, so of two concurrent writers expecting the same version only one succeeds.
+///
+/// Writes only return once the federation accepted them. [`KvStore::list`] returns entries of
+/// all users, apps should keep their keys under a prefix nobody else writes to.
+#[cfg_attr(target_family = "wasm", async_trait(? Send))]
+#[cfg_attr(not(target_family = "wasm"), async_trait)]
+impl<T> KvStore for Client<T>
+where
+    T: AsRef<ClientConfig> + Clone + Send + Sync,
+{
+    async fn get(&self, key: &str) -> Result<Option<KvEntry>> {
+        let smolfs = self.smolfs_client();
+        let Some(download) = smolfs.download(key.to_owned()).await? else {
+            return Ok(None);
+        };
+        // The download fails if the entry changes while reading it, so value and version match
+        let version = download.version();
+        let payload = download.read_to_end().await?;
+        let value = smolfs.decrypt(key, payload)?;
+        Ok(Some(KvEntry { value, version }))
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, expected_version: Option<u64>) -> Result<()> {
+        let encrypted = self.smolfs_client().encrypt_chunked(key, &value)?;
+        let result = self
+            .smolfs_store(key.to_owned(), encrypted, false, expected_version, OsRng)
+            .await;
+        self.await_kv_write(key, expected_version, result).await
+    }
+
+    async fn delete(&self, key: &str, expected_version: Option<u64>) -> Result<()> {
+        // The federation rejects deleting entries that don't exist
+        if expected_version.is_none() && self.entry_version(key).await?.is_none() {
+            return Ok(());
+        }
+        let result = self
+            .smolfs_put(
+                key.to_owned(),
+                SmolFSPayload::Delete,
+                false,
+                expected_version,
+                OsRng,
+            )
+            .await;
+        self.await_kv_write(key, expected_version, result).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<BTreeMap<String, u64>> {
+        Ok(self
+            .smolfs_client()
+            .list(prefix.to_owned())
+            .await?
+            .into_iter()
+            .map(|(key, meta)| (key, meta.version))
+            .collect())
+    }
+}
+
+impl<T: AsRef<ClientConfig> + Clone> Client<T> {
+    /// Waits for the outcome of a submitted write
+    ///
+    /// Writes rejected while the entry isn't at `expected_version` anymore, either on submission
+    /// or in consensus, are reported as [`KvError::Conflict`].
+    async fn await_kv_write(
+        &self,
+        key: &str,
+        expected_version: Option<u64>,
+        submitted: std::result::Result<TransactionId, ClientError>,
+    ) -> Result<()> {
+        let error = match submitted {
+            Ok(txid) => match self.context.api.fetch_tx_outcome(&txid).await? {
+                TransactionStatus::Accepted { .. } => return Ok(()),
+                TransactionStatus::Rejected(reason) => KvError::Rejected(reason),
+            },
+            Err(e) => KvError::Client(e),
+        };
+        let Some(expected) = expected_version else {
+            return Err(error);
+        };
+        let actual = self.entry_version(key).await?;
+        if actual != Some(expected) {
+            return Err(KvError::Conflict {
+                key: key.to_owned(),
+                expected: Some(expected),
+                actual,
+            });
+        }
+        Err(error)
+    }
+
+    async fn entry_version(&self, key: &str) -> Result<Option<u64>> {
+        Ok(self
+            .context
+            .api
+            .fetch_entry_meta(key.to_owned())
+            .await?
+            .map(|meta| meta.version))
+    }
+}
+
+type Result<T> = std::result::Result<T, KvError>;
+
+#[derive(Error, Debug)]
+pub enum KvError {
+    #[error("Entry {key} is at version {actual:?}, expected {expected:?}")]
+    Conflict {
+        key: String,
+        expected: Option<u64>,
+        actual: Option<u64>,
+    },
+    #[error("Write was rejected by the federation: {0}")]
+    Rejected(String),
+    #[error("Client error: {0}")]
+    Client(#[from] ClientError),
+}
+
+impl From<SmolFSClientError> for KvError {
+    fn from(e: SmolFSClientError) -> Self {
+        KvError::Client(e.into())
+    }
+}
+
+impl From<FederationError> for KvError {
+    fn from(e: FederationError) -> Self {
+        KvError::Client(e.into())
+    }
+}
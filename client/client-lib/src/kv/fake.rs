@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use fedimint_api::db::mem_impl::MemDatabase;
+use fedimint_api::db::{Database, DatabaseKeyPrefixConst};
+use fedimint_api::encoding::{Decodable, Encodable};
+
+use super::{KvEntry, KvError, KvStore, Result};
+use crate::module_decode_stubs;
+
+/// The store has a database of its own, so this can't collide with the client's prefixes
+const KV_ENTRY_PREFIX: u8 = 0x2d;
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+struct KvEntryKey(String);
+
+impl DatabaseKeyPrefixConst for KvEntryKey {
+    const DB_PREFIX: u8 = KV_ENTRY_PREFIX;
+    type Key = Self;
+    type Value = StoredKvEntry;
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+struct KvEntryKeyPrefix;
+
+impl DatabaseKeyPrefixConst for KvEntryKeyPrefix {
+    const DB_PREFIX: u8 = KV_ENTRY_PREFIX;
+    type Key = KvEntryKey;
+    type Value = StoredKvEntry;
+}
+
+/// Like smolfs, deleted keys are remembered so writing them again continues after their last
+/// version
+#[derive(Debug, Clone, Encodable, Decodable)]
+struct StoredKvEntry {
+    /// `None` once the key was deleted
+    value: Option<Vec<u8>>,
+    version: u64,
+}
+
+impl StoredKvEntry {
+    fn live_version(&self) -> Option<u64> {
+        self.value.as_ref().map(|_| self.version)
+    }
+}
+
+/// A fake [`KvStore`] keeping everything in a [`MemDatabase`]
+///
+/// Versions behave like the smolfs ones, starting at 0 and counting up with every write while
+/// deletes keep the version. [`MemKvStore::inject_conflicts`] lets tests simulate other clients
+/// racing them.
+#[derive(Debug)]
+pub struct MemKvStore {
+    db: Database,
+    /// Number of upcoming writes per key that lose a race against another client
+    injected_conflicts: Mutex<BTreeMap<String, u32>>,
+}
+
+impl Default for MemKvStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemKvStore {
+    pub fn new() -> Self {
+        Self {
+            db: Database::new(MemDatabase::new(), module_decode_stubs()),
+            injected_conflicts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Makes the next `count` writes or deletes of `key` fail with [`KvError::Conflict`]
+    ///
+    /// Each of them is preceded by another client writing the same value again, so the key's
+    /// version is bumped and a retry with the new version succeeds. Keys that don't exist are
+    /// deleted again by the other client, which leaves them as they are.
+    pub fn inject_conflicts(&self, key: &str, count: u32) {
+        *self
+            .injected_conflicts
+            .lock()
+            .expect("Lock poisoned")
+            .entry(key.to_owned())
+            .or_default() += count;
+    }
+
+    /// Number of injected conflicts for `key` that writes haven't run into yet
+    pub fn pending_conflicts(&self, key: &str) -> u32 {
+        self.injected_conflicts
+            .lock()
+            .expect("Lock poisoned")
+            .get(key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn take_injected_conflict(&self, key: &str) -> bool {
+        let mut conflicts = self.injected_conflicts.lock().expect("Lock poisoned");
+        match conflicts.get_mut(key) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    async fn write(
+        &self,
+        key: &str,
+        value: Option<Vec<u8>>,
+        expected_version: Option<u64>,
+    ) -> Result<()> {
+        let db_key = KvEntryKey(key.to_owned());
+        let mut dbtx = self.db.begin_transaction().await;
+        let stored = dbtx.get_value(&db_key).await.expect("DB error");
+        let next_version = stored.as_ref().map_or(0, |entry| entry.version + 1);
+
+        let actual = stored.as_ref().and_then(StoredKvEntry::live_version);
+        if self.take_injected_conflict(key) {
+            if let Some(value) = stored.and_then(|entry| entry.value) {
+                let concurrent = StoredKvEntry {
+                    value: Some(value),
+                    version: next_version,
+                };
+                dbtx.insert_entry(&db_key, &concurrent)
+                    .await
+                    .expect("DB error");
+                dbtx.commit_tx().await.expect("DB error");
+            }
+            return Err(KvError::Conflict {
+                key: key.to_owned(),
+                expected: expected_version,
+                actual: actual.map(|_| next_version),
+            });
+        }
+
+        if let Some(expected) = expected_version {
+            if actual != Some(expected) {
+                return Err(KvError::Conflict {
+                    key: key.to_owned(),
+                    expected: Some(expected),
+                    actual,
+                });
+            }
+        }
+        if value.is_none() && actual.is_none() {
+            return Ok(());
+        }
+
+        let entry = match value {
+            Some(value) => StoredKvEntry {
+                value: Some(value),
+                version: next_version,
+            },
+            None => StoredKvEntry {
+                value: None,
+                version: actual.expect("Checked above"),
+            },
+        };
+        dbtx.insert_entry(&db_key, &entry).await.expect("DB error");
+        dbtx.commit_tx().await.expect("DB error");
+        Ok(())
+    }
+}
+
+#[cfg_attr(target_family = "wasm", async_trait(? Send))]
+#[cfg_attr(not(target_family = "wasm"), async_trait)]
+impl KvStore for MemKvStore {
+    async fn get(&self, key: &str) -> Result<Option<KvEntry>> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let stored = dbtx
+            .get_value(&KvEntryKey(key.to_owned()))
+            .await
+            .expect("DB error");
+        Ok(stored.and_then(|entry| {
+            Some(KvEntry {
+                value: entry.value?,
+                version: entry.version,
+            })
+        }))
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, expected_version: Option<u64>) -> Result<()> {
+        self.write(key, Some(value), expected_version).await
+    }
+
+    async fn delete(&self, key: &str, expected_version: Option<u64>) -> Result<()> {
+        self.write(key, None, expected_version).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<BTreeMap<String, u64>> {
+        let mut dbtx = self.db.begin_transaction().await;
+        Ok(dbtx
+            .find_by_prefix(&KvEntryKeyPrefix)
+            .await
+            .filter_map(|res| {
+                let (key, entry) = res.expect("DB error");
+                Some((key.0, entry.live_version()?))
+            })
+            .filter(|(key, _)| key.starts_with(prefix))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::MemKvStore;
+    use crate::kv::{KvEntry, KvError, KvStore};
+
+    #[test_log::test(tokio::test)]
+    async fn versions_continue_after_deletes() {
+        let store = MemKvStore::new();
+        assert_eq!(store.get("app/a").await.unwrap(), None);
+
+        store.put("app/a", b"one".to_vec(), None).await.unwrap();
+        store.put("app/a", b"two".to_vec(), Some(0)).await.unwrap();
+        store.put("app/b", b"three".to_vec(), None).await.unwrap();
+        store.put("other", b"four".to_vec(), None).await.unwrap();
+        assert_eq!(
+            store.get("app/a").await.unwrap(),
+            Some(KvEntry {
+                value: b"two".to_vec(),
+                version: 1
+            })
+        );
+        assert_eq!(
+            store.list("app/").await.unwrap(),
+            BTreeMap::from([("app/a".to_owned(), 1), ("app/b".to_owned(), 0)])
+        );
+
+        store.delete("app/a", Some(1)).await.unwrap();
+        store.delete("app/a", None).await.unwrap();
+        assert_eq!(store.get("app/a").await.unwrap(), None);
+        assert_eq!(
+            store.list("app/").await.unwrap(),
+            BTreeMap::from([("app/b".to_owned(), 0)])
+        );
+
+        // Like smolfs, deleting doesn't count as a version of its own
+        store.put("app/a", b"five".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("app/a").await.unwrap().unwrap().version, 2);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn stale_and_injected_conflicts() {
+        let store = MemKvStore::new();
+        store.put("key", b"one".to_vec(), None).await.unwrap();
+
+        assert!(matches!(
+            store.put("key", b"two".to_vec(), Some(1)).await,
+            Err(KvError::Conflict {
+                expected: Some(1),
+                actual: Some(0),
+                ..
+            })
+        ));
+        assert!(matches!(
+            store.delete("missing", Some(0)).await,
+            Err(KvError::Conflict { actual: None, .. })
+        ));
+
+        // Another client rewrites the value before each of our next two writes
+        store.inject_conflicts("key", 2);
+        assert!(matches!(
+            store.put("key", b"two".to_vec(), Some(0)).await,
+            Err(KvError::Conflict {
+                actual: Some(1),
+                ..
+            })
+        ));
+        assert!(matches!(
+            store.put("key", b"two".to_vec(), None).await,
+            Err(KvError::Conflict {
+                actual: Some(2),
+                ..
+            })
+        ));
+        assert_eq!(store.pending_conflicts("key"), 0);
+        assert_eq!(
+            store.get("key").await.unwrap(),
+            Some(KvEntry {
+                value: b"one".to_vec(),
+                version: 2
+            })
+        );
+
+        store.put("key", b"two".to_vec(), Some(2)).await.unwrap();
+        assert_eq!(store.get("key").await.unwrap().unwrap().version, 3);
+    }
+}
@@ -1,5 +1,7 @@
 pub mod api;
+pub mod backup;
 pub mod db;
+pub mod kv;
 pub mod ln;
 pub mod mint;
 pub mod query;
@@ -10,11 +12,9 @@ pub mod wallet;
 
 use std::fmt::{Debug, Formatter};
 use std::sync::Arc;
-use std::time::Duration;
-#[cfg(not(target_family = "wasm"))]
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use anyhow::ensure;
+use anyhow::{ensure, format_err};
 use api::{
     DynFederationApi, FederationError, GlobalFederationApi, LnFederationApi, OutputOutcomeError,
     WalletFederationApi,
@@ -22,6 +22,7 @@ use api::{
 use bitcoin::util::key::KeyPair;
 use bitcoin::{secp256k1, Address, Transaction as BitcoinTransaction};
 use bitcoin_hashes::{sha256, Hash};
+use fedimint_api::cancellable::Cancellable;
 use fedimint_api::config::{ClientConfig, ModuleGenRegistry};
 use fedimint_api::core::{
     DynDecoder, LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
@@ -30,7 +31,7 @@ use fedimint_api::core::{
 use fedimint_api::db::Database;
 use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::module::registry::ModuleDecoderRegistry;
-use fedimint_api::task::{self, sleep};
+use fedimint_api::task::{self, sleep, TaskGroup};
 use fedimint_api::tiered::InvalidAmountTierError;
 use fedimint_api::{Amount, OutPoint, TransactionId};
 use fedimint_api::{ServerModule, TieredMulti};
@@ -41,6 +42,9 @@ use fedimint_core::modules::mint::common::MintDecoder;
 use fedimint_core::modules::mint::config::MintClientConfig;
 use fedimint_core::modules::mint::{MintOutput, MintOutputOutcome};
 use fedimint_core::modules::smolfs::config::SmolFSClientConfig;
+use fedimint_core::modules::smolfs::{
+    SmolFSEntry, SmolFSInput, SmolFSOutput, SmolFSPayload, MAX_INLINE_PAYLOAD_SIZE,
+};
 use fedimint_core::modules::wallet::common::WalletDecoder;
 use fedimint_core::modules::wallet::config::WalletClientConfig;
 use fedimint_core::modules::wallet::{PegOut, WalletInput, WalletOutput};
@@ -80,6 +84,7 @@ use threshold_crypto::PublicKey;
 use tracing::debug;
 use url::Url;
 
+use crate::backup::{backup_key, ClientBackup, CLIENT_BACKUP_VERSION};
 use crate::db::ClientSecretKey;
 use crate::ln::db::{
     OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey,
@@ -89,6 +94,7 @@ use crate::ln::outgoing::OutgoingContractAccount;
 use crate::ln::LnClientError;
 use crate::mint::db::{CoinKey, PendingCoinsKeyPrefix};
 use crate::mint::MintClientError;
+use crate::smolfs::SmolFSClientError;
 use crate::transaction::TransactionBuilder;
 use crate::utils::{network_to_currency, ClientContext};
 use crate::wallet::WalletClientError;
@@ -104,6 +110,8 @@ use crate::{
 const OUTGOING_LN_CONTRACT_TIMELOCK: u64 = 500;
 /// Mint module's secret key derivation child id
 pub const MINT_SECRET_CHILD_ID: ChildId = ChildId(0);
+/// SmolFS module's secret key derivation child id
+pub const SMOLFS_SECRET_CHILD_ID: ChildId = ChildId(1);
 
 type Result<T> = std::result::Result<T, ClientError>;
 pub type GatewayClient = Client<GatewayClientConfig>;
@@ -199,6 +207,10 @@ impl<T> Client<T> {
     pub fn mint_secret_static(root_secret: &DerivableSecret) -> DerivableSecret {
         root_secret.child_key(MINT_SECRET_CHILD_ID)
     }
+
+    pub fn smolfs_secret_static(root_secret: &DerivableSecret) -> DerivableSecret {
+        root_secret.child_key(SMOLFS_SECRET_CHILD_ID)
+    }
 }
 
 // TODO: `get_module` is parsing `serde_json::Value` every time, which is not best for performance
@@ -252,10 +264,11 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
                 .config
                 .as_ref()
                 .get_first_module_by_kind::<SmolFSClientConfig>("smolfs")
-                .expect("needs wallet module client config")
+                .expect("needs smolfs module client config")
                 .1,
 
             context: self.context.clone(),
+            secret: Self::smolfs_secret_static(&self.root_secret),
         }
     }
 
@@ -357,6 +370,216 @@ impl<T: AsRef<ClientConfig> + Clone> Client<T> {
         self.submit_tx_with_change(tx, &mut rng).await
     }
 
+    /// Writes `payload` to the smolfs entry `key`, making it readable by anyone if `public` is set
+    ///
+    /// Large payloads have to be uploaded with [`SmolFSClient::upload`] first, which returns the
+    /// payload to commit here. If `expected_version` is set the federation rejects the write
+    /// unless the entry is still at that version.
+    pub async fn smolfs_put<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        payload: SmolFSPayload,
+        public: bool,
+        expected_version: Option<u64>,
+        mut rng: R,
+    ) -> Result<TransactionId> {
+        let smolfs = self.smolfs_client();
+        let owner_key = smolfs.owner_key();
+        let owner = owner_key.x_only_public_key().0;
+
+        // Draw as much of the fee as possible from our prepaid balance, e-cash covers the rest
+        let fee = smolfs.write_fee(&payload);
+        let prepaid = if fee == Amount::ZERO {
+            Amount::ZERO
+        } else {
+            std::cmp::min(fee, smolfs.prepaid_balance(owner).await?)
+        };
+
+        let entry = SmolFSEntry {
+            pubkey: key,
+            payload,
+            timestamp: utils::now(),
+            pow_nonce: 0,
+            // Owning the entry keeps others from overwriting it and lets us grant read
+            // capabilities for it
+            owner: Some(owner),
+            prepaid,
+            public,
+            expected_version,
+        };
+        let entry = smolfs.solve_pow(entry).await;
+
+        let mut tx = TransactionBuilder::default();
+        tx.input(
+            &mut vec![owner_key],
+            Input::SmolFS(SmolFSInput(Box::new(entry))),
+        );
+        if prepaid < fee {
+            let (mut keys, input) = self.mint_client().select_input(fee - prepaid).await?;
+            tx.input(&mut keys, input);
+        }
+        self.submit_tx_with_change(tx, &mut rng).await
+    }
+
+    /// Prepays `amount` of smolfs storage for `owner`, whose writes draw their fees from it
+    ///
+    /// The owner doesn't have to be us, see [`SmolFSClient::owner_key`].
+    pub async fn smolfs_prepay<R: RngCore + CryptoRng>(
+        &self,
+        owner: secp256k1::XOnlyPublicKey,
+        amount: Amount,
+        mut rng: R,
+    ) -> Result<OutPoint> {
+        let mut tx = TransactionBuilder::default();
+
+        let (mut keys, input) = self.mint_client().select_input(amount).await?;
+        tx.input(&mut keys, input);
+        let out_idx = tx.output(Output::SmolFS(SmolFSOutput { owner, amount }));
+
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        Ok(OutPoint { txid, out_idx })
+    }
+
+    /// Encrypts `plaintext` and writes it to the smolfs entry `key`
+    ///
+    /// Payloads that are too large to be included in the transaction are uploaded to the
+    /// guardians first. Rewrites that only change a few chunks of the plaintext are written as a
+    /// delta against the current payload.
+    pub async fn smolfs_write<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        plaintext: &[u8],
+        rng: R,
+    ) -> Result<TransactionId> {
+        let encrypted = self.smolfs_client().encrypt_chunked(&key, plaintext)?;
+        self.smolfs_store(key, encrypted, false, None, rng).await
+    }
+
+    /// Writes `bytes` unencrypted to the smolfs entry `key`, which anyone can then read
+    ///
+    /// Guardians serve public entries over plain HTTP together with a proof signed by the
+    /// federation, see [`fedimint_core::modules::smolfs::public`].
+    pub async fn smolfs_write_public<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        bytes: Vec<u8>,
+        rng: R,
+    ) -> Result<TransactionId> {
+        self.smolfs_store(key, bytes, true, None, rng).await
+    }
+
+    /// Writes `bytes` as a delta against the current payload if that's much smaller, otherwise
+    /// includes them in the transaction if small enough or uploads them first
+    ///
+    /// Retrievability challenges for auditing the entry later are computed on the way.
+    async fn smolfs_store<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        bytes: Vec<u8>,
+        public: bool,
+        expected_version: Option<u64>,
+        rng: R,
+    ) -> Result<TransactionId> {
+        let smolfs = self.smolfs_client();
+        smolfs.precompute_challenges(&key, &bytes).await;
+
+        let payload = if let Some(delta) = smolfs.delta_against_current(key.clone(), &bytes).await?
+        {
+            delta
+        } else if bytes.len() as u64 <= MAX_INLINE_PAYLOAD_SIZE {
+            SmolFSPayload::Inline(bytes)
+        } else {
+            let mut upload = smolfs.upload(bytes.len() as u64).await?;
+            upload.write_all(&bytes).await?;
+            upload.finish().await?
+        };
+
+        self.smolfs_put(key, payload, public, expected_version, rng)
+            .await
+    }
+
+    /// Deletes the smolfs entry `key`
+    ///
+    /// The federation keeps the entry's retained versions, so it can still be brought back with
+    /// [`Client::smolfs_restore_version`].
+    pub async fn smolfs_delete<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        rng: R,
+    ) -> Result<TransactionId> {
+        self.smolfs_put(key, SmolFSPayload::Delete, false, None, rng)
+            .await
+    }
+
+    /// Makes a retained older version of the smolfs entry `key` its current version again
+    pub async fn smolfs_restore_version<R: RngCore + CryptoRng>(
+        &self,
+        key: String,
+        version: u64,
+        public: bool,
+        rng: R,
+    ) -> Result<TransactionId> {
+        let payload = self
+            .smolfs_client()
+            .restore_version(key.clone(), version)
+            .await?;
+        self.smolfs_put(key, payload, public, None, rng).await
+    }
+
+    /// Backs up the state of all module clients to smolfs, see [`backup`]
+    pub async fn backup_all<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+    ) -> anyhow::Result<TransactionId> {
+        let backup = ClientBackup {
+            version: CLIENT_BACKUP_VERSION,
+            mint: self.mint_client().prepare_plaintext_ecash_backup().await?,
+            ln: self.ln_client().prepare_backup().await,
+            wallet: self.wallet_client().prepare_backup().await,
+        };
+        let key = backup_key(&self.smolfs_client().owner_key().x_only_public_key().0);
+        Ok(self
+            .smolfs_write(key, &backup.consensus_encode_to_vec()?, rng)
+            .await?)
+    }
+
+    /// Rebuilds the database of every module client from the backup made by
+    /// [`Client::backup_all`]
+    ///
+    /// Lightning and wallet state is merged into what the database already has, see [`backup`].
+    /// Ecash received or spent since the backup is recovered by scanning the epochs after it,
+    /// see [`MintClient::restore_ecash_from_backup`].
+    pub async fn restore_all(
+        &self,
+        gap_limit: usize,
+        task_group: &mut TaskGroup,
+    ) -> anyhow::Result<Cancellable<()>> {
+        let smolfs = self.smolfs_client();
+        let key = backup_key(&smolfs.owner_key().x_only_public_key().0);
+        let bytes = smolfs
+            .read_file(key)
+            .await?
+            .ok_or_else(|| format_err!("No backup found"))?;
+        let decoders = ModuleDecoderRegistry::default();
+        let version = u32::consensus_decode(&mut &bytes[..], &decoders)?;
+        ensure!(
+            version == CLIENT_BACKUP_VERSION,
+            "Unsupported backup version {version}"
+        );
+        let backup = ClientBackup::consensus_decode(&mut &bytes[..], &decoders)?;
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        self.ln_client().restore_backup(&mut dbtx, backup.ln).await;
+        self.wallet_client()
+            .restore_backup(&mut dbtx, backup.wallet)
+            .await;
+        dbtx.commit_tx().await?;
+
+        self.mint_client()
+            .restore_ecash_from_backup(backup.mint, gap_limit, task_group)
+            .await
+    }
+
     async fn submit_tx_with_change<R: RngCore + CryptoRng>(
         &self,
         tx: TransactionBuilder,
@@ -870,14 +1093,7 @@ impl Client<UserClientConfig> {
             htlc_maximum_msat: None,
         }]);
 
-        #[cfg(not(target_family = "wasm"))]
-        let duration_since_epoch = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap();
-
-        #[cfg(target_family = "wasm")]
-        let duration_since_epoch =
-            Duration::from_secs_f64(js_sys::Date::new_0().get_time() / 1000.);
+        let duration_since_epoch = utils::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
 
         let invoice = InvoiceBuilder::new(network_to_currency(
             self.config
@@ -1375,6 +1591,8 @@ pub enum ClientError {
     MintClientError(#[from] MintClientError),
     #[error("Lightning client error: {0}")]
     LnClientError(#[from] LnClientError),
+    #[error("SmolFS client error: {0}")]
+    SmolFSClientError(#[from] SmolFSClientError),
     #[error("Peg-in amount must be greater than peg-in fee")]
     PegInAmountTooSmall,
     #[error("Peg-out waiting for UTXOs")]
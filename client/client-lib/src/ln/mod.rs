@@ -10,6 +10,7 @@ use bitcoin_hashes::sha256::Hash as Sha256Hash;
 use fedimint_api::config::FederationId;
 use fedimint_api::core::client::ClientModule;
 use fedimint_api::db::DatabaseTransaction;
+use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::module::TransactionItemAmount;
 use fedimint_api::task::timeout;
 use fedimint_api::{Amount, ServerModule};
@@ -28,9 +29,14 @@ use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use self::db::ConfirmedInvoiceKey;
+use self::db::{
+    ConfirmedInvoiceKey, ConfirmedInvoiceKeyPrefix, LightningGatewayKey,
+    OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey,
+    OutgoingPaymentClaimKeyPrefix,
+};
 use self::incoming::ConfirmedInvoice;
 use crate::api::{FederationError, LnFederationApi, WalletFederationApi};
+use crate::backup::insert_missing;
 use crate::ln::db::{OutgoingPaymentKey, OutgoingPaymentKeyPrefix};
 use crate::ln::incoming::IncomingContractAccount;
 use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
@@ -295,6 +301,77 @@ impl LnClient {
     }
 }
 
+impl LnClient {
+    /// Collects the contracts and invoices we need to finish or refund payments on another device
+    pub async fn prepare_backup(&self) -> LnClientBackup {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let outgoing_payments = dbtx
+            .find_by_prefix(&OutgoingPaymentKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .map(|(key, data)| (key.0, data))
+            .collect();
+        let outgoing_payment_claims = dbtx
+            .find_by_prefix(&OutgoingPaymentClaimKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").0 .0)
+            .collect();
+        let outgoing_contract_accounts = dbtx
+            .find_by_prefix(&OutgoingContractAccountKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error"))
+            .map(|(key, account)| (key.0, account))
+            .collect();
+        let confirmed_invoices = dbtx
+            .find_by_prefix(&ConfirmedInvoiceKeyPrefix)
+            .await
+            .map(|res| res.expect("DB error").1)
+            .collect();
+        let gateway = dbtx
+            .get_value(&LightningGatewayKey)
+            .await
+            .expect("DB error");
+
+        LnClientBackup {
+            outgoing_payments,
+            outgoing_payment_claims,
+            outgoing_contract_accounts,
+            confirmed_invoices,
+            gateway,
+        }
+    }
+
+    /// Adds the state from `backup` that is missing from the database, see [`crate::backup`]
+    pub async fn restore_backup(&self, dbtx: &mut DatabaseTransaction<'_>, backup: LnClientBackup) {
+        for (contract_id, data) in backup.outgoing_payments {
+            insert_missing(dbtx, &OutgoingPaymentKey(contract_id), &data).await;
+        }
+        for contract_id in backup.outgoing_payment_claims {
+            insert_missing(dbtx, &OutgoingPaymentClaimKey(contract_id), &()).await;
+        }
+        for (contract_id, account) in backup.outgoing_contract_accounts {
+            insert_missing(dbtx, &OutgoingContractAccountKey(contract_id), &account).await;
+        }
+        for invoice in backup.confirmed_invoices {
+            insert_missing(dbtx, &ConfirmedInvoiceKey(invoice.contract_id()), &invoice).await;
+        }
+        if let Some(gateway) = backup.gateway {
+            insert_missing(dbtx, &LightningGatewayKey, &gateway).await;
+        }
+    }
+}
+
+/// Lightning part of a [`crate::backup::ClientBackup`]
+#[derive(Debug, Encodable, Decodable)]
+pub struct LnClientBackup {
+    pub outgoing_payments: Vec<(ContractId, OutgoingContractData)>,
+    pub outgoing_payment_claims: Vec<ContractId>,
+    pub outgoing_contract_accounts: Vec<(ContractId, OutgoingContractAccount)>,
+    pub confirmed_invoices: Vec<ConfirmedInvoice>,
+    /// Gateway we had selected for outgoing payments
+    pub gateway: Option<LightningGateway>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PayInvoicePayload {
     pub federation_id: FederationId,
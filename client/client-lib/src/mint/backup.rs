@@ -54,6 +54,17 @@ impl MintClient {
             PlaintextEcashBackup::new_empty()
         };
 
+        self.restore_ecash_from_backup(backup, gap_limit, task_group)
+            .await
+    }
+
+    /// Recovers our ecash starting from the snapshot in `backup`, replacing the notes we have
+    pub async fn restore_ecash_from_backup(
+        &self,
+        backup: PlaintextEcashBackup,
+        gap_limit: usize,
+        task_group: &mut TaskGroup,
+    ) -> Result<Cancellable<()>> {
         let mut task_group = task_group.make_subgroup().await;
 
         // TODO: If the client attempts any operations between while the recovery is working,
@@ -169,7 +180,7 @@ impl MintClient {
         Self::get_derived_backup_signing_key_static(&self.secret)
     }
 
-    async fn prepare_plaintext_ecash_backup(&self) -> Result<PlaintextEcashBackup> {
+    pub(crate) async fn prepare_plaintext_ecash_backup(&self) -> Result<PlaintextEcashBackup> {
         // fetch consensus height first - so we dont miss anything when scanning
         let epoch = self.context.api.fetch_last_epoch().await?;
 
@@ -394,7 +405,7 @@ impl EcashBackup {
     pub fn into_backup_request(self, keypair: &KeyPair) -> Result<SignedBackupRequest> {
         let request = BackupRequest {
             id: keypair.x_only_public_key().0,
-            timestamp: std::time::SystemTime::now(),
+            timestamp: crate::utils::now(),
             payload: self.0,
         };
 
@@ -0,0 +1,111 @@
+//! Read tokens for sharing a single private entry
+//!
+//! A token bundles a [`SignedReadCapability`] granted by the entry's owner with the key the entry
+//! is encrypted with, so whoever holds it can read and decrypt that one entry without knowing the
+//! owner's secret. Tokens are printed as `smolfs-read:` followed by the URL-safe base64 encoding of
+//!
+//! ```text
+//! signed capability (consensus encoding) || entry key (32 bytes)
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, format_err};
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_core::modules::smolfs::common::SignedReadCapability;
+
+/// Prefix of the string encoding of [`ReadToken`]
+const READ_TOKEN_PREFIX: &str = "smolfs-read:";
+
+/// Everything needed to read a single private entry, created by
+/// [`super::SmolFSClient::create_read_token`]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReadToken {
+    pub capability: SignedReadCapability,
+    /// Key the entry's payload is encrypted with
+    pub entry_key: [u8; 32],
+}
+
+impl fmt::Display for ReadToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = self
+            .capability
+            .consensus_encode_to_vec()
+            .expect("Encoding to vec can't fail");
+        bytes.extend_from_slice(&self.entry_key);
+        write!(
+            f,
+            "{READ_TOKEN_PREFIX}{}",
+            base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+impl FromStr for ReadToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s
+            .strip_prefix(READ_TOKEN_PREFIX)
+            .ok_or_else(|| format_err!("Read token has to start with {READ_TOKEN_PREFIX}"))?;
+        let bytes = base64::decode_config(encoded, base64::URL_SAFE_NO_PAD)?;
+        if bytes.len() < 32 {
+            bail!("Read token too short");
+        }
+        let (capability, entry_key) = bytes.split_at(bytes.len() - 32);
+
+        let mut capability_reader = capability;
+        let capability = SignedReadCapability::consensus_decode(
+            &mut capability_reader,
+            &ModuleDecoderRegistry::default(),
+        )?;
+        if !capability_reader.is_empty() {
+            bail!("Read token has trailing bytes");
+        }
+        Ok(ReadToken {
+            capability,
+            entry_key: entry_key.try_into().expect("split at 32 bytes"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use fedimint_core::modules::smolfs::common::{ReadCapability, SignedReadCapability};
+    use secp256k1::{KeyPair, Secp256k1};
+
+    use super::ReadToken;
+
+    #[test]
+    fn read_token_string_roundtrip() {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+        let capability = ReadCapability {
+            key: "photos/cat.jpg".to_string(),
+            expiry: 1_700_000_000,
+            max_reads: Some(3),
+        };
+        let token = ReadToken {
+            capability: SignedReadCapability {
+                signature: secp.sign_schnorr(&capability.message(), &keypair),
+                capability,
+            },
+            entry_key: [42; 32],
+        };
+
+        let encoded = token.to_string();
+        assert!(encoded.starts_with("smolfs-read:"));
+        let decoded = ReadToken::from_str(&encoded).unwrap();
+        assert_eq!(decoded, token);
+        assert!(decoded
+            .capability
+            .verify(&secp, &keypair.x_only_public_key().0));
+
+        assert!(ReadToken::from_str(&encoded[1..]).is_err());
+        assert!(ReadToken::from_str("smolfs-read:AAAA").is_err());
+    }
+}
@@ -1,13 +1,17 @@
-use bitcoin::Script;
+use std::time::SystemTime;
+
+use bitcoin_hashes::sha256;
 use fedimint_api::db::DatabaseKeyPrefixConst;
 use fedimint_api::encoding::{Decodable, Encodable};
-use serde::Serialize;
+use fedimint_api::PeerId;
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
-    PegIn = 0x22,
+    AuditFailure = 0x2c,
+    AuditChallenges = 0x2e,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -17,21 +21,71 @@ impl std::fmt::Display for DbKeyPrefix {
 }
 
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
-pub struct PegInKey {
-    pub peg_in_script: Script,
+pub struct AuditFailureKey(pub PeerId);
+
+impl DatabaseKeyPrefixConst for AuditFailureKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::AuditFailure as u8;
+    type Key = Self;
+    type Value = AuditFailure;
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct AuditFailureKeyPrefix;
+
+impl DatabaseKeyPrefixConst for AuditFailureKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::AuditFailure as u8;
+    type Key = AuditFailureKey;
+    type Value = AuditFailure;
+}
+
+/// Most recent failed retrievability challenge of a guardian
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct AuditFailure {
+    /// Entry the guardian was challenged for
+    pub key: String,
+    pub reason: String,
+    pub time: SystemTime,
+    /// Number of challenges the guardian failed in total
+    pub failures: u64,
+}
+
+/// Challenges precomputed for the payload `hash` of the entry `key`
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct AuditChallengesKey {
+    pub key: String,
+    pub hash: sha256::Hash,
 }
 
-impl DatabaseKeyPrefixConst for PegInKey {
-    const DB_PREFIX: u8 = DbKeyPrefix::PegIn as u8;
+impl DatabaseKeyPrefixConst for AuditChallengesKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::AuditChallenges as u8;
     type Key = Self;
-    type Value = [u8; 32]; // TODO: introduce newtype
+    type Value = Vec<PrecomputedChallenge>;
 }
 
 #[derive(Debug, Clone, Encodable, Decodable)]
-pub struct PegInPrefixKey;
+pub struct AuditChallengesKeyPrefix;
+
+impl DatabaseKeyPrefixConst for AuditChallengesKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::AuditChallenges as u8;
+    type Key = AuditChallengesKey;
+    type Value = Vec<PrecomputedChallenge>;
+}
+
+/// Challenges precomputed for any payload of the entry
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct AuditChallengesEntryPrefix(pub String);
+
+impl DatabaseKeyPrefixConst for AuditChallengesEntryPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::AuditChallenges as u8;
+    type Key = AuditChallengesKey;
+    type Value = Vec<PrecomputedChallenge>;
+}
 
-impl DatabaseKeyPrefixConst for PegInPrefixKey {
-    const DB_PREFIX: u8 = DbKeyPrefix::PegIn as u8;
-    type Key = PegInKey;
-    type Value = [u8; 32];
+/// Retrievability challenge whose answer was computed while we still had the payload, each one
+/// is only used once
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PrecomputedChallenge {
+    pub nonce: [u8; 32],
+    pub chunk: u64,
+    pub response: sha256::Hash,
 }
@@ -0,0 +1,260 @@
+//! Client-side encryption of smolfs payloads
+//!
+//! Guardians only ever see encrypted payloads. Before uploading, the plaintext is compressed with
+//! zstd, prefixed with its compressed length, padded up to a size bucket and then encrypted with a
+//! key derived from the client secret and the entry's key. The result is prefixed with a format
+//! version byte so the encoding can be changed later without breaking existing entries:
+//!
+//! ```text
+//! version (1 byte) || nonce || aead(compressed_len (u64 LE) || compressed || zero padding) || tag
+//! ```
+//!
+//! A fresh nonce makes every write of the same plaintext look completely different, so binary
+//! deltas between two versions don't save anything. Payloads meant to be rewritten with deltas use
+//! the chunked format instead: the plaintext is prefixed with its length and padded the same way,
+//! but not compressed, and every chunk of [`CHUNK_SIZE`] bytes is encrypted on its own with a nonce
+//! derived from the key, its position and its content. Unchanged chunks thus encrypt to the same
+//! bytes on every write, at the cost of guardians learning which chunks changed:
+//!
+//! ```text
+//! version (1 byte) || (nonce || aead(chunk) || tag) for every chunk of
+//!     len (u64 LE) || plaintext || zero padding
+//! ```
+
+use anyhow::{bail, format_err, Result};
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_derive_secret::{ChildId, DerivableSecret};
+use ring::aead::Nonce;
+
+/// Version of the compressed payload format with a random nonce
+pub const SMOLFS_FORMAT_V1: u8 = 1;
+/// Version of the chunked payload format with deterministic nonces
+pub const SMOLFS_FORMAT_V2: u8 = 2;
+
+/// Plaintext size of the chunks of [`SMOLFS_FORMAT_V2`] payloads, divides every size bucket
+const CHUNK_SIZE: usize = 4 * 1024;
+
+/// zstd compression level used for new payloads
+const COMPRESSION_LEVEL: i32 = 3;
+/// Smallest size bucket, every payload is padded to at least this size
+const MIN_ALIGNMENT: usize = 4 * 1024;
+
+/// Align a compressed payload size up to its size bucket for better privacy
+///
+/// Small payloads are padded to multiples of [`MIN_ALIGNMENT`], larger ones to 1/8 of the next
+/// power of two, so guardians learn the size up to ~12.5% while the overhead stays bounded.
+pub fn get_alignment_size(len: usize) -> usize {
+    let padding_alignment = std::cmp::max(MIN_ALIGNMENT, len.next_power_of_two() / 8);
+    ((len.saturating_sub(1) / padding_alignment) + 1) * padding_alignment
+}
+
+/// Derives the encryption key of the entry stored under `key`
+///
+/// Every entry gets its own key so that sharing one of them (e.g. with a capability token) doesn't
+/// reveal any other entry.
+pub fn derive_entry_key(secret: &DerivableSecret, key: &str) -> aead::LessSafeKey {
+    entry_key_from_bytes(&derive_entry_key_bytes(secret, key))
+}
+
+/// Raw bytes of [`derive_entry_key`], included in read tokens for the entry
+pub fn derive_entry_key_bytes(secret: &DerivableSecret, key: &str) -> [u8; 32] {
+    let key_hash = sha256::Hash::hash(key.as_bytes());
+    let child_id = u64::from_le_bytes(key_hash[..8].try_into().expect("hash is 32 bytes"));
+    secret
+        .child_key(ChildId(child_id))
+        .to_chacha20_poly1305_key_raw()
+}
+
+/// Entry key from the bytes returned by [`derive_entry_key_bytes`]
+pub fn entry_key_from_bytes(bytes: &[u8; 32]) -> aead::LessSafeKey {
+    aead::LessSafeKey::new(
+        aead::UnboundKey::new(&ring::aead::CHACHA20_POLY1305, bytes).expect("created key"),
+    )
+}
+
+/// Compresses, pads and encrypts `plaintext` in the current format
+pub fn encrypt_payload(plaintext: &[u8], key: &aead::LessSafeKey) -> Result<Vec<u8>> {
+    let compressed = zstd::bulk::compress(plaintext, COMPRESSION_LEVEL)?;
+
+    let mut padded = Vec::with_capacity(get_alignment_size(compressed.len() + 8));
+    padded.extend_from_slice(&(compressed.len() as u64).to_le_bytes());
+    padded.extend_from_slice(&compressed);
+    padded.resize(get_alignment_size(padded.len()), 0);
+
+    let mut payload = vec![SMOLFS_FORMAT_V1];
+    payload.append(&mut aead::encrypt(padded, key)?);
+    Ok(payload)
+}
+
+/// Pads and encrypts `plaintext` chunk by chunk with the entry key `key_bytes`
+///
+/// Encrypting the same plaintext twice gives the same payload, and plaintexts differing in a few
+/// chunks only give payloads differing in those chunks, which keeps deltas between them small.
+pub fn encrypt_payload_chunked(plaintext: &[u8], key_bytes: &[u8; 32]) -> Result<Vec<u8>> {
+    let key = entry_key_from_bytes(key_bytes);
+    let mut nonce_key = HmacEngine::<sha256::Hash>::new(key_bytes);
+    nonce_key.input(b"smolfs-chunk-nonce");
+    let nonce_key = Hmac::from_engine(nonce_key).into_inner();
+
+    let mut padded = Vec::with_capacity(get_alignment_size(plaintext.len() + 8));
+    padded.extend_from_slice(&(plaintext.len() as u64).to_le_bytes());
+    padded.extend_from_slice(plaintext);
+    padded.resize(get_alignment_size(padded.len()), 0);
+
+    let mut payload = vec![SMOLFS_FORMAT_V2];
+    for (idx, chunk) in padded.chunks(CHUNK_SIZE).enumerate() {
+        let idx = (idx as u64).to_le_bytes();
+        let mut engine = HmacEngine::<sha256::Hash>::new(&nonce_key);
+        engine.input(&idx);
+        engine.input(chunk);
+        let nonce: [u8; aead::NONCE_LEN] = Hmac::from_engine(engine).into_inner()
+            [..aead::NONCE_LEN]
+            .try_into()
+            .expect("hmac is 32 bytes");
+
+        // The position is authenticated so chunks can't be reordered
+        let mut sealed = chunk.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            aead::Aad::from(idx),
+            &mut sealed,
+        )
+        .map_err(|_| format_err!("Encryption failed due to unspecified aead error"))?;
+        payload.extend_from_slice(&nonce);
+        payload.append(&mut sealed);
+    }
+    Ok(payload)
+}
+
+/// Reverses [`encrypt_payload`] and [`encrypt_payload_chunked`], supporting all known format
+/// versions
+pub fn decrypt_payload(mut payload: Vec<u8>, key: &aead::LessSafeKey) -> Result<Vec<u8>> {
+    let Some((&mut version, ciphertext)) = payload.split_first_mut() else {
+        bail!("Empty payload");
+    };
+    match version {
+        SMOLFS_FORMAT_V1 => decrypt_compressed(ciphertext, key),
+        SMOLFS_FORMAT_V2 => decrypt_chunked(ciphertext, key),
+        _ => bail!("Unknown payload format version {version}"),
+    }
+}
+
+fn decrypt_compressed(ciphertext: &mut [u8], key: &aead::LessSafeKey) -> Result<Vec<u8>> {
+    let padded = aead::decrypt(ciphertext, key)?;
+    if padded.len() < 8 {
+        bail!("Decrypted payload too short: {}", padded.len());
+    }
+    let (len, rest) = padded.split_at(8);
+    let len = u64::from_le_bytes(len.try_into().expect("split at 8")) as usize;
+    let compressed = rest
+        .get(..len)
+        .ok_or_else(|| format_err!("Invalid compressed length {len}"))?;
+
+    let mut plaintext = vec![];
+    zstd::stream::copy_decode(compressed, &mut plaintext)?;
+    Ok(plaintext)
+}
+
+fn decrypt_chunked(ciphertext: &mut [u8], key: &aead::LessSafeKey) -> Result<Vec<u8>> {
+    let sealed_size = aead::NONCE_LEN + CHUNK_SIZE + key.algorithm().tag_len();
+    if ciphertext.is_empty() || ciphertext.len() % sealed_size != 0 {
+        bail!("Invalid chunked payload size {}", ciphertext.len());
+    }
+
+    let mut padded = Vec::with_capacity(ciphertext.len() / sealed_size * CHUNK_SIZE);
+    for (idx, sealed) in ciphertext.chunks_mut(sealed_size).enumerate() {
+        let (nonce, chunk) = sealed.split_at_mut(aead::NONCE_LEN);
+        let nonce = Nonce::assume_unique_for_key(nonce.try_into().expect("split at nonce length"));
+        let chunk = key
+            .open_in_place(nonce, aead::Aad::from((idx as u64).to_le_bytes()), chunk)
+            .map_err(|_| format_err!("Decryption failed due to unspecified aead error"))?;
+        padded.extend_from_slice(chunk);
+    }
+
+    let (len, rest) = padded.split_at(8);
+    let len = u64::from_le_bytes(len.try_into().expect("split at 8")) as usize;
+    let plaintext = rest
+        .get(..len)
+        .ok_or_else(|| format_err!("Invalid plaintext length {len}"))?;
+    // Dropping or appending whole chunks would otherwise go unnoticed
+    if get_alignment_size(len + 8) != padded.len() {
+        bail!(
+            "Chunked payload of {} bytes doesn't match plaintext length {len}",
+            padded.len()
+        );
+    }
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_derive_secret::DerivableSecret;
+
+    use super::*;
+
+    #[test]
+    fn alignment_buckets() {
+        assert_eq!(get_alignment_size(1), 4 * 1024);
+        assert_eq!(get_alignment_size(4 * 1024), 4 * 1024);
+        assert_eq!(get_alignment_size(40 * 1024), 40 * 1024);
+        assert_eq!(get_alignment_size(40 * 1024 + 1), 48 * 1024);
+        assert_eq!(
+            get_alignment_size(1024 * 1024 + 1),
+            1024 * 1024 + 256 * 1024
+        );
+    }
+
+    #[test]
+    fn encrypt_decrypt_roundtrip() {
+        let secret = DerivableSecret::new_root(&[], &[]);
+        let key = derive_entry_key(&secret, "docs/notes.txt");
+        let plaintext = b"smolfs ".repeat(1000);
+
+        let payload = encrypt_payload(&plaintext, &key).unwrap();
+        assert_eq!(payload[0], SMOLFS_FORMAT_V1);
+        assert_eq!(
+            (payload.len() - 1 - aead::NONCE_LEN - 16) % MIN_ALIGNMENT,
+            0
+        );
+        assert_eq!(decrypt_payload(payload.clone(), &key).unwrap(), plaintext);
+
+        let other_key = derive_entry_key(&secret, "docs/other.txt");
+        assert!(decrypt_payload(payload, &other_key).is_err());
+    }
+
+    #[test]
+    fn chunked_payloads_only_differ_in_changed_chunks() {
+        let secret = DerivableSecret::new_root(&[], &[]);
+        let key_bytes = derive_entry_key_bytes(&secret, "backup");
+        let key = entry_key_from_bytes(&key_bytes);
+        let mut plaintext = vec![7; 5 * CHUNK_SIZE];
+
+        let payload = encrypt_payload_chunked(&plaintext, &key_bytes).unwrap();
+        assert_eq!(payload[0], SMOLFS_FORMAT_V2);
+        assert_eq!(
+            payload,
+            encrypt_payload_chunked(&plaintext, &key_bytes).unwrap()
+        );
+        assert_eq!(decrypt_payload(payload.clone(), &key).unwrap(), plaintext);
+
+        plaintext[3 * CHUNK_SIZE] = 8;
+        let changed = encrypt_payload_chunked(&plaintext, &key_bytes).unwrap();
+        assert_eq!(changed.len(), payload.len());
+        let sealed_size = aead::NONCE_LEN + CHUNK_SIZE + 16;
+        let differing = payload[1..]
+            .chunks(sealed_size)
+            .zip(changed[1..].chunks(sealed_size))
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(differing, 1);
+        assert_eq!(decrypt_payload(changed.clone(), &key).unwrap(), plaintext);
+
+        // Reordered or truncated chunks are rejected
+        let mut swapped = changed.clone();
+        swapped[1..].rotate_left(sealed_size);
+        assert!(decrypt_payload(swapped, &key).is_err());
+        let truncated = changed[..changed.len() - sealed_size].to_vec();
+        assert!(decrypt_payload(truncated, &key).is_err());
+    }
+}
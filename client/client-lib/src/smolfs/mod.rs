@@ -1,45 +1,65 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use bitcoin::Address;
-use bitcoin::KeyPair;
-use bitcoin_hashes::sha256;
-use bitcoin_hashes::Hash;
-use db::PegInKey;
+use bitcoin_hashes::{sha256, Hash};
 use fedimint_api::core::client::ClientModule;
-use fedimint_api::db::DatabaseTransaction;
+use fedimint_api::encoding::Encodable;
 use fedimint_api::module::TransactionItemAmount;
-use fedimint_api::OutPoint;
+use fedimint_api::task::{sleep, TaskHandle};
 use fedimint_api::PeerId;
 use fedimint_api::{Amount, ServerModule};
-use fedimint_core::modules::smolfs::common::SmolFSDecoder;
+use fedimint_core::modules::smolfs::common::{
+    challenge_chunk, challenge_chunk_count, challenge_response, write_fee, CapabilityReadRequest,
+    ChallengeRequest, ChangeEvent, EntryMeta, ReadCapability, SignedReadCapability, SmolFSDecoder,
+    VersionMeta, WatchRequest, WatchTarget,
+};
 use fedimint_core::modules::smolfs::config::SmolFSClientConfig;
-use fedimint_core::modules::smolfs::db::ExampleKey;
-use fedimint_core::modules::wallet::common::WalletDecoder;
-use fedimint_core::modules::wallet::config::WalletClientConfig;
-use fedimint_core::modules::wallet::tweakable::Tweakable;
-use fedimint_core::modules::wallet::txoproof::{PegInProof, PegInProofError, TxOutProof};
-use fedimint_core::modules::wallet::{Wallet, WalletOutputOutcome};
-use fedimint_smolfs::db::FinishedSmolFSEntry;
-use rand::{CryptoRng, RngCore};
+use fedimint_core::modules::smolfs::delta::Delta;
+use fedimint_core::modules::smolfs::pow;
+use fedimint_core::modules::smolfs::upload::MAX_CHUNK_SIZE;
+use fedimint_core::modules::smolfs::{SmolFS, SmolFSEntry, SmolFSPayload, MAX_INLINE_PAYLOAD_SIZE};
+use fedimint_derive_secret::DerivableSecret;
+use futures::Stream;
+use rand::{thread_rng, Rng};
+use secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey};
 use thiserror::Error;
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::api::GlobalFederationApi;
-use crate::api::OutputOutcomeError;
-use crate::api::SmolFSFederationApi;
-use crate::api::WalletFederationApi;
-use crate::utils::ClientContext;
-use crate::MemberError;
+use crate::api::{FederationError, SmolFSFederationApi};
+use crate::smolfs::capability::ReadToken;
+use crate::smolfs::db::{
+    AuditChallengesEntryPrefix, AuditChallengesKey, AuditFailure, AuditFailureKey,
+    AuditFailureKeyPrefix, PrecomputedChallenge,
+};
+use crate::smolfs::encryption::{
+    decrypt_payload, derive_entry_key, derive_entry_key_bytes, encrypt_payload,
+    encrypt_payload_chunked, entry_key_from_bytes,
+};
+use crate::smolfs::stream::{SmolFSDownload, SmolFSUpload};
+use crate::utils::{now, ClientContext};
 
+pub mod capability;
 pub mod db;
-use fedimint_core::modules::smolfs::*;
+pub mod encryption;
+pub mod stream;
 
-/// Federation module client for the Wallet module. It can both create transaction inputs and
-/// outputs of the wallet (on-chain) type.
+/// Number of challenges computed for a payload at once, every audit round uses one per guardian
+///
+/// Federations with more guardians get one challenge per guardian instead.
+const AUDIT_CHALLENGE_BATCH: usize = 64;
+
+/// How long the capabilities we sign for reading our own private entries stay valid
+const OWNER_READ_CAPABILITY_VALIDITY: Duration = Duration::from_secs(10 * 60);
+
+/// Federation module client for the SmolFS module. It stores and retrieves entries and audits
+/// the guardians holding them.
 #[derive(Debug)]
 pub struct SmolFSClient {
     pub config: SmolFSClientConfig,
     pub context: Arc<ClientContext>,
+    /// Secret all entry encryption keys are derived from
+    pub secret: DerivableSecret,
 }
 
 impl ClientModule for SmolFSClient {
@@ -51,242 +71,1075 @@ impl ClientModule for SmolFSClient {
         SmolFSDecoder
     }
 
-    fn input_amount(
-        &self,
-        _input: &<Self::Module as ServerModule>::Input,
-    ) -> TransactionItemAmount {
+    fn input_amount(&self, input: &<Self::Module as ServerModule>::Input) -> TransactionItemAmount {
         TransactionItemAmount {
-            amount: Amount::from_sats(0),
-            fee: Amount::from_sats(0),
+            amount: input.prepaid,
+            fee: self.write_fee(&input.payload),
         }
     }
 
     fn output_amount(
         &self,
-        _output: &<Self::Module as ServerModule>::Output,
+        output: &<Self::Module as ServerModule>::Output,
     ) -> TransactionItemAmount {
         TransactionItemAmount {
-            amount: Amount::from_sats(0),
-            fee: Amount::from_sats(0),
+            amount: output.amount,
+            fee: Amount::ZERO,
         }
     }
 }
 
 impl SmolFSClient {
-    /// Returns a bitcoin-address derived from the federations peg-in-descriptor and a random tweak
+    /// Compresses, pads and encrypts `plaintext` for storing it under `key`
+    ///
+    /// The result is what guardians get to see, see [`encryption`] for the format.
+    pub fn encrypt(&self, key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        encrypt_payload(plaintext, &derive_entry_key(&self.secret, key))
+            .map_err(SmolFSClientError::Encryption)
+    }
+
+    /// Pads and encrypts `plaintext` for storing it under `key` so that rewriting it with a few
+    /// changes only needs a small delta, see [`SmolFSClient::delta_against_current`]
+    pub fn encrypt_chunked(&self, key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        encrypt_payload_chunked(plaintext, &derive_entry_key_bytes(&self.secret, key))
+            .map_err(SmolFSClientError::Encryption)
+    }
+
+    /// Decrypts a payload previously stored under `key` using [`SmolFSClient::encrypt`] or
+    /// [`SmolFSClient::encrypt_chunked`]
+    pub fn decrypt(&self, key: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+        decrypt_payload(payload, &derive_entry_key(&self.secret, key))
+            .map_err(SmolFSClientError::Encryption)
+    }
+
+    /// Key owning the storage balance our writes draw their fees from
+    ///
+    /// Anyone can prepay storage for us using its public key, see
+    /// [`crate::Client::smolfs_prepay`].
+    pub fn owner_key(&self) -> KeyPair {
+        self.secret
+            .clone()
+            .to_secp_key(&Secp256k1::<secp256k1::SignOnly>::gen_new())
+    }
+
+    /// Fee the federation charges for writing `payload`
+    pub fn write_fee(&self, payload: &SmolFSPayload) -> Amount {
+        write_fee(self.config.write_fee_per_kib, payload.size())
+    }
+
+    /// Storage balance prepaid for `owner` that write fees are drawn from first
+    pub async fn prepaid_balance(&self, owner: XOnlyPublicKey) -> Result<Amount> {
+        Ok(self.context.api.fetch_prepaid_balance(owner).await?)
+    }
+
+    /// Fetches and decrypts the entry stored under `key` from the federation
+    pub async fn get_entry(&self, key: String) -> Result<Option<Vec<u8>>> {
+        self.get_raw_entry(key.clone())
+            .await?
+            .map(|payload| self.decrypt(&key, payload))
+            .transpose()
+    }
+
+    /// Fetches the entry stored under `key` as the guardians store it, without decrypting it
+    pub async fn get_raw_entry(&self, key: String) -> Result<Option<Vec<u8>>> {
+        match self.download(key).await? {
+            Some(download) => download.read_to_end().await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Downloads and decrypts the entry stored under `key` regardless of its size
+    pub async fn read_file(&self, key: String) -> Result<Option<Vec<u8>>> {
+        let Some(download) = self.download(key.clone()).await? else {
+            return Ok(None);
+        };
+        let payload = download.read_to_end().await?;
+        self.decrypt(&key, payload).map(Some)
+    }
+
+    /// Grants whoever holds the returned token read access to the entry `key` until `expiry`
     ///
-    /// This function will create a public/secret [keypair](bitcoin::KeyPair). The public key is used to tweak the
-    /// federations peg-in-descriptor resulting in a bitcoin script. Both script and keypair are stored in the DB
-    /// by using the script as part of the key and the keypair as the value. Even though only the public-key is used to tweak
-    /// the descriptor, the secret-key is needed to prove that one actually created the tweak to be able to claim the funds and
-    /// prevent front-running by a malicious  federation member
-    /// The returned bitcoin-address is derived from the script. Thus sending bitcoin to that address will result in a
-    /// transaction containing the scripts public-key in at least one of it's outpoints.
-    pub async fn add_entry<'a>(
+    /// Each guardian serves the entry at most `max_reads` times for the token if set. Only works
+    /// for entries we own, which all entries written by [`crate::Client::smolfs_put`] are.
+    pub fn create_read_token(
         &self,
-        // dbtx: &mut DatabaseTransaction<'a>,
-        pubkey: String,
-        backup: String,
-        // mut rng: R,
-    ) -> String {
-        // self.context.api.fetch_backups_by_pubkey(pubkey);
-        // let mut dbtx = self.context.db.begin_transaction().await;
-        let peerid = PeerId::from(0);
-        let a = self
-            .context
-            .api
-            .request_raw(
-                peerid,
-                "smolfsput",
-                &[serde_json::Value::String(format!("{pubkey} {backup}"))],
-            )
+        key: String,
+        expiry: SystemTime,
+        max_reads: Option<u64>,
+    ) -> ReadToken {
+        ReadToken {
+            capability: self.sign_read_capability(key.clone(), expiry, max_reads),
+            entry_key: derive_entry_key_bytes(&self.secret, &key),
+        }
+    }
+
+    /// Capability for reading one of our own private entries, the guardians only serve those to
+    /// their owner
+    pub(crate) fn owner_read_capability(&self, key: String) -> SignedReadCapability {
+        self.sign_read_capability(key, now() + OWNER_READ_CAPABILITY_VALIDITY, None)
+    }
+
+    fn sign_read_capability(
+        &self,
+        key: String,
+        expiry: SystemTime,
+        max_reads: Option<u64>,
+    ) -> SignedReadCapability {
+        let capability = ReadCapability {
+            key,
+            expiry: expiry
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+            max_reads,
+        };
+        let signature =
+            Secp256k1::signing_only().sign_schnorr(&capability.message(), &self.owner_key());
+        SignedReadCapability {
+            capability,
+            signature,
+        }
+    }
+
+    /// Downloads and decrypts the entry a read token was created for
+    ///
+    /// Doesn't need our secret, so tokens created by anyone can be read.
+    pub async fn read_with_token(&self, token: &ReadToken) -> Result<Option<Vec<u8>>> {
+        let key = token.capability.capability.key.clone();
+        let Some(meta) = self.context.api.fetch_entry_meta(key).await? else {
+            return Ok(None);
+        };
+
+        let mut payload = Vec::with_capacity(meta.size as usize);
+        while (payload.len() as u64) < meta.size {
+            let request = CapabilityReadRequest {
+                capability: token.capability.clone(),
+                offset: payload.len() as u64,
+                len: std::cmp::min(MAX_CHUNK_SIZE, meta.size - payload.len() as u64),
+            };
+            let chunk = self
+                .context
+                .api
+                .read_with_capability(request)
+                .await?
+                .ok_or(SmolFSClientError::EntryChanged)?
+                .bytes;
+            if chunk.is_empty() {
+                return Err(SmolFSClientError::EntryChanged);
+            }
+            payload.extend(chunk);
+        }
+        if sha256::Hash::hash(&payload) != meta.hash {
+            return Err(SmolFSClientError::EntryChanged);
+        }
+
+        decrypt_payload(payload, &entry_key_from_bytes(&token.entry_key))
+            .map(Some)
+            .map_err(SmolFSClientError::Encryption)
+    }
+
+    /// Adds the proof-of-work the federation requires for writes to `entry`, if any
+    ///
+    /// The work is done on a blocking thread since it can take a while for large payloads.
+    pub async fn solve_pow(&self, entry: SmolFSEntry) -> SmolFSEntry {
+        let Some(base_difficulty) = self.config.pow_difficulty else {
+            return entry;
+        };
+        let bits = pow::required_bits(base_difficulty, entry.payload.size());
+        solve_pow_blocking(entry, bits).await
+    }
+
+    /// Lists the entries whose key starts with `prefix`, regardless of who owns them
+    pub async fn list(&self, prefix: String) -> Result<BTreeMap<String, EntryMeta>> {
+        Ok(self.context.api.list_entries(prefix).await?)
+    }
+
+    /// Lists the versions of the entry stored under `key` the federation still retains
+    pub async fn versions(&self, key: String) -> Result<BTreeMap<u64, VersionMeta>> {
+        Ok(self.context.api.fetch_entry_versions(key).await?)
+    }
+
+    /// Downloads and decrypts an older version of the entry stored under `key`
+    pub async fn get_version(&self, key: String, version: u64) -> Result<Option<Vec<u8>>> {
+        let Some(download) = self.download_version(key.clone(), version).await? else {
+            return Ok(None);
+        };
+        let payload = download.read_to_end().await?;
+        self.decrypt(&key, payload).map(Some)
+    }
+
+    /// Builds a payload that makes a retained older version the current one again, to be
+    /// written with [`crate::Client::smolfs_put`]
+    ///
+    /// The guardians still hold the old blob, so nothing has to be uploaded and no upload
+    /// receipts are needed.
+    pub async fn restore_version(&self, key: String, version: u64) -> Result<SmolFSPayload> {
+        let meta = self
+            .versions(key)
+            .await?
+            .remove(&version)
+            .ok_or(SmolFSClientError::UnknownVersion(version))?;
+        Ok(SmolFSPayload::Uploaded {
+            hash: meta.hash,
+            size: meta.size,
+            receipts: BTreeMap::new(),
+        })
+    }
+
+    /// Streams every change to the entries matched by `target` after the change with sequence
+    /// number `since`, pass `0` to start with the oldest change the federation still remembers
+    ///
+    /// Each item is only yielded once the federation agrees on it. The stream never ends on its
+    /// own, it ends after the first error.
+    pub fn watch(
+        &self,
+        target: WatchTarget,
+        since: u64,
+    ) -> impl Stream<Item = Result<ChangeEvent>> + '_ {
+        futures::stream::unfold(Some(since), move |since| {
+            let target = target.clone();
+            async move {
+                let mut since = since?;
+                loop {
+                    let request = WatchRequest {
+                        target: target.clone(),
+                        since,
+                    };
+                    match self.context.api.smolfs_watch(request).await {
+                        Ok(Some(change)) => {
+                            since = change.seq;
+                            return Some((Ok(change), Some(since)));
+                        }
+                        Ok(None) => continue,
+                        Err(e) => return Some((Err(e.into()), None)),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Builds a payload that turns the stored payload `base` into `new` by only
+    /// transferring the changed bytes, to be written with [`crate::Client::smolfs_put`]
+    ///
+    /// Both payloads are the bytes as stored by the guardians. Only payloads that keep their
+    /// unchanged parts byte for byte benefit, i.e. public ones and those encrypted with
+    /// [`SmolFSClient::encrypt_chunked`], but not those encrypted with [`SmolFSClient::encrypt`].
+    pub fn delta_payload(base: &[u8], new: &[u8]) -> SmolFSPayload {
+        SmolFSPayload::Delta {
+            base: sha256::Hash::hash(base),
+            delta: Delta::compute(base, new),
+            hash: sha256::Hash::hash(new),
+        }
+    }
+
+    /// Delta turning the payload currently stored under `key` into `new`, if the entry exists and
+    /// the delta is at most half the size of `new`
+    ///
+    /// The current payload is downloaded from the federation to compute the delta, which saves
+    /// uploading `new` to every guardian and keeps it out of the consensus transaction.
+    pub async fn delta_against_current(
+        &self,
+        key: String,
+        new: &[u8],
+    ) -> Result<Option<SmolFSPayload>> {
+        let Some(base) = self.get_raw_entry(key).await? else {
+            return Ok(None);
+        };
+        if base == new {
+            return Ok(None);
+        }
+
+        let delta = Delta::compute(&base, new);
+        let encoded_size = delta
+            .consensus_encode_to_vec()
+            .expect("Encoding to vec can't fail")
+            .len() as u64;
+        if encoded_size > MAX_INLINE_PAYLOAD_SIZE || encoded_size > new.len() as u64 / 2 {
+            return Ok(None);
+        }
+        Ok(Some(SmolFSPayload::Delta {
+            base: sha256::Hash::hash(&base),
+            delta,
+            hash: sha256::Hash::hash(new),
+        }))
+    }
+
+    /// Starts a chunked upload of a payload of `size` bytes to all guardians
+    pub async fn upload(&self, size: u64) -> Result<SmolFSUpload<'_>> {
+        SmolFSUpload::start(self, size).await
+    }
+
+    /// Starts reading the entry stored under `key` in chunks, returns `None` if it doesn't exist
+    ///
+    /// The stream yields the encrypted payload, see [`SmolFSClient::read_file`].
+    pub async fn download(&self, key: String) -> Result<Option<SmolFSDownload<'_>>> {
+        SmolFSDownload::start(self, key).await
+    }
+
+    /// Starts reading a retained version of the entry stored under `key` in chunks, returns
+    /// `None` if the federation doesn't retain it
+    pub async fn download_version(
+        &self,
+        key: String,
+        version: u64,
+    ) -> Result<Option<SmolFSDownload<'_>>> {
+        SmolFSDownload::start_version(self, key, version).await
+    }
+
+    /// Computes a batch of retrievability challenges for `payload` stored under `key`
+    ///
+    /// Called while writing, so audits don't have to download the payload again to come up with
+    /// challenges. Challenges computed for earlier payloads of the entry are dropped.
+    pub async fn precompute_challenges(&self, key: &str, payload: &[u8]) {
+        let challenges = self.compute_challenges(payload);
+        self.store_challenges(key, sha256::Hash::hash(payload), challenges)
+            .await;
+    }
+
+    fn compute_challenges(&self, payload: &[u8]) -> Vec<PrecomputedChallenge> {
+        let count = std::cmp::max(AUDIT_CHALLENGE_BATCH, self.context.api.all_members().len());
+        let mut rng = thread_rng();
+        (0..count)
+            .map(|_| {
+                let nonce = rng.gen();
+                let chunk = rng.gen_range(0..challenge_chunk_count(payload.len()));
+                let bytes = challenge_chunk(payload, chunk).expect("Chunk is in range");
+                PrecomputedChallenge {
+                    nonce,
+                    chunk,
+                    response: challenge_response(&nonce, bytes).hash,
+                }
+            })
+            .collect()
+    }
+
+    /// Stores the unused challenges for the payload `hash` of `key`, replacing those of earlier
+    /// payloads
+    async fn store_challenges(
+        &self,
+        key: &str,
+        hash: sha256::Hash,
+        challenges: Vec<PrecomputedChallenge>,
+    ) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_by_prefix(&AuditChallengesEntryPrefix(key.to_owned()))
             .await
-            .unwrap();
-        // let mut dbtx = db.begin_transaction().await;
+            .expect("DB error");
+        let db_key = AuditChallengesKey {
+            key: key.to_owned(),
+            hash,
+        };
+        dbtx.insert_entry(&db_key, &challenges)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB error");
+    }
 
-        println!("add entry generating fake smolfs entry {:?}", a);
+    /// Challenges every guardian to prove it still holds the current payload of `key`
+    ///
+    /// Each guardian has to hash a randomly chosen chunk of the payload together with a fresh
+    /// nonce, which it can only do if it has access to the data. Every challenge is only used
+    /// once, the payload is only downloaded again to compute new ones once all challenges from
+    /// [`SmolFSClient::precompute_challenges`] are used up. Guardians that fail to answer
+    /// correctly are recorded in the client database and returned.
+    pub async fn audit_entry(&self, key: String) -> Result<BTreeSet<PeerId>> {
+        let Some(meta) = self.context.api.fetch_entry_meta(key.clone()).await? else {
+            debug!(%key, "Skipping audit of unknown entry");
+            return Ok(BTreeSet::new());
+        };
+        let peers = self.context.api.all_members().clone();
+        let challenges = match self.take_challenges(&key, meta.hash, peers.len()).await {
+            Some(challenges) => challenges,
+            None => {
+                let download = self
+                    .download(key.clone())
+                    .await?
+                    .ok_or(SmolFSClientError::EntryChanged)?;
+                let payload = download.read_to_end().await?;
+                if sha256::Hash::hash(&payload) != meta.hash {
+                    return Err(SmolFSClientError::EntryChanged);
+                }
+                let mut challenges = self.compute_challenges(&payload);
+                let taken = challenges.split_off(challenges.len() - peers.len());
+                self.store_challenges(&key, meta.hash, challenges).await;
+                taken
+            }
+        };
 
-        pubkey
+        let mut failed = BTreeSet::new();
+        for (peer, challenge) in peers.into_iter().zip(challenges) {
+            let request = ChallengeRequest {
+                key: key.clone(),
+                nonce: challenge.nonce,
+                chunk: challenge.chunk,
+            };
+
+            let reason = match self.context.api.smolfs_challenge(peer, &request).await {
+                Ok(Some(response)) if response.hash == challenge.response => continue,
+                Ok(Some(_)) => "wrong challenge response".to_string(),
+                Ok(None) => "entry not held".to_string(),
+                Err(e) => format!("request failed: {e}"),
+            };
+            warn!(%peer, %key, %reason, "Guardian failed retrievability challenge");
+            self.record_audit_failure(peer, key.clone(), reason).await;
+            failed.insert(peer);
+        }
+
+        Ok(failed)
     }
-    pub async fn get_entry<'a>(
+
+    /// Periodically audits the entries stored under `keys` until the task group shuts down
+    ///
+    /// Uses the challenges computed when the entries were written, see
+    /// [`SmolFSClient::audit_entry`].
+    pub async fn run_audit(&self, keys: Vec<String>, interval: Duration, handle: &TaskHandle) {
+        while !handle.is_shutting_down() {
+            for key in &keys {
+                match self.audit_entry(key.clone()).await {
+                    Ok(failed) => debug!(%key, ?failed, "Audited entry"),
+                    Err(e) => warn!(%key, "Failed to audit entry: {}", e),
+                }
+            }
+            sleep(interval).await;
+        }
+    }
+
+    /// Removes and returns `count` unused challenges for the payload `hash` of `key`, `None` if
+    /// fewer are left
+    async fn take_challenges(
         &self,
-        dbtx: &mut DatabaseTransaction<'a>,
-        pubkey: String,
-        // mut rng: R,
-    ) -> String {
-        println!("get entry");
-        // self.context.api.fetch_backups_by_pubkey(pubkey).await.unwrap().unwrap()
-        let mut dbtx = dbtx.with_module_prefix(3);
-        let a = dbtx.get_value(&ExampleKey(pubkey)).await.unwrap().unwrap();
-        // String::new()
-        a
+        key: &str,
+        hash: sha256::Hash,
+        count: usize,
+    ) -> Option<Vec<PrecomputedChallenge>> {
+        let db_key = AuditChallengesKey {
+            key: key.to_owned(),
+            hash,
+        };
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut challenges = dbtx.get_value(&db_key).await.expect("DB error")?;
+        if challenges.len() < count {
+            return None;
+        }
+        let taken = challenges.split_off(challenges.len() - count);
+        dbtx.insert_entry(&db_key, &challenges)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB error");
+        Some(taken)
     }
+
+    /// Returns the last recorded audit failure of every guardian that ever failed one
+    pub async fn audit_failures(&self) -> Vec<(PeerId, AuditFailure)> {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&AuditFailureKeyPrefix)
+            .await
+            .map(|res| {
+                let (key, failure) = res.expect("DB error");
+                (key.0, failure)
+            })
+            .collect()
+    }
+
+    async fn record_audit_failure(&self, peer: PeerId, key: String, reason: String) {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let failures = dbtx
+            .get_value(&AuditFailureKey(peer))
+            .await
+            .expect("DB error")
+            .map_or(0, |failure| failure.failures);
+        let failure = AuditFailure {
+            key,
+            reason,
+            time: now(),
+            failures: failures + 1,
+        };
+        dbtx.insert_entry(&AuditFailureKey(peer), &failure)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB error");
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+async fn solve_pow_blocking(entry: SmolFSEntry, bits: u32) -> SmolFSEntry {
+    tokio::task::spawn_blocking(move || pow::solve(entry, bits))
+        .await
+        .expect("Proof-of-work task panicked")
+}
+
+/// There are no threads to block in the browser
+#[cfg(target_family = "wasm")]
+async fn solve_pow_blocking(entry: SmolFSEntry, bits: u32) -> SmolFSEntry {
+    pow::solve(entry, bits)
 }
 
-type Result<T> = std::result::Result<T, WalletClientError>;
+type Result<T> = std::result::Result<T, SmolFSClientError>;
 
 #[derive(Error, Debug)]
-pub enum WalletClientError {
-    #[error("Could not find an ongoing matching peg-in")]
-    NoMatchingPegInFound,
-    #[error("Peg-in amount must be greater than peg-in fee")]
-    PegInAmountTooSmall,
-    #[error("Inconsistent peg-in proof: {0}")]
-    PegInProofError(PegInProofError),
-    #[error("Output outcome error: {0}")]
-    OutputOutcomeError(#[from] OutputOutcomeError),
-    #[error("Mint API error: {0}")]
-    ApiError(#[from] MemberError),
+pub enum SmolFSClientError {
+    #[error("Federation API error: {0}")]
+    ApiError(#[from] FederationError),
+    #[error("Not enough guardians accepted the upload")]
+    NotEnoughGuardians,
+    #[error("Written bytes don't match the announced upload size")]
+    SizeMismatch,
+    #[error("Entry changed or was corrupted while reading it")]
+    EntryChanged,
+    #[error("Version {0} of the entry isn't retained by the federation")]
+    UnknownVersion(u64),
+    #[error("Failed to encrypt or decrypt payload: {0}")]
+    Encryption(anyhow::Error),
 }
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::path::Path;
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, UNIX_EPOCH};
 
-    use bitcoin::hashes::sha256;
-    use bitcoin::{Address, Txid};
-    use bitcoin_hashes::Hash;
+    use bitcoin_hashes::{sha256, Hash};
     use fedimint_api::config::ConfigGenParams;
-    use fedimint_api::core::{
-        DynOutputOutcome, ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
-    };
+    use fedimint_api::core::client::ClientModule;
+    use fedimint_api::core::ModuleInstanceId;
     use fedimint_api::db::mem_impl::MemDatabase;
     use fedimint_api::db::Database;
     use fedimint_api::module::registry::ModuleDecoderRegistry;
-    use fedimint_api::task::TaskGroup;
-    use fedimint_api::{Feerate, OutPoint, TransactionId};
-    use fedimint_core::modules::smolfs::common::SmolFSDecoder;
-    use fedimint_core::modules::smolfs::config::SmolFSClientConfig;
-    use fedimint_core::modules::smolfs::{
-        SmolFS, SmolFSConfigGenParams, SmolFSConfigGenerator, SmolFSEntry, SmolFSOutput,
-        SmolFSOutputOutcome,
+    use fedimint_api::module::ModuleError;
+    use fedimint_api::{Amount, NumPeers, OutPoint, PeerId, TransactionId};
+    use fedimint_core::modules::smolfs::archive::{archived_entries, state_root};
+    use fedimint_core::modules::smolfs::blob::BlobStatus;
+    use fedimint_core::modules::smolfs::common::{write_fee, EntryMeta, SmolFSDecoder};
+    use fedimint_core::modules::smolfs::config::{SmolFSClientConfig, SmolFSConfig};
+    use fedimint_core::modules::smolfs::db::{
+        DbKeyPrefix, EntryKey, EntryVersionPrefix, PrepaidBalanceKey, SignedRootKey,
     };
-    use fedimint_core::modules::wallet::common::WalletDecoder;
-    use fedimint_core::modules::wallet::config::WalletClientConfig;
-    use fedimint_core::modules::wallet::{
-        PegOut, PegOutFees, Wallet, WalletGen, WalletGenParams, WalletOutput, WalletOutputOutcome,
+    use fedimint_core::modules::smolfs::public::{public_root, SignedRoot};
+    use fedimint_core::modules::smolfs::upload::upload_receipt_message;
+    use fedimint_core::modules::smolfs::{
+        SmolFS, SmolFSConfigGenParams, SmolFSConfigGenerator, SmolFSEntry, SmolFSError,
+        SmolFSInput, SmolFSOutput, SmolFSOutputOutcome, SmolFSPayload,
     };
-    use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
-    use fedimint_testing::btc::bitcoind::{FakeBitcoindRpc, FakeBitcoindRpcController};
+    use fedimint_core::transaction::agg_sign;
+    use fedimint_core::transaction::legacy::{Input, Transaction};
+    use fedimint_derive_secret::DerivableSecret;
     use fedimint_testing::FakeFed;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey};
     use tokio::sync::Mutex;
     use tracing::info;
 
     use crate::api::fake::FederationApiFaker;
     use crate::smolfs::SmolFSClient;
-    use crate::wallet::WalletClient;
-    use crate::{module_decode_stubs, ClientContext};
+    use crate::{module_decode_stubs, ClientContext, SMOLFS_SECRET_CHILD_ID};
 
     type Fed = FakeFed<SmolFS>;
-    type SharedFed = Arc<tokio::sync::Mutex<Fed>>;
 
-    #[derive(Debug)]
-    struct FakeApi {
-        // for later use once wallet outcomes are implemented
-        _mint: SharedFed,
+    const MODULE_ID: ModuleInstanceId = 3;
+    const WRITE_FEE_PER_KIB: Amount = Amount::from_msats(1000);
+    const TOMBSTONE_RETENTION_EPOCHS: u64 = 5;
+
+    /// Seed of the random operations, change it to explore other histories
+    const SIMULATION_SEED: u64 = 0x5eed;
+    const SIMULATION_EPOCHS: u64 = 50;
+    const SIMULATION_KEYS: usize = 12;
+    const SIMULATION_OWNERS: u8 = 5;
+
+    async fn new_fed(blob_root: &Path) -> Fed {
+        FakeFed::<SmolFS>::new(
+            4,
+            move |cfg, db| async move {
+                let mut cfg: SmolFSConfig = cfg.to_typed()?;
+                // Guardians sharing a blob store would delete blobs the others still
+                // reference
+                cfg.local.blob_dir = tempfile::tempdir_in(blob_root)?.into_path();
+                SmolFS::new(cfg, db)
+            },
+            &ConfigGenParams::new().attach(SmolFSConfigGenParams {
+                max_versions: 3,
+                tombstone_retention_epochs: TOMBSTONE_RETENTION_EPOCHS,
+                pow_difficulty: None,
+                write_fee_per_kib: WRITE_FEE_PER_KIB,
+                peer_api_urls: Default::default(),
+            }),
+            &SmolFSConfigGenerator,
+            MODULE_ID,
+        )
+        .await
+        .unwrap()
     }
 
-    pub async fn make_test_smolfs_fed(
-        module_id: ModuleInstanceId,
-        fed: Arc<Mutex<FakeFed<SmolFS>>>,
-    ) -> FederationApiFaker<tokio::sync::Mutex<FakeFed<SmolFS>>> {
-        let members = fed
-            .lock()
+    async fn new_fed_and_client(blob_root: &Path) -> (Arc<Mutex<Fed>>, SmolFSClient) {
+        let fed = Arc::new(Mutex::new(new_fed(blob_root).await));
+        let (members, client_config) = {
+            let fed = fed.lock().await;
+            let members = fed
+                .members
+                .iter()
+                .map(|(peer_id, _, _, _)| *peer_id)
+                .collect();
+            (
+                members,
+                fed.client_cfg_typed::<SmolFSClientConfig>().unwrap(),
+            )
+        };
+
+        let context = ClientContext {
+            decoders: ModuleDecoderRegistry::from_iter([(MODULE_ID, SmolFSDecoder.into())]),
+            module_gens: Default::default(),
+            db: Database::new(MemDatabase::new(), module_decode_stubs()),
+            api: FederationApiFaker::new(fed.clone(), members).into(),
+            secp: secp256k1_zkp::Secp256k1::new(),
+        };
+        let client = SmolFSClient {
+            config: client_config,
+            context: Arc::new(context),
+            secret: DerivableSecret::new_root(&[], &[]).child_key(SMOLFS_SECRET_CHILD_ID),
+        };
+        (fed, client)
+    }
+
+    fn owner_keypair(seed: u8) -> KeyPair {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32]).unwrap()
+    }
+
+    fn owner_key(seed: u8) -> XOnlyPublicKey {
+        owner_keypair(seed).x_only_public_key().0
+    }
+
+    /// All keys and values of the module's database guardians have to agree on,
+    /// leaving out tables that only describe the guardian's local blob store
+    async fn consensus_state(
+        db: &Database,
+        module_instance_id: ModuleInstanceId,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let local = [
+            DbKeyPrefix::DamagedBlob as u8,
+            DbKeyPrefix::RepairStatus as u8,
+            DbKeyPrefix::CapabilityReads as u8,
+            DbKeyPrefix::CatchUpStatus as u8,
+            DbKeyPrefix::UploadedBlob as u8,
+        ];
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+        let state = module_dbtx
+            .raw_find_by_prefix(&[])
             .await
-            .members
-            .iter()
-            .map(|(peer_id, _, _, _)| *peer_id)
+            .map(|res| res.expect("DB Error"))
+            .filter(|(key, _)| !local.contains(&key[0]))
             .collect();
-        FederationApiFaker::new(fed, members)
+        state
     }
 
-    async fn new_mint_and_client(
-        task_group: &mut TaskGroup,
-    ) -> (
-        Arc<tokio::sync::Mutex<Fed>>,
-        SmolFSClientConfig,
-        ClientContext,
-        //     FakeBitcoindRpcController,
-    ) {
-        let module_id = 3;
-        let fed = Arc::new(tokio::sync::Mutex::new(
-            FakeFed::<SmolFS>::new(
-                4,
-                move |cfg, db| async move { Ok(SmolFS::new(cfg.to_typed().unwrap()).await) },
-                &ConfigGenParams::new().attach(SmolFSConfigGenParams {
-                    important_param: 10,
-                }),
-                &SmolFSConfigGenerator,
-                module_id,
-            )
+    /// State root over all entries and root of the public entries
+    async fn roots(
+        db: &Database,
+        module_instance_id: ModuleInstanceId,
+    ) -> (sha256::Hash, sha256::Hash) {
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+        let entries = archived_entries(&mut module_dbtx).await;
+        let public_root = public_root(&mut module_dbtx).await;
+        (state_root(&entries), public_root)
+    }
+
+    async fn signed_root(
+        db: &Database,
+        module_instance_id: ModuleInstanceId,
+    ) -> Option<SignedRoot> {
+        let mut dbtx = db.begin_transaction().await;
+        let signed = dbtx
+            .with_module_prefix(module_instance_id)
+            .get_value(&SignedRootKey)
             .await
-            .unwrap(),
-        ));
+            .expect("DB Error");
+        signed
+    }
 
-        let api = make_test_smolfs_fed(module_id, fed.clone()).await;
-        let client_config = fed.lock().await.client_cfg().clone();
+    async fn entry_and_balance(
+        db: &Database,
+        module_instance_id: ModuleInstanceId,
+        key: &str,
+        owner: XOnlyPublicKey,
+    ) -> (Option<EntryMeta>, Option<Amount>) {
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+        let entry = module_dbtx
+            .get_value(&EntryKey(key.to_string()))
+            .await
+            .expect("DB Error");
+        let balance = module_dbtx
+            .get_value(&PrepaidBalanceKey(owner))
+            .await
+            .expect("DB Error");
+        (entry, balance)
+    }
 
-        let client = ClientContext {
-            decoders: ModuleDecoderRegistry::from_iter([(module_id, SmolFSDecoder.into())]),
-            module_gens: Default::default(),
-            db: Database::new(MemDatabase::new(), module_decode_stubs()),
-            api: api.into(),
-            secp: secp256k1_zkp::Secp256k1::new(),
+    async fn retained_versions(
+        db: &Database,
+        module_instance_id: ModuleInstanceId,
+        key: &str,
+    ) -> Vec<u64> {
+        let mut dbtx = db.begin_transaction().await;
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+        let versions = module_dbtx
+            .find_by_prefix(&EntryVersionPrefix(key.to_string()))
+            .await
+            .map(|res| res.expect("DB Error").0.version)
+            .collect();
+        versions
+    }
+
+    /// Stores `bytes` on every guardian like an upload and signs receipts for it in their name
+    fn uploaded_payload(fed: &Fed, bytes: &[u8]) -> SmolFSPayload {
+        let secp = Secp256k1::new();
+        let hash = sha256::Hash::hash(bytes);
+        let message = upload_receipt_message(&hash, bytes.len() as u64);
+        let receipts = fed
+            .members
+            .iter()
+            .map(|(peer, smolfs, _, _)| {
+                smolfs.blobs.put(bytes).unwrap();
+                let key = KeyPair::from_secret_key(&secp, &smolfs.cfg.private.root_key);
+                (*peer, secp.sign_schnorr(&message, &key))
+            })
+            .collect();
+        SmolFSPayload::Uploaded {
+            hash,
+            size: bytes.len() as u64,
+            receipts,
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn prepayment_pays_for_writes() {
+        let blob_root = tempfile::tempdir().unwrap();
+        let (fed, client) = new_fed_and_client(blob_root.path()).await;
+        let mut fed = fed.lock().await;
+        let owner = owner_key(1);
+
+        let out_point = OutPoint {
+            txid: TransactionId::from_inner([42; 32]),
+            out_idx: 0,
+        };
+        let output = SmolFSOutput {
+            owner,
+            amount: Amount::from_msats(5000),
         };
+        assert_eq!(client.output_amount(&output).amount, output.amount);
+        fed.consensus_round(&[], &[(out_point, output)]).await;
+        assert_eq!(
+            fed.output_outcome(out_point).await,
+            Some(SmolFSOutputOutcome)
+        );
 
-        (
-            fed,
-            client_config.cast().unwrap(),
-            client,
-            // btc_rpc_controller,
-        )
+        let payload = SmolFSPayload::Inline(b"42".to_vec());
+        let fee = client.write_fee(&payload);
+        let input = SmolFSInput(Box::new(SmolFSEntry {
+            pubkey: "backup".to_string(),
+            payload,
+            timestamp: UNIX_EPOCH,
+            pow_nonce: 0,
+            owner: Some(owner),
+            prepaid: fee,
+            public: false,
+            expected_version: None,
+        }));
+        let meta = fed.verify_input(&input).await.unwrap();
+        assert_eq!(meta.amount, client.input_amount(&input));
+        assert_eq!(meta.keys, vec![owner]);
+
+        fed.consensus_round(&[input], &[]).await;
+        let (entry, balance) = fed
+            .fetch_from_all(|_, db, module_instance_id| {
+                entry_and_balance(db, *module_instance_id, "backup", owner)
+            })
+            .await;
+        assert_eq!(entry.unwrap().hash, sha256::Hash::hash(b"42"));
+        assert_eq!(balance, Some(Amount::from_msats(5000) - fee));
     }
 
     #[test_log::test(tokio::test)]
-    async fn create_output_for_smolfs() {
-        let mut task_group = TaskGroup::new();
-        let (fed, client_config, client_context) = new_mint_and_client(&mut task_group).await;
-        let _client = SmolFSClient {
-            config: client_config,
-            context: Arc::new(client_context),
+    async fn deleted_entries_can_be_restored_until_purged() {
+        let blob_root = tempfile::tempdir().unwrap();
+        let mut fed = new_fed(blob_root.path()).await;
+        let owner = owner_key(1);
+        let bytes = b"restore me".to_vec();
+        let entry = |payload| {
+            SmolFSInput(Box::new(SmolFSEntry {
+                pubkey: "deleted".to_string(),
+                payload,
+                timestamp: UNIX_EPOCH,
+                pow_nonce: 0,
+                owner: Some(owner),
+                prepaid: Amount::ZERO,
+                public: false,
+                expected_version: None,
+            }))
         };
-        info!("create_output");
-
-        let pubkey = "pubkey".to_string();
-        let backup = "backup".to_string();
-        // _client.add_entry(dbtx, pubkey, backup)A
-        // let outpoint = OutPoint{txid:fedimint_api::TransactionId::from_slice([0;32].as_slice()).unwrap(), out_idx: 1 };
-        let outpoint = OutPoint {
-            txid: sha256::Hash::hash(pubkey.as_bytes()).into(),
-            out_idx: 0,
+        let restore = entry(SmolFSPayload::Uploaded {
+            hash: sha256::Hash::hash(&bytes),
+            size: bytes.len() as u64,
+            receipts: BTreeMap::new(),
+        });
+
+        fed.consensus_round(&[entry(SmolFSPayload::Inline(bytes.clone()))], &[])
+            .await;
+        fed.consensus_round(&[entry(SmolFSPayload::Delete)], &[])
+            .await;
+        fed.verify_input(&restore).await.unwrap();
+        for _ in 0..TOMBSTONE_RETENTION_EPOCHS {
+            fed.consensus_round(&[], &[]).await;
+            fed.verify_input(&restore).await.unwrap();
+        }
+        // All guardians drop the retained versions in the same epoch
+        fed.consensus_round(&[], &[]).await;
+        assert!(fed.verify_input(&restore).await.is_err());
+        fed.fetch_from_all(|_, db, module_instance_id| consensus_state(db, *module_instance_id))
+            .await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn expired_versions_are_collected_after_their_epoch() {
+        let blob_root = tempfile::tempdir().unwrap();
+        let mut fed = new_fed(blob_root.path()).await;
+        let owner = owner_key(1);
+        let entry = |payload| {
+            SmolFSInput(Box::new(SmolFSEntry {
+                pubkey: "versioned".to_string(),
+                payload,
+                timestamp: UNIX_EPOCH,
+                pow_nonce: 0,
+                owner: Some(owner),
+                prepaid: Amount::ZERO,
+                public: false,
+                expected_version: None,
+            }))
         };
-        fed.lock().await.generate_fake_smolfs_entry(outpoint).await;
-        fed.lock().await.get_fake_smolfs_entry().await;
-        fed.lock().await.consensus_round(&[], &[]).await;
-        let output = SmolFSOutput(Box::new(SmolFSEntry { pubkey, backup }));
-        let outputs = [(outpoint, output)];
-        println!("consensus with outputs");
-        fed.lock().await.consensus_round(&[], &outputs).await;
-        println!("empty consensus");
-        fed.lock().await.consensus_round(&[], &[]).await;
-        let output_outcome = fed.lock().await.output_outcome(outpoint).await;
-        println!("{:?}", output_outcome);
-        let backup = fed
-            .lock()
-            .await
-            .fetch_from_all(|m, db, module_instance_id| async {
-                m.get_backups(
-                    &mut db
-                        .begin_transaction()
-                        .await
-                        .with_module_prefix(*module_instance_id),
-                    "pubkey".to_string(),
-                )
-                .await
+        let first = b"first version".to_vec();
+        let first_hash = sha256::Hash::hash(&first);
+        let restore_first = entry(SmolFSPayload::Uploaded {
+            hash: first_hash,
+            size: first.len() as u64,
+            receipts: BTreeMap::new(),
+        });
+
+        let payload = uploaded_payload(&fed, &first);
+        fed.consensus_round(&[entry(payload)], &[]).await;
+        for idx in 1..3 {
+            fed.consensus_round(&[entry(SmolFSPayload::Inline(vec![idx]))], &[])
+                .await;
+        }
+        fed.verify_input(&restore_first).await.unwrap();
+
+        // Only the configured three versions are retained
+        fed.consensus_round(&[entry(SmolFSPayload::Inline(vec![3]))], &[])
+            .await;
+        let versions = fed
+            .fetch_from_all(|_, db, module_instance_id| {
+                retained_versions(db, *module_instance_id, "versioned")
             })
             .await;
-        assert_eq!("backup", backup)
+        assert_eq!(versions, vec![1, 2, 3]);
+        assert!(fed.verify_input(&restore_first).await.is_err());
+
+        // The epoch that dropped the last reference could still have been rolled back, so the
+        // blob is only deleted once the next epoch starts
+        for (_, smolfs, _, _) in &fed.members {
+            assert_eq!(smolfs.blobs.check(&first_hash).unwrap(), BlobStatus::Ok);
+        }
+        fed.consensus_round(&[], &[]).await;
+        for (_, smolfs, _, _) in &fed.members {
+            assert_eq!(
+                smolfs.blobs.check(&first_hash).unwrap(),
+                BlobStatus::Missing
+            );
+        }
+    }
+
+    /// Runs random writes, deletes, renewals and prepayments through consensus
+    /// while dropping and reordering consensus items, checking after every epoch
+    /// that all guardians still agree on their state
+    ///
+    /// Renewals write a payload the entry had before by reference, which is only
+    /// accepted while the entry still retains that version. Writes are signed like
+    /// transactions, some of them by a key other than their owner's.
+    #[test_log::test(tokio::test)]
+    async fn guardians_agree_under_random_operations() {
+        let blob_root = tempfile::tempdir().unwrap();
+        let mut fed = new_fed(blob_root.path()).await;
+        let mut rng = StdRng::seed_from_u64(SIMULATION_SEED);
+        let secp = secp256k1_zkp::Secp256k1::new();
+
+        let owners = (1..=SIMULATION_OWNERS)
+            .map(owner_keypair)
+            .collect::<Vec<_>>();
+        let keys = (0..SIMULATION_KEYS)
+            .map(|idx| format!("simulation/{idx}"))
+            .collect::<Vec<_>>();
+        // Few distinct payloads, so versions of different entries share blobs
+        let payloads = (0..6u8)
+            .map(|idx| vec![idx; 700 * usize::from(idx) + 1])
+            .collect::<Vec<_>>();
+
+        // Payloads written to every key, which renewals pick from
+        let mut written: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+        let mut out_idx = 0;
+        let (mut accepted, mut rejected, mut renewed) = (0, 0, 0);
+        let (mut wrong_owner, mut invalid_signature) = (0, 0);
+        for epoch in 0..SIMULATION_EPOCHS {
+            let mut inputs = vec![];
+            let mut outputs = vec![];
+            // Inputs of one epoch must not depend on each other, otherwise checking
+            // them against the state before the epoch isn't meaningful
+            let mut used_keys = BTreeSet::new();
+            let mut used_owners = BTreeSet::new();
+
+            for _ in 0..rng.gen_range(0..8) {
+                let owner_keypair = rng
+                    .gen_ratio(4, 5)
+                    .then(|| *owners.choose(&mut rng).unwrap());
+                let owner = owner_keypair.map(|keypair| keypair.x_only_public_key().0);
+
+                if let (Some(owner), true) = (owner, rng.gen_ratio(1, 4)) {
+                    outputs.push((
+                        OutPoint {
+                            txid: TransactionId::from_inner([0; 32]),
+                            out_idx,
+                        },
+                        SmolFSOutput {
+                            owner,
+                            amount: Amount::from_msats(rng.gen_range(1..5) * 1000),
+                        },
+                    ));
+                    out_idx += 1;
+                    continue;
+                }
+
+                let key = keys.choose(&mut rng).unwrap().clone();
+                if !used_keys.insert(key.clone())
+                    || owner.map_or(false, |owner| !used_owners.insert(owner))
+                {
+                    continue;
+                }
+                let (payload, renewal) = match rng.gen_range(0..4) {
+                    0 => (SmolFSPayload::Delete, false),
+                    1 => {
+                        let idx = written
+                            .get(&key)
+                            .and_then(|written| written.choose(&mut rng))
+                            .copied()
+                            .unwrap_or_else(|| rng.gen_range(0..payloads.len()));
+                        let payload = SmolFSPayload::Uploaded {
+                            hash: sha256::Hash::hash(&payloads[idx]),
+                            size: payloads[idx].len() as u64,
+                            receipts: BTreeMap::new(),
+                        };
+                        (payload, true)
+                    }
+                    _ => {
+                        let idx = rng.gen_range(0..payloads.len());
+                        written.entry(key.clone()).or_default().push(idx);
+                        (SmolFSPayload::Inline(payloads[idx].clone()), false)
+                    }
+                };
+                let prepaid = match owner {
+                    Some(_) if rng.gen() => write_fee(WRITE_FEE_PER_KIB, payload.size()),
+                    _ => Amount::ZERO,
+                };
+                let input = SmolFSInput(Box::new(SmolFSEntry {
+                    pubkey: key,
+                    payload,
+                    timestamp: UNIX_EPOCH + Duration::from_secs(epoch),
+                    pow_nonce: 0,
+                    owner,
+                    prepaid,
+                    public: rng.gen(),
+                    expected_version: None,
+                }));
+
+                // Most writes are signed by their owner, the others by an unrelated key
+                let signer = match owner_keypair {
+                    Some(keypair) if rng.gen_ratio(7, 8) => keypair,
+                    _ => KeyPair::new(&secp, &mut rng),
+                };
+                let mut tx = Transaction {
+                    inputs: vec![Input::SmolFS(input.clone())],
+                    outputs: vec![],
+                    signature: None,
+                };
+                tx.signature = Some(agg_sign(&[signer], tx.tx_hash().as_hash(), &secp, &mut rng));
+
+                // Writes without an owner or to entries of other owners, writes not
+                // signed by their owner, deletes of missing entries, renewals of
+                // versions that are no longer retained and overdrawn balances have to
+                // be rejected by all guardians alike
+                match fed.verify_input(&input).await {
+                    Ok(meta) if tx.validate_signature(meta.keys.iter().copied()).is_ok() => {
+                        accepted += 1;
+                        renewed += u32::from(renewal);
+                        inputs.push(input);
+                    }
+                    Ok(_) => {
+                        rejected += 1;
+                        invalid_signature += 1;
+                    }
+                    Err(ModuleError::Other(err)) => {
+                        rejected += 1;
+                        let error = err.downcast_ref::<SmolFSError>();
+                        wrong_owner +=
+                            u32::from(matches!(error, Some(SmolFSError::NotEntryOwner(_))));
+                    }
+                }
+            }
+
+            fed.consensus_round_with_items(&inputs, &outputs, |items| {
+                items.shuffle(&mut rng);
+                items.retain(|_| rng.gen_ratio(2, 3));
+            })
+            .await;
+
+            fed.fetch_from_all(|_, db, module_instance_id| {
+                consensus_state(db, *module_instance_id)
+            })
+            .await;
+            let (state_root, public_root) = fed
+                .fetch_from_all(|_, db, module_instance_id| roots(db, *module_instance_id))
+                .await;
+            info!(%epoch, %state_root, %public_root, "Guardians agree");
+        }
+        assert!(
+            accepted > 0 && rejected > 0,
+            "Simulation didn't exercise both accepted and rejected writes"
+        );
+        assert!(renewed > 0, "Simulation didn't exercise renewals");
+        assert!(
+            wrong_owner > 0 && invalid_signature > 0,
+            "Simulation didn't exercise writes to other owners' entries and invalid signatures"
+        );
+
+        // Once no items are dropped every guardian signs the current public root
+        fed.consensus_round(&[], &[]).await;
+        let (_, public_root) = fed
+            .fetch_from_all(|_, db, module_instance_id| roots(db, *module_instance_id))
+            .await;
+        let signed = fed
+            .fetch_from_all(|_, db, module_instance_id| signed_root(db, *module_instance_id))
+            .await
+            .expect("Public root wasn't signed");
+        let root_keys = &fed.members[0].1.cfg.consensus.root_keys;
+        let peers = root_keys.keys().copied().collect::<BTreeSet<PeerId>>();
+        assert_eq!(signed.root, public_root);
+        assert!(signed.verify(
+            &Secp256k1::verification_only(),
+            root_keys,
+            peers.threshold()
+        ));
     }
 }
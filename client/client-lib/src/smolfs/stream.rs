@@ -0,0 +1,278 @@
+use std::collections::BTreeMap;
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_api::{NumPeers, PeerId};
+use fedimint_core::modules::smolfs::common::{EntryMeta, FetchVersionRequest};
+use fedimint_core::modules::smolfs::upload::{
+    ReadRequest, UploadFinalizeRequest, UploadPushRequest, UploadStartRequest, MAX_CHUNK_SIZE,
+};
+use fedimint_core::modules::smolfs::SmolFSPayload;
+use secp256k1::Secp256k1;
+use tracing::warn;
+
+use super::{Result, SmolFSClient, SmolFSClientError};
+use crate::api::SmolFSFederationApi;
+use crate::utils::now;
+
+/// Size of the ranges pushed to and read from the guardians
+const TRANSFER_CHUNK_SIZE: usize = MAX_CHUNK_SIZE as usize;
+
+/// Streams a payload to all guardians in chunks, created by [`SmolFSClient::upload`]
+///
+/// Guardians that fail during the upload are dropped, they will repair the payload from their
+/// peers once it is committed. The upload fails if fewer than a threshold of guardians are left.
+///
+/// The guardians only accept uploads the prepaid balance of our owner key pays for, see
+/// [`SmolFSClient::owner_key`].
+pub struct SmolFSUpload<'a> {
+    client: &'a SmolFSClient,
+    sessions: BTreeMap<PeerId, u64>,
+    size: u64,
+    offset: u64,
+    buffer: Vec<u8>,
+    engine: sha256::HashEngine,
+}
+
+impl<'a> SmolFSUpload<'a> {
+    pub(crate) async fn start(client: &'a SmolFSClient, size: u64) -> Result<SmolFSUpload<'a>> {
+        let request =
+            UploadStartRequest::new(&Secp256k1::signing_only(), &client.owner_key(), size, now());
+        let mut sessions = BTreeMap::new();
+        for peer in client.context.api.all_members().clone() {
+            match client.context.api.smolfs_upload_start(peer, &request).await {
+                Ok(session) => {
+                    sessions.insert(peer, session);
+                }
+                Err(e) => warn!(%peer, "Failed to start upload: {}", e),
+            }
+        }
+
+        let upload = SmolFSUpload {
+            client,
+            sessions,
+            size,
+            offset: 0,
+            buffer: vec![],
+            engine: sha256::Hash::engine(),
+        };
+        upload.ensure_threshold()?;
+        Ok(upload)
+    }
+
+    /// Writes all of `buf`, pushing full chunks to the guardians as they fill up
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        if self.offset + (self.buffer.len() + buf.len()) as u64 > self.size {
+            return Err(SmolFSClientError::SizeMismatch);
+        }
+        self.engine.input(buf);
+        self.buffer.extend_from_slice(buf);
+
+        while self.buffer.len() >= TRANSFER_CHUNK_SIZE {
+            let chunk = self.buffer.drain(..TRANSFER_CHUNK_SIZE).collect();
+            self.push(chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// Pushes the remaining buffered bytes and commits the upload on all guardians
+    ///
+    /// The returned payload still has to be written to an entry using
+    /// [`crate::Client::smolfs_put`].
+    pub async fn finish(mut self) -> Result<SmolFSPayload> {
+        if !self.buffer.is_empty() {
+            let chunk = std::mem::take(&mut self.buffer);
+            self.push(chunk).await?;
+        }
+        if self.offset != self.size {
+            return Err(SmolFSClientError::SizeMismatch);
+        }
+
+        let hash = sha256::Hash::from_engine(self.engine.clone());
+        let mut receipts = BTreeMap::new();
+        for (peer, session) in self.sessions.clone() {
+            let request = UploadFinalizeRequest { session, hash };
+            match self
+                .client
+                .context
+                .api
+                .smolfs_upload_finalize(peer, &request)
+                .await
+            {
+                Ok(receipt) => {
+                    receipts.insert(peer, receipt);
+                }
+                Err(e) => {
+                    warn!(%peer, "Failed to finalize upload: {}", e);
+                    self.sessions.remove(&peer);
+                }
+            }
+        }
+        self.ensure_threshold()?;
+
+        Ok(SmolFSPayload::Uploaded {
+            hash,
+            size: self.size,
+            receipts,
+        })
+    }
+
+    async fn push(&mut self, bytes: Vec<u8>) -> Result<()> {
+        for (peer, session) in self.sessions.clone() {
+            let request = UploadPushRequest {
+                session,
+                offset: self.offset,
+                bytes: bytes.clone(),
+            };
+            if let Err(e) = self
+                .client
+                .context
+                .api
+                .smolfs_upload_push(peer, &request)
+                .await
+            {
+                warn!(%peer, "Failed to push upload chunk: {}", e);
+                self.sessions.remove(&peer);
+            }
+        }
+        self.offset += bytes.len() as u64;
+        self.ensure_threshold()
+    }
+
+    fn ensure_threshold(&self) -> Result<()> {
+        if self.sessions.len() < self.client.context.api.all_members().threshold() {
+            return Err(SmolFSClientError::NotEnoughGuardians);
+        }
+        Ok(())
+    }
+}
+
+/// Reads an entry from the federation in chunks, created by [`SmolFSClient::download`] or
+/// [`SmolFSClient::download_version`]
+///
+/// The payload is verified against the hash the federation agreed on once it was read
+/// completely. Private entries can only be read if they are ours.
+pub struct SmolFSDownload<'a> {
+    client: &'a SmolFSClient,
+    key: String,
+    meta: EntryMeta,
+    /// Whether a retained version is read instead of the current payload
+    retained: bool,
+    offset: u64,
+    buffer: Vec<u8>,
+    engine: sha256::HashEngine,
+}
+
+impl<'a> SmolFSDownload<'a> {
+    pub(crate) async fn start(
+        client: &'a SmolFSClient,
+        key: String,
+    ) -> Result<Option<SmolFSDownload<'a>>> {
+        let Some(meta) = client.context.api.fetch_entry_meta(key.clone()).await? else {
+            return Ok(None);
+        };
+        Ok(Some(SmolFSDownload {
+            client,
+            key,
+            meta,
+            retained: false,
+            offset: 0,
+            buffer: vec![],
+            engine: sha256::Hash::engine(),
+        }))
+    }
+
+    pub(crate) async fn start_version(
+        client: &'a SmolFSClient,
+        key: String,
+        version: u64,
+    ) -> Result<Option<SmolFSDownload<'a>>> {
+        let Some(retained) = client.versions(key.clone()).await?.remove(&version) else {
+            return Ok(None);
+        };
+        Ok(Some(SmolFSDownload {
+            client,
+            key,
+            meta: EntryMeta {
+                hash: retained.hash,
+                size: retained.size,
+                version,
+            },
+            retained: true,
+            offset: 0,
+            buffer: vec![],
+            engine: sha256::Hash::engine(),
+        }))
+    }
+
+    /// Total size of the payload in bytes
+    pub fn size(&self) -> u64 {
+        self.meta.size
+    }
+
+    /// Version of the entry being read, the download fails if it changes midway
+    pub fn version(&self) -> u64 {
+        self.meta.version
+    }
+
+    /// Reads the next bytes into `buf`, returning how many were read, `0` means the end of the
+    /// payload was reached
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.buffer.is_empty() && self.offset < self.meta.size {
+            self.fetch_next_chunk().await?;
+        }
+
+        let len = std::cmp::min(buf.len(), self.buffer.len());
+        buf[..len].copy_from_slice(&self.buffer[..len]);
+        self.buffer.drain(..len);
+        Ok(len)
+    }
+
+    /// Reads the remaining payload into memory
+    pub async fn read_to_end(mut self) -> Result<Vec<u8>> {
+        let mut bytes = std::mem::take(&mut self.buffer);
+        while self.offset < self.meta.size {
+            self.fetch_next_chunk().await?;
+            bytes.append(&mut self.buffer);
+        }
+        Ok(bytes)
+    }
+
+    async fn fetch_next_chunk(&mut self) -> Result<()> {
+        let len = std::cmp::min(TRANSFER_CHUNK_SIZE as u64, self.meta.size - self.offset);
+        let capability = Some(self.client.owner_read_capability(self.key.clone()));
+        let api = &self.client.context.api;
+        let response = if self.retained {
+            let request = FetchVersionRequest {
+                key: self.key.clone(),
+                version: self.meta.version,
+                offset: self.offset,
+                len,
+                capability,
+            };
+            api.fetch_entry_version(request).await?
+        } else {
+            let request = ReadRequest {
+                key: self.key.clone(),
+                offset: self.offset,
+                len,
+                capability,
+            };
+            api.read_entry_range(request).await?
+        };
+        let chunk = response.ok_or(SmolFSClientError::EntryChanged)?.bytes;
+        if chunk.is_empty() {
+            return Err(SmolFSClientError::EntryChanged);
+        }
+
+        self.engine.input(&chunk);
+        self.offset += chunk.len() as u64;
+        self.buffer = chunk;
+
+        if self.offset >= self.meta.size
+            && sha256::Hash::from_engine(self.engine.clone()) != self.meta.hash
+        {
+            return Err(SmolFSClientError::EntryChanged);
+        }
+        Ok(())
+    }
+}
@@ -35,6 +35,7 @@ impl Final for OutputOutcome {
                 ContractOutcome::Incoming(_) => false,
                 ContractOutcome::Outgoing(_) => true,
             },
+            OutputOutcome::SmolFS(_) => true,
         }
     }
 }
@@ -137,6 +138,7 @@ impl TransactionBuilder {
             Input::Mint(input) => client.mint_client().input_amount(input),
             Input::Wallet(input) => client.wallet_client().input_amount(input),
             Input::LN(input) => client.ln_client().input_amount(input),
+            Input::SmolFS(input) => client.smolfs_client().input_amount(input),
         })
     }
 
@@ -151,6 +153,7 @@ impl TransactionBuilder {
             Output::Mint(output) => client.mint_client().output_amount(output),
             Output::Wallet(output) => client.wallet_client().output_amount(output),
             Output::LN(output) => client.ln_client().output_amount(output),
+            Output::SmolFS(output) => client.smolfs_client().output_amount(output),
         })
     }
 
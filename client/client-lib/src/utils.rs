@@ -1,4 +1,7 @@
 use std::str::FromStr;
+#[cfg(target_family = "wasm")]
+use std::time::Duration;
+use std::time::SystemTime;
 
 use bitcoin::{secp256k1, Network};
 use bitcoin_hashes::hex::FromHex;
@@ -12,6 +15,16 @@ use lightning_invoice::Currency;
 use crate::api::DynFederationApi;
 use crate::mint::SpendableNote;
 
+/// Current wall clock time, [`SystemTime::now`] panics in the browser so we ask JS there
+pub fn now() -> SystemTime {
+    #[cfg(not(target_family = "wasm"))]
+    let now = SystemTime::now();
+    #[cfg(target_family = "wasm")]
+    let now =
+        SystemTime::UNIX_EPOCH + Duration::from_secs_f64(js_sys::Date::new_0().get_time() / 1000.);
+    now
+}
+
 pub fn parse_ecash(s: &str) -> anyhow::Result<TieredMulti<SpendableNote>> {
     let bytes = base64::decode(s)?;
     Ok(Decodable::consensus_decode(
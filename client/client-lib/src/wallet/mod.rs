@@ -2,9 +2,10 @@ use std::sync::Arc;
 
 use bitcoin::Address;
 use bitcoin::KeyPair;
-use db::PegInKey;
+use db::{PegInKey, PegInPrefixKey};
 use fedimint_api::core::client::ClientModule;
 use fedimint_api::db::DatabaseTransaction;
+use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::module::TransactionItemAmount;
 use fedimint_api::{Amount, ServerModule};
 use fedimint_core::modules::wallet::common::WalletDecoder;
@@ -18,6 +19,7 @@ use tracing::debug;
 
 use crate::api::GlobalFederationApi;
 use crate::api::OutputOutcomeError;
+use crate::backup::insert_missing;
 use crate::utils::ClientContext;
 use crate::MemberError;
 
@@ -171,6 +173,42 @@ impl WalletClient {
     }
 }
 
+impl WalletClient {
+    /// Collects the peg-in tweaks we need to claim peg-ins on another device
+    pub async fn prepare_backup(&self) -> WalletClientBackup {
+        let peg_ins = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&PegInPrefixKey)
+            .await
+            .map(|res| res.expect("DB error"))
+            .map(|(key, tweak_secret)| (key.peg_in_script, tweak_secret))
+            .collect();
+        WalletClientBackup { peg_ins }
+    }
+
+    /// Adds the peg-in tweaks from `backup` that are missing from the database, see
+    /// [`crate::backup`]
+    pub async fn restore_backup(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        backup: WalletClientBackup,
+    ) {
+        for (peg_in_script, tweak_secret) in backup.peg_ins {
+            insert_missing(dbtx, &PegInKey { peg_in_script }, &tweak_secret).await;
+        }
+    }
+}
+
+/// Wallet part of a [`crate::backup::ClientBackup`]
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct WalletClientBackup {
+    /// Peg-in scripts with the secret keys of their tweaks
+    pub peg_ins: Vec<(bitcoin::Script, [u8; 32])>,
+}
+
 type Result<T> = std::result::Result<T, WalletClientError>;
 
 #[derive(Error, Debug)]
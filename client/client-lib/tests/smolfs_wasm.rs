@@ -0,0 +1,165 @@
+//! Runs the smolfs client in a headless browser against a faked federation API, see
+//! `scripts/wasm-tests.sh`
+
+#![cfg(target_family = "wasm")]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+use fedimint_api::db::mem_impl::MemDatabase;
+use fedimint_api::db::Database;
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_api::{Amount, PeerId};
+use fedimint_core::modules::smolfs::common::{BlobResponse, EntryMeta, SmolFSDecoder};
+use fedimint_core::modules::smolfs::config::SmolFSClientConfig;
+use fedimint_core::modules::smolfs::upload::{
+    upload_receipt_message, ReadRequest, UploadFinalizeRequest, UploadPushRequest,
+    UploadStartRequest,
+};
+use fedimint_core::modules::smolfs::SmolFSPayload;
+use fedimint_derive_secret::DerivableSecret;
+use mint_client::api::fake::FederationApiFaker;
+use mint_client::smolfs::SmolFSClient;
+use mint_client::utils::ClientContext;
+use mint_client::{module_decode_stubs, SMOLFS_SECRET_CHILD_ID};
+use secp256k1::{KeyPair, Secp256k1};
+use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+/// What the faked guardians store, shared by all of them
+#[derive(Debug, Default)]
+struct FakeStorage {
+    next_session: u64,
+    /// Bytes received so far by upload session
+    uploads: BTreeMap<u64, Vec<u8>>,
+    /// Finalized uploads by hash
+    blobs: BTreeMap<sha256::Hash, Vec<u8>>,
+    /// Hash of the payload of every entry, written by the test in place of a transaction
+    entries: BTreeMap<String, sha256::Hash>,
+}
+
+type State = Mutex<FakeStorage>;
+
+fn endpoint(name: &str) -> String {
+    format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_SMOLFS}/{name}")
+}
+
+fn fake_api(state: Arc<State>) -> FederationApiFaker<State> {
+    let members = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+    FederationApiFaker::new(state, members)
+        .with(
+            endpoint("upload_start"),
+            |state: Arc<State>, _request: UploadStartRequest| async move {
+                let mut storage = state.lock().unwrap();
+                let session = storage.next_session;
+                storage.next_session += 1;
+                storage.uploads.insert(session, vec![]);
+                Ok(session)
+            },
+        )
+        .with(
+            endpoint("upload_push"),
+            |state: Arc<State>, request: UploadPushRequest| async move {
+                let mut storage = state.lock().unwrap();
+                let upload = storage.uploads.get_mut(&request.session).unwrap();
+                assert_eq!(upload.len() as u64, request.offset);
+                upload.extend_from_slice(&request.bytes);
+                Ok(upload.len() as u64)
+            },
+        )
+        .with(
+            endpoint("upload_finalize"),
+            |state: Arc<State>, request: UploadFinalizeRequest| async move {
+                let mut storage = state.lock().unwrap();
+                let bytes = storage.uploads.remove(&request.session).unwrap();
+                assert_eq!(sha256::Hash::hash(&bytes), request.hash);
+                let receipt = upload_receipt_message(&request.hash, bytes.len() as u64);
+                storage.blobs.insert(request.hash, bytes);
+                // The client only collects receipts, they are checked by the real guardians
+                let secp = Secp256k1::signing_only();
+                let key = KeyPair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+                Ok(secp.sign_schnorr(&receipt, &key))
+            },
+        )
+        .with(
+            endpoint("entry_meta"),
+            |state: Arc<State>, key: String| async move {
+                let storage = state.lock().unwrap();
+                Ok(storage.entries.get(&key).map(|hash| EntryMeta {
+                    hash: *hash,
+                    size: storage.blobs[hash].len() as u64,
+                    version: 0,
+                }))
+            },
+        )
+        .with(
+            endpoint("read"),
+            |state: Arc<State>, request: ReadRequest| async move {
+                let storage = state.lock().unwrap();
+                Ok(storage.entries.get(&request.key).map(|hash| {
+                    let bytes = &storage.blobs[hash];
+                    let start = request.offset as usize;
+                    let end = std::cmp::min(bytes.len(), start + request.len as usize);
+                    BlobResponse {
+                        bytes: bytes[start..end].to_vec(),
+                    }
+                }))
+            },
+        )
+}
+
+fn new_client(state: Arc<State>) -> SmolFSClient {
+    let context = ClientContext {
+        decoders: ModuleDecoderRegistry::from_iter([(
+            LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+            SmolFSDecoder.into(),
+        )]),
+        module_gens: Default::default(),
+        db: Database::new(MemDatabase::new(), module_decode_stubs()),
+        api: fake_api(state).into(),
+        secp: secp256k1_zkp::Secp256k1::new(),
+    };
+    SmolFSClient {
+        config: SmolFSClientConfig {
+            merkle_root: vec![],
+            max_versions: 3,
+            pow_difficulty: None,
+            write_fee_per_kib: Amount::from_msats(1000),
+            root_keys: BTreeMap::new(),
+        },
+        context: Arc::new(context),
+        secret: DerivableSecret::new_root(&[], &[]).child_key(SMOLFS_SECRET_CHILD_ID),
+    }
+}
+
+#[wasm_bindgen_test]
+async fn write_and_read_backup_in_browser() {
+    let state = Arc::new(State::default());
+    let client = new_client(state.clone());
+    let backup = (0..200_000u32)
+        .flat_map(|i| i.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    let encrypted = client.encrypt("backup", &backup).unwrap();
+    let mut upload = client.upload(encrypted.len() as u64).await.unwrap();
+    upload.write_all(&encrypted).await.unwrap();
+    let SmolFSPayload::Uploaded { hash, .. } = upload.finish().await.unwrap() else {
+        panic!("Streamed uploads are referenced by hash");
+    };
+
+    // The transaction writing the entry would be submitted by the full client
+    state
+        .lock()
+        .unwrap()
+        .entries
+        .insert("backup".to_string(), hash);
+
+    assert_eq!(
+        client.read_file("backup".to_string()).await.unwrap(),
+        Some(backup)
+    );
+    assert_eq!(client.read_file("missing".to_string()).await.unwrap(), None);
+}
@@ -74,12 +74,16 @@ impl DerivableSecret {
     pub fn to_chacha20_poly1305_key(&self) -> aead::UnboundKey {
         aead::UnboundKey::new(
             &aead::CHACHA20_POLY1305,
-            &self
-                .kdf
-                .derive::<32>(&tagged_derive(CHACHA20_POLY1305, ChildId(0))),
+            &self.to_chacha20_poly1305_key_raw(),
         )
         .expect("created key")
     }
+
+    /// Raw bytes of [`Self::to_chacha20_poly1305_key`], for handing a single key to someone else
+    pub fn to_chacha20_poly1305_key_raw(&self) -> [u8; 32] {
+        self.kdf
+            .derive::<32>(&tagged_derive(CHACHA20_POLY1305, ChildId(0)))
+    }
 }
 
 fn tagged_derive(tag: &[u8; 8], derivation: ChildId) -> [u8; 16] {
@@ -38,6 +38,10 @@ use crate::module::DynModuleGen;
 use crate::net::peers::MuxPeerConnections;
 use crate::PeerId;
 
+/// Name of the env value used for passing the guardian's data directory to modules that store
+/// files next to the database
+pub const FM_DATA_DIR_ENV: &str = "FM_DATA_DIR";
+
 /// [`serde_json::Value`] that must contain `kind: String` field
 ///
 /// TODO: enforce at ser/deserialization
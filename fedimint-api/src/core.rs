@@ -51,7 +51,9 @@ pub const MODULE_INSTANCE_ID_GLOBAL: u16 = u16::MAX;
 pub const LEGACY_HARDCODED_INSTANCE_ID_LN: ModuleInstanceId = 0;
 pub const LEGACY_HARDCODED_INSTANCE_ID_MINT: ModuleInstanceId = 1;
 pub const LEGACY_HARDCODED_INSTANCE_ID_WALLET: ModuleInstanceId = 2;
-pub const LEGACY_HARDCODED_INSTANCE_ID_SMOLFS: ModuleInstanceId = 128;
+/// Config generation hands out instance ids in `legacy_init_order_iter` order, which puts
+/// smolfs right after the three modules above
+pub const LEGACY_HARDCODED_INSTANCE_ID_SMOLFS: ModuleInstanceId = 3;
 
 /// A type of a module
 ///
@@ -1,5 +1,6 @@
 use std::io;
 
+use anyhow::format_err;
 use fedimint_api::encoding::{Decodable, DecodeError};
 
 use super::ModuleInstanceId;
@@ -16,6 +17,9 @@ where
     F: FnOnce(&mut R, &DynDecoder, ModuleInstanceId) -> Result<T, DecodeError>,
 {
     let key = ModuleInstanceId::consensus_decode(&mut d, modules)?;
+    let decoder = modules
+        .get(key)
+        .ok_or_else(|| DecodeError::new_custom(format_err!("Unknown module instance {key}")))?;
 
-    decode_fn(d, modules.get_expect(key), key)
+    decode_fn(d, decoder, key)
 }
@@ -0,0 +1,197 @@
+//! Schema versioning and migrations of module databases
+//!
+//! Every module that opts in stores the version of its database layout under
+//! [`DatabaseVersionKey`] inside its isolated key space. When the module is
+//! initialized [`apply_migrations`] brings an older database up to the version
+//! the binary expects by running the module's migrations in order, all within a
+//! single [`DatabaseTransaction`], so a failing migration leaves the database
+//! untouched.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{bail, format_err};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::db::{DatabaseKeyPrefixConst, DatabaseTransaction};
+use crate::encoding::{Decodable, Encodable};
+
+/// Prefix of [`DatabaseVersionKey`] inside a module's key space, chosen so it
+/// doesn't collide with any module's `DbKeyPrefix`
+pub const DATABASE_VERSION_PREFIX: u8 = 0xfe;
+
+/// Version of a module's database layout
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Serialize,
+    Deserialize,
+    Encodable,
+    Decodable,
+)]
+pub struct DatabaseVersion(pub u64);
+
+impl DatabaseVersion {
+    pub fn increment(self) -> DatabaseVersion {
+        DatabaseVersion(self.0 + 1)
+    }
+}
+
+impl fmt::Display for DatabaseVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DatabaseVersionKey;
+
+impl DatabaseKeyPrefixConst for DatabaseVersionKey {
+    const DB_PREFIX: u8 = DATABASE_VERSION_PREFIX;
+    type Key = Self;
+    type Value = DatabaseVersion;
+}
+
+/// Migrates a module's database from one version to the next, `C` is whatever
+/// context besides the database the module's migrations need
+pub type DbMigrationFn<C> =
+    for<'r, 'tx> fn(&'r mut DatabaseTransaction<'tx>, &'r C) -> BoxFuture<'r, anyhow::Result<()>>;
+
+/// Migrations of a module keyed by the version they migrate from
+pub type MigrationMap<C> = BTreeMap<DatabaseVersion, DbMigrationFn<C>>;
+
+/// Brings the database of a module up to `target` by running all migrations
+/// starting at its stored version, then stores `target` as the new version
+///
+/// `dbtx` has to be isolated to the module's key space. A database without a
+/// stored version is treated as freshly created if it is empty and as version 0
+/// otherwise. Fails without running anything if the database is newer than
+/// `target`, since we can't know how to interpret it, or if a migration is
+/// missing.
+pub async fn apply_migrations<C>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    context: &C,
+    module: &str,
+    target: DatabaseVersion,
+    migrations: MigrationMap<C>,
+) -> anyhow::Result<()> {
+    let stored = dbtx.get_value(&DatabaseVersionKey).await?;
+    let mut current = match stored {
+        Some(version) => version,
+        None if dbtx.raw_find_by_prefix(&[]).await.next().is_none() => {
+            info!(%module, %target, "Initializing new module database");
+            dbtx.insert_entry(&DatabaseVersionKey, &target).await?;
+            return Ok(());
+        }
+        None => DatabaseVersion(0),
+    };
+
+    if current > target {
+        bail!(
+            "Database of module {module} has version {current} but this binary only supports up to {target}, refusing to start"
+        );
+    }
+
+    while current < target {
+        let migration = migrations.get(&current).ok_or_else(|| {
+            format_err!("Missing migration of module {module} from database version {current}")
+        })?;
+        info!(%module, from = %current, "Migrating module database");
+        migration(dbtx, context).await?;
+        current = current.increment();
+    }
+
+    if stored != Some(target) {
+        dbtx.insert_entry(&DatabaseVersionKey, &target).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::{apply_migrations, DatabaseVersion, DatabaseVersionKey, MigrationMap};
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{Database, DatabaseKeyPrefixConst, DatabaseTransaction};
+    use crate::encoding::{Decodable, Encodable};
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct CounterKey;
+
+    impl DatabaseKeyPrefixConst for CounterKey {
+        const DB_PREFIX: u8 = 0x01;
+        type Key = Self;
+        type Value = u64;
+    }
+
+    fn increment_counter<'r>(
+        dbtx: &'r mut DatabaseTransaction<'_>,
+        step: &'r u64,
+    ) -> futures::future::BoxFuture<'r, anyhow::Result<()>> {
+        async move {
+            let counter = dbtx.get_value(&CounterKey).await?.unwrap_or(0);
+            dbtx.insert_entry(&CounterKey, &(counter + step)).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn migrations() -> MigrationMap<u64> {
+        MigrationMap::from([
+            (DatabaseVersion(0), increment_counter as _),
+            (DatabaseVersion(1), increment_counter as _),
+        ])
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn migrations_run_in_order_once() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&CounterKey, &0).await.unwrap();
+        apply_migrations(&mut dbtx, &5, "test", DatabaseVersion(2), migrations())
+            .await
+            .unwrap();
+        assert_eq!(dbtx.get_value(&CounterKey).await.unwrap(), Some(10));
+        assert_eq!(
+            dbtx.get_value(&DatabaseVersionKey).await.unwrap(),
+            Some(DatabaseVersion(2))
+        );
+
+        apply_migrations(&mut dbtx, &5, "test", DatabaseVersion(2), migrations())
+            .await
+            .unwrap();
+        assert_eq!(dbtx.get_value(&CounterKey).await.unwrap(), Some(10));
+
+        assert!(
+            apply_migrations(&mut dbtx, &5, "test", DatabaseVersion(1), migrations())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn empty_database_skips_migrations() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+
+        let mut dbtx = db.begin_transaction().await;
+        apply_migrations(&mut dbtx, &5, "test", DatabaseVersion(2), migrations())
+            .await
+            .unwrap();
+        assert_eq!(dbtx.get_value(&CounterKey).await.unwrap(), None);
+        assert_eq!(
+            dbtx.get_value(&DatabaseVersionKey).await.unwrap(),
+            Some(DatabaseVersion(2))
+        );
+    }
+}
@@ -16,6 +16,7 @@ use crate::{
 };
 
 pub mod mem_impl;
+pub mod migration;
 
 pub use tests::*;
 
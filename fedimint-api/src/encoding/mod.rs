@@ -342,8 +342,14 @@ impl Decodable for SystemTime {
         modules: &ModuleDecoderRegistry,
     ) -> Result<Self, DecodeError> {
         let secs = Decodable::consensus_decode(d, modules)?;
-        let nsecs = Decodable::consensus_decode(d, modules)?;
-        Ok(UNIX_EPOCH + Duration::new(secs, nsecs))
+        let nsecs: u32 = Decodable::consensus_decode(d, modules)?;
+        // Would be normalized into the seconds, so the time had more than one encoding
+        if nsecs >= 1_000_000_000 {
+            return Err(DecodeError::from_str("Nanoseconds out of range"));
+        }
+        UNIX_EPOCH
+            .checked_add(Duration::new(secs, nsecs))
+            .ok_or_else(|| DecodeError::from_str("Time out of range"))
     }
 }
 
@@ -447,9 +453,12 @@ where
         for _ in 0..len {
             let amt = K::consensus_decode(d, modules)?;
             let v = V::consensus_decode(d, modules)?;
-            if res.insert(amt, v).is_some() {
-                return Err(DecodeError(format_err!("Duplicate key")));
+            // Keys are encoded in ascending order, accepting any other order would
+            // give the same map multiple encodings
+            if res.keys().next_back().map_or(false, |last| last >= &amt) {
+                return Err(DecodeError(format_err!("Keys not in ascending order")));
             }
+            res.insert(amt, v);
         }
         Ok(res)
     }
@@ -481,9 +490,10 @@ where
         let len = u64::consensus_decode(d, modules)?;
         for _ in 0..len {
             let k = K::consensus_decode(d, modules)?;
-            if !res.insert(k) {
-                return Err(DecodeError(format_err!("Duplicate key")));
+            if res.iter().next_back().map_or(false, |last| last >= &k) {
+                return Err(DecodeError(format_err!("Keys not in ascending order")));
             }
+            res.insert(k);
         }
         Ok(res)
     }
@@ -673,11 +683,51 @@ mod tests {
         test_roundtrip(BTreeSet::from(["a".to_string(), "b".to_string()]));
     }
 
+    #[test_log::test]
+    fn test_btreemap_rejects_unordered_keys() {
+        // {"b": 2, "a": 1}
+        let bytes = [
+            2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'b', 2, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+            0, b'a', 1, 0, 0, 0,
+        ];
+        assert!(BTreeMap::<String, u32>::consensus_decode(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default()
+        )
+        .is_err());
+
+        let bytes = [2, 0, 0, 0, 0, 0, 0, 0, 7, 7];
+        assert!(BTreeSet::<u8>::consensus_decode(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default()
+        )
+        .is_err());
+    }
+
     #[test_log::test]
     fn test_systemtime() {
         test_roundtrip(SystemTime::now());
     }
 
+    #[test_log::test]
+    fn test_systemtime_rejects_out_of_range() {
+        let mut bytes = u64::MAX.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&999_999_999u32.to_le_bytes());
+        assert!(SystemTime::consensus_decode(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default()
+        )
+        .is_err());
+
+        let mut bytes = 0u64.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&1_000_000_000u32.to_le_bytes());
+        assert!(SystemTime::consensus_decode(
+            &mut Cursor::new(bytes),
+            &ModuleDecoderRegistry::default()
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_derive_empty_enum_decode() {
         #[derive(Debug, Encodable, Decodable)]
@@ -1,10 +1,13 @@
 use std::fmt::{Display, Formatter};
 
+use serde::{Deserialize, Serialize};
+
 use crate::db::{DatabaseKeyPrefix, DatabaseKeyPrefixConst, DatabaseTransaction};
 
 #[derive(Default)]
 pub struct Audit {
     items: Vec<AuditItem>,
+    storage: Vec<StorageUsageItem>,
 }
 
 impl Audit {
@@ -41,6 +44,20 @@ impl Audit {
             .collect();
         self.items.append(&mut new_items);
     }
+
+    /// Reports data a module stores for its users, which guardians can query at the
+    /// `/storage_usage` endpoint for capacity planning
+    pub fn add_storage_usage(&mut self, module: &str, entries: u64, bytes: u64) {
+        self.storage.push(StorageUsageItem {
+            module: module.to_string(),
+            entries,
+            bytes,
+        });
+    }
+
+    pub fn storage_usage(&self) -> &[StorageUsageItem] {
+        &self.storage
+    }
 }
 
 impl Display for Audit {
@@ -49,7 +66,14 @@ impl Display for Audit {
         for item in &self.items {
             formatter.write_fmt(format_args!("\n{}", item))?;
         }
-        formatter.write_fmt(format_args!("\n{}", self.sum()))
+        formatter.write_fmt(format_args!("\n{}", self.sum()))?;
+        if !self.storage.is_empty() {
+            formatter.write_str("\n- Storage Usage -")?;
+            for item in &self.storage {
+                formatter.write_fmt(format_args!("\n{}", item))?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -64,3 +88,19 @@ impl Display for AuditItem {
         formatter.write_fmt(format_args!("{:>+15.3}|{}", sats, self.name))
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageUsageItem {
+    pub module: String,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl Display for StorageUsageItem {
+    fn fmt(&self, formatter: &mut Formatter) -> std::fmt::Result {
+        formatter.write_fmt(format_args!(
+            "{:>15} bytes|{:>10} entries|{}",
+            self.bytes, self.entries, self.module
+        ))
+    }
+}
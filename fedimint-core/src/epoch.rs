@@ -177,7 +177,9 @@ impl Decodable for EpochOutcomeSignature {
     ) -> Result<Self, DecodeError> {
         let mut bytes = [0u8; 96];
         d.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
-        Ok(EpochOutcomeSignature(Signature::from_bytes(bytes).unwrap()))
+        Signature::from_bytes(bytes)
+            .map(EpochOutcomeSignature)
+            .map_err(|_| DecodeError::from_str("Invalid epoch outcome signature"))
     }
 }
 
@@ -194,9 +196,9 @@ impl Decodable for EpochOutcomeSignatureShare {
     ) -> Result<Self, DecodeError> {
         let mut bytes = [0u8; 96];
         d.read_exact(&mut bytes).map_err(DecodeError::from_err)?;
-        Ok(EpochOutcomeSignatureShare(
-            SignatureShare::from_bytes(bytes).unwrap(),
-        ))
+        SignatureShare::from_bytes(bytes)
+            .map(EpochOutcomeSignatureShare)
+            .map_err(|_| DecodeError::from_str("Invalid epoch outcome signature share"))
     }
 }
 
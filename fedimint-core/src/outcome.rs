@@ -19,7 +19,7 @@ pub type SerdeOutputOutcome = SerdeModuleEncoding<fedimint_api::core::DynOutputO
 pub mod legacy {
     use fedimint_api::core::{
         LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-        LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+        LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
     };
     use fedimint_api::encoding::{Decodable, Encodable};
     use fedimint_api::ServerModule;
@@ -30,6 +30,7 @@ pub mod legacy {
     };
     use fedimint_ln::{Lightning, LightningOutputOutcome};
     use fedimint_mint::{Mint, MintOutputOutcome};
+    use fedimint_smolfs::{SmolFS, SmolFSOutputOutcome};
     use fedimint_wallet::{Wallet, WalletOutputOutcome};
 
     use crate::CoreError;
@@ -39,6 +40,7 @@ pub mod legacy {
         Mint(<Mint as ServerModule>::OutputOutcome),
         Wallet(<Wallet as ServerModule>::OutputOutcome),
         LN(<Lightning as ServerModule>::OutputOutcome),
+        SmolFS(<SmolFS as ServerModule>::OutputOutcome),
     }
 
     impl From<fedimint_api::core::DynOutputOutcome> for OutputOutcome {
@@ -62,6 +64,12 @@ pub mod legacy {
                         .expect("Module key matches")
                         .clone(),
                 ),
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS => OutputOutcome::SmolFS(
+                    oo.as_any()
+                        .downcast_ref::<SmolFSOutputOutcome>()
+                        .expect("Module key matches")
+                        .clone(),
+                ),
                 _ => panic!("Unknown Module"),
             }
         }
@@ -83,6 +91,7 @@ pub mod legacy {
                 OutputOutcome::Mint(outcome) => Ok(outcome),
                 OutputOutcome::Wallet(_) => Err(CoreError::MismatchingVariant("mint", "wallet")),
                 OutputOutcome::LN(_) => Err(CoreError::MismatchingVariant("mint", "ln")),
+                OutputOutcome::SmolFS(_) => Err(CoreError::MismatchingVariant("mint", "smolfs")),
             }
         }
     }
@@ -93,6 +102,7 @@ pub mod legacy {
                 OutputOutcome::Mint(_) => Err(CoreError::MismatchingVariant("wallet", "mint")),
                 OutputOutcome::Wallet(outcome) => Ok(outcome),
                 OutputOutcome::LN(_) => Err(CoreError::MismatchingVariant("wallet", "ln")),
+                OutputOutcome::SmolFS(_) => Err(CoreError::MismatchingVariant("wallet", "smolfs")),
             }
         }
     }
@@ -103,6 +113,7 @@ pub mod legacy {
                 OutputOutcome::Mint(_) => Err(CoreError::MismatchingVariant("ln", "mint")),
                 OutputOutcome::Wallet(_) => Err(CoreError::MismatchingVariant("ln", "wallet")),
                 OutputOutcome::LN(outcome) => Ok(outcome),
+                OutputOutcome::SmolFS(_) => Err(CoreError::MismatchingVariant("ln", "smolfs")),
             }
         }
     }
@@ -170,7 +170,7 @@ pub mod legacy {
     use bitcoin_hashes::Hash;
     use fedimint_api::core::{
         LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-        LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+        LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
     };
     use fedimint_api::encoding::{Decodable, Encodable};
     use fedimint_api::{ServerModule, TransactionId};
@@ -204,6 +204,7 @@ pub mod legacy {
         Mint(<fedimint_mint::Mint as ServerModule>::Input),
         Wallet(<fedimint_wallet::Wallet as ServerModule>::Input),
         LN(<fedimint_ln::Lightning as ServerModule>::Input),
+        SmolFS(<fedimint_smolfs::SmolFS as ServerModule>::Input),
     }
 
     // TODO: check if clippy is right
@@ -213,6 +214,7 @@ pub mod legacy {
         Mint(<fedimint_mint::Mint as ServerModule>::Output),
         Wallet(<fedimint_wallet::Wallet as ServerModule>::Output),
         LN(<fedimint_ln::Lightning as ServerModule>::Output),
+        SmolFS(<fedimint_smolfs::SmolFS as ServerModule>::Output),
     }
 
     impl Transaction {
@@ -237,6 +239,9 @@ pub mod legacy {
                         core::DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_WALLET, i)
                     }
                     Input::LN(i) => core::DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_LN, i),
+                    Input::SmolFS(i) => {
+                        core::DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, i)
+                    }
                 })
                 .collect::<Vec<fedimint_api::core::DynInput>>();
             let erased_outputs = outputs
@@ -251,6 +256,9 @@ pub mod legacy {
                     Output::LN(o) => {
                         core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_LN, o)
                     }
+                    Output::SmolFS(o) => {
+                        core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, o)
+                    }
                 })
                 .collect::<Vec<fedimint_api::core::DynOutput>>();
 
@@ -314,6 +322,9 @@ pub mod legacy {
                         Input::LN(input) => {
                             core::DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_LN, input)
                         }
+                        Input::SmolFS(input) => {
+                            core::DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, input)
+                        }
                     })
                     .collect(),
                 outputs: self
@@ -329,6 +340,9 @@ pub mod legacy {
                         Output::LN(output) => {
                             core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_LN, output)
                         }
+                        Output::SmolFS(output) => {
+                            core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, output)
+                        }
                     })
                     .collect(),
                 signature: self.signature,
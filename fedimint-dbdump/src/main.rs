@@ -7,6 +7,9 @@ use fedimint_api::db::DatabaseTransaction;
 use fedimint_api::encoding::Encodable;
 use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::module::DynModuleGen;
+use fedimint_core::modules::smolfs::common::{ChangeEvent, EntryMeta, VersionMeta};
+use fedimint_core::modules::smolfs::db as SmolFSRange;
+use fedimint_core::modules::smolfs::SmolFSConfigGenerator;
 use fedimint_ln::{db as LightningRange, LightningGen};
 use fedimint_mint::{db as MintRange, MintGen};
 use fedimint_rocksdb::RocksDbReadOnly;
@@ -15,6 +18,7 @@ use fedimint_wallet::{db as WalletRange, WalletGen};
 use mint_client::db as ClientRange;
 use mint_client::ln::db as ClientLightningRange;
 use mint_client::mint::db as ClientMintRange;
+use mint_client::smolfs::db as ClientSmolFSRange;
 use mint_client::wallet::db as ClientWalletRange;
 use serde::Deserialize;
 use strum::IntoEnumIterator;
@@ -109,6 +113,9 @@ impl<'a> DatabaseDump<'a> {
                 "lightning" => {
                     self.get_lightning_data().await;
                 }
+                "smolfs" => {
+                    self.get_smolfs_data().await;
+                }
                 "mintclient" => {
                     self.get_mint_client_data().await;
                 }
@@ -118,6 +125,9 @@ impl<'a> DatabaseDump<'a> {
                 "walletclient" => {
                     self.get_wallet_client_data().await;
                 }
+                "smolfsclient" => {
+                    self.get_smolfs_client_data().await;
+                }
                 "client" => {
                     self.get_client_data().await;
                 }
@@ -437,6 +447,247 @@ impl<'a> DatabaseDump<'a> {
             .insert("Lightning".to_string(), Box::new(lightning));
     }
 
+    /// Iterates through each of the prefixes within the smolfs range and retrieves
+    /// the corresponding data.
+    ///
+    /// Upload sessions only live in memory of the guardian, so writes that haven't been
+    /// committed by consensus yet don't show up here.
+    async fn get_smolfs_data(&mut self) {
+        let mut smolfs: BTreeMap<String, Box<dyn Serialize>> = BTreeMap::new();
+        for table in SmolFSRange::DbKeyPrefix::iter() {
+            filter_prefixes!(table, self);
+
+            match table {
+                SmolFSRange::DbKeyPrefix::Example => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::ExampleKeyPrefix,
+                        SmolFSRange::ExampleKey,
+                        String,
+                        smolfs,
+                        "Legacy Entries"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::Entry => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::EntryKeyPrefix,
+                        SmolFSRange::EntryKey,
+                        EntryMeta,
+                        smolfs,
+                        "Entries"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::DamagedBlob => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::DamagedBlobKeyPrefix,
+                        SmolFSRange::DamagedBlobKey,
+                        fedimint_core::modules::smolfs::blob::BlobStatus,
+                        smolfs,
+                        "Damaged Blobs"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::RepairStatus => {
+                    let repair_status = self
+                        .read_only
+                        .get_value(&SmolFSRange::RepairStatusKey)
+                        .await
+                        .unwrap();
+                    if let Some(repair_status) = repair_status {
+                        smolfs.insert("Repair Status".to_string(), Box::new(repair_status));
+                    }
+                }
+                SmolFSRange::DbKeyPrefix::EntryVersion => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::EntryVersionKeyPrefix,
+                        SmolFSRange::EntryVersionKey,
+                        VersionMeta,
+                        smolfs,
+                        "Entry Versions"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::BlobRef => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::BlobRefKeyPrefix,
+                        SmolFSRange::BlobRefKey,
+                        u64,
+                        smolfs,
+                        "Blob References"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::ChangeSeq => {
+                    let change_seq = self
+                        .read_only
+                        .get_value(&SmolFSRange::ChangeSeqKey)
+                        .await
+                        .unwrap();
+                    if let Some(change_seq) = change_seq {
+                        smolfs.insert("Change Sequence".to_string(), Box::new(change_seq));
+                    }
+                }
+                SmolFSRange::DbKeyPrefix::Change => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::ChangeKeyPrefix,
+                        SmolFSRange::ChangeKey,
+                        ChangeEvent,
+                        smolfs,
+                        "Changes"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::PrepaidBalance => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::PrepaidBalanceKeyPrefix,
+                        SmolFSRange::PrepaidBalanceKey,
+                        fedimint_api::Amount,
+                        smolfs,
+                        "Prepaid Balances"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::PrepayOutcome => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::PrepayOutcomeKeyPrefix,
+                        SmolFSRange::PrepayOutcomeKey,
+                        fedimint_core::modules::smolfs::SmolFSOutputOutcome,
+                        smolfs,
+                        "Prepay Outcomes"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::PublicEntry => {
+                    push_db_key_items!(
+                        self,
+                        SmolFSRange::PublicEntryKeyPrefix,
+                        SmolFSRange::PublicEntryKey,
+                        smolfs,
+                        "Public Entries"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::SignedRoot => {
+                    let signed_root = self
+                        .read_only
+                        .get_value(&SmolFSRange::SignedRootKey)
+                        .await
+                        .unwrap();
+                    if let Some(signed_root) = signed_root {
+                        smolfs.insert("Signed Root".to_string(), Box::new(signed_root));
+                    }
+                }
+                SmolFSRange::DbKeyPrefix::EntryOwner => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::EntryOwnerKeyPrefix,
+                        SmolFSRange::EntryOwnerKey,
+                        secp256k1::XOnlyPublicKey,
+                        smolfs,
+                        "Entry Owners"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::CapabilityReads => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::CapabilityReadsKeyPrefix,
+                        SmolFSRange::CapabilityReadsKey,
+                        u64,
+                        smolfs,
+                        "Capability Bytes Served"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::CatchUpStatus => {
+                    let catch_up_status = self
+                        .read_only
+                        .get_value(&SmolFSRange::CatchUpStatusKey)
+                        .await
+                        .unwrap();
+                    if let Some(catch_up_status) = catch_up_status {
+                        smolfs.insert("Catch-up Status".to_string(), Box::new(catch_up_status));
+                    }
+                }
+                SmolFSRange::DbKeyPrefix::PendingBlobGc => {
+                    push_db_key_items!(
+                        self,
+                        SmolFSRange::PendingBlobGcKeyPrefix,
+                        SmolFSRange::PendingBlobGcKey,
+                        smolfs,
+                        "Blobs Pending Deletion"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::Tombstone => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::TombstoneKeyPrefix,
+                        SmolFSRange::TombstoneKey,
+                        SmolFSRange::Tombstone,
+                        smolfs,
+                        "Tombstones"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::UploadedBlob => {
+                    push_db_pair_items!(
+                        self,
+                        SmolFSRange::UploadedBlobKeyPrefix,
+                        SmolFSRange::UploadedBlobKey,
+                        std::time::SystemTime,
+                        smolfs,
+                        "Uploaded Blobs"
+                    );
+                }
+                SmolFSRange::DbKeyPrefix::EpochCount => {
+                    let epoch_count = self
+                        .read_only
+                        .get_value(&SmolFSRange::EpochCountKey)
+                        .await
+                        .unwrap();
+                    if let Some(epoch_count) = epoch_count {
+                        smolfs.insert("Epoch Count".to_string(), Box::new(epoch_count));
+                    }
+                }
+            }
+        }
+
+        self.serialized
+            .insert("SmolFS".to_string(), Box::new(smolfs));
+    }
+
+    /// Iterates through each of the prefixes within the smolfs client range and retrieves
+    /// the corresponding data.
+    async fn get_smolfs_client_data(&mut self) {
+        let mut smolfs_client: BTreeMap<String, Box<dyn Serialize>> = BTreeMap::new();
+        for table in ClientSmolFSRange::DbKeyPrefix::iter() {
+            filter_prefixes!(table, self);
+
+            match table {
+                ClientSmolFSRange::DbKeyPrefix::AuditFailure => {
+                    push_db_pair_items!(
+                        self,
+                        ClientSmolFSRange::AuditFailureKeyPrefix,
+                        ClientSmolFSRange::AuditFailureKey,
+                        ClientSmolFSRange::AuditFailure,
+                        smolfs_client,
+                        "Audit Failures"
+                    );
+                }
+                ClientSmolFSRange::DbKeyPrefix::AuditChallenges => {
+                    push_db_pair_items!(
+                        self,
+                        ClientSmolFSRange::AuditChallengesKeyPrefix,
+                        ClientSmolFSRange::AuditChallengesKey,
+                        Vec<ClientSmolFSRange::PrecomputedChallenge>,
+                        smolfs_client,
+                        "Audit Challenges"
+                    );
+                }
+            }
+        }
+
+        self.serialized
+            .insert("Client SmolFS".to_string(), Box::new(smolfs_client));
+    }
+
     /// Iterates through each of the prefixes within the lightning client range and retrieves
     /// the corresponding data.
     async fn get_ln_client_data(&mut self) {
@@ -624,17 +875,19 @@ Options:
     --range=<range>    A CSV list of the ranges of the database to dump [default: All].
     --prefix=<prefix>  A CSV list of he prefixes within the range of the database to dump [default: All].
 
-    RANGES=consensus,mint,wallet,lightning,mintclient,lightningclient,walletclient,client
+    RANGES=consensus,mint,wallet,lightning,smolfs,mintclient,lightningclient,walletclient,smolfsclient,client
 ";
 
-const RANGES: [&str; 8] = [
+const RANGES: [&str; 10] = [
     "consensus",
     "mint",
     "wallet",
     "lightning",
+    "smolfs",
     "mintclient",
     "lightningclient",
     "walletclient",
+    "smolfsclient",
     "client",
 ];
 
@@ -681,6 +934,7 @@ async fn main() {
         DynModuleGen::from(WalletGen),
         DynModuleGen::from(MintGen),
         DynModuleGen::from(LightningGen),
+        DynModuleGen::from(SmolFSConfigGenerator),
     ]);
 
     let decoders: ModuleDecoderRegistry = Default::default(); // TODO: read config and use it to create decoders
@@ -0,0 +1,296 @@
+//! Decoding of raw database entries into typed keys and values
+//!
+//! Every range of the database (consensus, each module, the client) defines a `DbKeyPrefix` enum
+//! whose discriminants are the first byte of its keys. We use those to find the table an entry
+//! belongs to and then decode key and value with the types registered for that table. Module keys
+//! are additionally prefixed with [`MODULE_GLOBAL_PREFIX`] and the module instance id.
+
+use anyhow::format_err;
+use bitcoin_hashes::hex::ToHex;
+use fedimint_api::core::{
+    ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+};
+use fedimint_api::db::migration::{DatabaseVersionKey, DATABASE_VERSION_PREFIX};
+use fedimint_api::db::{DatabaseKey, DatabaseKeyPrefixConst, DatabaseValue, MODULE_GLOBAL_PREFIX};
+use fedimint_api::encoding::Encodable;
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_core::modules::ln::db as LightningRange;
+use fedimint_core::modules::mint::db as MintRange;
+use fedimint_core::modules::smolfs::db as SmolFSRange;
+use fedimint_core::modules::wallet::db as WalletRange;
+use fedimint_server::db as ConsensusRange;
+use mint_client::db as ClientRange;
+use mint_client::ln::db as ClientLightningRange;
+use mint_client::mint::db as ClientMintRange;
+use mint_client::smolfs::db as ClientSmolFSRange;
+use mint_client::wallet::db as ClientWalletRange;
+use serde::Serialize;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+
+/// A database entry with its key and value decoded as far as possible
+#[derive(Debug, Serialize)]
+pub struct DecodedEntry {
+    /// Range of the database the entry belongs to, see `fedimint-dbdump`
+    pub range: String,
+    /// Name of the `DbKeyPrefix` variant, `None` if the prefix is unknown
+    pub table: Option<String>,
+    pub key: Value,
+    pub value: Value,
+    /// Reason the entry could only be printed as hex
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of decoding key and value of a known table
+type DecodeResult = anyhow::Result<(Value, Value)>;
+
+/// Finds the table `$key` belongs to in `$range` and decodes the entry with the matching decode
+/// function. The match over the prefix enum is exhaustive so new tables have to be added here.
+macro_rules! decode_range {
+    ($range:ident, $key:ident, $value:ident, $decoders:ident, {
+        $($variant:ident => $decode:ident $(::<$prefix:ident>)?),* $(,)?
+    }) => {
+        match $range::DbKeyPrefix::iter().find(|table| table.clone() as u8 == $key[0]) {
+            Some(table) => {
+                let decoded = match table.clone() {
+                    $($range::DbKeyPrefix::$variant => {
+                        $decode $(::<$range::$prefix>)? ($key, $value, $decoders)
+                    })*
+                };
+                Some((table.to_string(), decoded))
+            }
+            None => None,
+        }
+    };
+}
+
+/// Decodes a key/value pair read from a server or client database
+pub fn decode_entry(key: &[u8], value: &[u8], decoders: &ModuleDecoderRegistry) -> DecodedEntry {
+    let (range, decoded) = match split_module_key(key) {
+        Some((module_instance_id, module_key)) => (
+            module_range_name(module_instance_id),
+            decode_module_entry(module_instance_id, module_key, value, decoders),
+        ),
+        None => decode_root_entry(key, value, decoders),
+    };
+
+    match decoded {
+        Some((table, Ok((key_json, value_json)))) => DecodedEntry {
+            range,
+            table: Some(table),
+            key: key_json,
+            value: value_json,
+            error: None,
+        },
+        Some((table, Err(e))) => DecodedEntry {
+            range,
+            table: Some(table),
+            key: Value::String(key.to_hex()),
+            value: Value::String(value.to_hex()),
+            error: Some(e.to_string()),
+        },
+        None => DecodedEntry {
+            range,
+            table: None,
+            key: Value::String(key.to_hex()),
+            value: Value::String(value.to_hex()),
+            error: Some("Unknown key prefix".to_string()),
+        },
+    }
+}
+
+/// Splits a key of a module's isolated database into the module instance id and the key as the
+/// module sees it
+fn split_module_key(key: &[u8]) -> Option<(ModuleInstanceId, &[u8])> {
+    match key {
+        [MODULE_GLOBAL_PREFIX, id_lo, id_hi, module_key @ ..] if !module_key.is_empty() => {
+            Some((u16::from_le_bytes([*id_lo, *id_hi]), module_key))
+        }
+        _ => None,
+    }
+}
+
+fn module_range_name(module_instance_id: ModuleInstanceId) -> String {
+    match module_instance_id {
+        LEGACY_HARDCODED_INSTANCE_ID_LN => "lightning".to_string(),
+        LEGACY_HARDCODED_INSTANCE_ID_MINT => "mint".to_string(),
+        LEGACY_HARDCODED_INSTANCE_ID_WALLET => "wallet".to_string(),
+        LEGACY_HARDCODED_INSTANCE_ID_SMOLFS => "smolfs".to_string(),
+        other => format!("module-{other}"),
+    }
+}
+
+fn decode_module_entry(
+    module_instance_id: ModuleInstanceId,
+    key: &[u8],
+    value: &[u8],
+    decoders: &ModuleDecoderRegistry,
+) -> Option<(String, DecodeResult)> {
+    // Stored by every module that uses migrations, outside of its own prefixes
+    if key[0] == DATABASE_VERSION_PREFIX {
+        return Some((
+            "DatabaseVersion".to_string(),
+            singleton::<DatabaseVersionKey>(key, value, decoders),
+        ));
+    }
+
+    match module_instance_id {
+        LEGACY_HARDCODED_INSTANCE_ID_LN => decode_range!(LightningRange, key, value, decoders, {
+            Contract => pair::<ContractKeyPrefix>,
+            Offer => pair::<OfferKeyPrefix>,
+            ProposeDecryptionShare => pair::<ProposeDecryptionShareKeyPrefix>,
+            AgreedDecryptionShare => pair::<AgreedDecryptionShareKeyPrefix>,
+            ContractUpdate => pair::<ContractUpdateKeyPrefix>,
+            LightningGateway => pair::<LightningGatewayKeyPrefix>,
+        }),
+        LEGACY_HARDCODED_INSTANCE_ID_MINT => decode_range!(MintRange, key, value, decoders, {
+            CoinNonce => pair::<NonceKeyPrefix>,
+            ProposedPartialSig => pair::<ProposedPartialSignaturesKeyPrefix>,
+            ReceivedPartialSig => pair::<ReceivedPartialSignaturesKeyPrefix>,
+            OutputOutcome => pair::<OutputOutcomeKeyPrefix>,
+            MintAuditItem => pair::<MintAuditItemKeyPrefix>,
+            EcashBackup => pair::<EcashBackupKeyPrefix>,
+        }),
+        LEGACY_HARDCODED_INSTANCE_ID_WALLET => decode_range!(WalletRange, key, value, decoders, {
+            BlockHash => pair::<BlockHashKeyPrefix>,
+            Utxo => pair::<UTXOPrefixKey>,
+            RoundConsensus => singleton::<RoundConsensusKey>,
+            UnsignedTransaction => pair::<UnsignedTransactionPrefixKey>,
+            PendingTransaction => pair::<PendingTransactionPrefixKey>,
+            PegOutTxSigCi => pair::<PegOutTxSignatureCIPrefix>,
+            PegOutBitcoinOutPoint => pair::<PegOutBitcoinTransactionPrefix>,
+        }),
+        LEGACY_HARDCODED_INSTANCE_ID_SMOLFS => decode_range!(SmolFSRange, key, value, decoders, {
+            Example => pair::<ExampleKeyPrefix>,
+            Entry => pair::<EntryKeyPrefix>,
+            DamagedBlob => pair::<DamagedBlobKeyPrefix>,
+            RepairStatus => singleton::<RepairStatusKey>,
+            EntryVersion => pair::<EntryVersionKeyPrefix>,
+            BlobRef => pair::<BlobRefKeyPrefix>,
+            ChangeSeq => singleton::<ChangeSeqKey>,
+            Change => pair::<ChangeKeyPrefix>,
+            PrepaidBalance => pair::<PrepaidBalanceKeyPrefix>,
+            PrepayOutcome => pair::<PrepayOutcomeKeyPrefix>,
+            PublicEntry => pair::<PublicEntryKeyPrefix>,
+            SignedRoot => singleton::<SignedRootKey>,
+            EntryOwner => pair::<EntryOwnerKeyPrefix>,
+            CapabilityReads => pair::<CapabilityReadsKeyPrefix>,
+            CatchUpStatus => singleton::<CatchUpStatusKey>,
+            PendingBlobGc => pair::<PendingBlobGcKeyPrefix>,
+            Tombstone => pair::<TombstoneKeyPrefix>,
+            UploadedBlob => pair::<UploadedBlobKeyPrefix>,
+            EpochCount => singleton::<EpochCountKey>,
+        }),
+        _ => None,
+    }
+}
+
+/// Decodes entries outside of any module. Server and client databases use disjoint prefixes
+/// so we can simply try all ranges that live there.
+fn decode_root_entry(
+    key: &[u8],
+    value: &[u8],
+    decoders: &ModuleDecoderRegistry,
+) -> (String, Option<(String, DecodeResult)>) {
+    if key.is_empty() {
+        return ("unknown".to_string(), None);
+    }
+
+    if let Some(decoded) = decode_range!(ConsensusRange, key, value, decoders, {
+        ProposedTransaction => pair_hex::<ProposedTransactionKeyPrefix>,
+        AcceptedTransaction => pair_hex::<AcceptedTransactionKeyPrefix>,
+        DropPeer => pair::<DropPeerKeyPrefix>,
+        RejectedTransaction => pair::<RejectedTransactionKeyPrefix>,
+        EpochHistory => pair_hex::<EpochHistoryKeyPrefix>,
+        LastEpoch => singleton::<LastEpochKey>,
+        Module => reserved,
+    }) {
+        return ("consensus".to_string(), Some(decoded));
+    }
+
+    if let Some(decoded) = decode_range!(ClientRange, key, value, decoders, {
+        ClientSecret => singleton::<ClientSecretKey>,
+    }) {
+        return ("client".to_string(), Some(decoded));
+    }
+
+    if let Some(decoded) = decode_range!(ClientMintRange, key, value, decoders, {
+        Coin => pair::<CoinKeyPrefix>,
+        OutputFinalizationData => pair::<OutputFinalizationKeyPrefix>,
+        PendingCoins => pair::<PendingCoinsKeyPrefix>,
+        NextECashNoteIndex => pair::<NextECashNoteIndexKeyPrefix>,
+        NotesPerDenomination => singleton::<NotesPerDenominationKey>,
+    }) {
+        return ("mintclient".to_string(), Some(decoded));
+    }
+
+    if let Some(decoded) = decode_range!(ClientLightningRange, key, value, decoders, {
+        OutgoingPayment => pair::<OutgoingPaymentKeyPrefix>,
+        OutgoingPaymentClaim => pair::<OutgoingPaymentClaimKeyPrefix>,
+        OutgoingContractAccount => pair::<OutgoingContractAccountKeyPrefix>,
+        ConfirmedInvoice => pair::<ConfirmedInvoiceKeyPrefix>,
+        LightningGateway => pair::<LightningGatewayKeyPrefix>,
+    }) {
+        return ("lightningclient".to_string(), Some(decoded));
+    }
+
+    if let Some(decoded) = decode_range!(ClientWalletRange, key, value, decoders, {
+        PegIn => pair::<PegInPrefixKey>,
+    }) {
+        return ("walletclient".to_string(), Some(decoded));
+    }
+
+    if let Some(decoded) = decode_range!(ClientSmolFSRange, key, value, decoders, {
+        AuditFailure => pair::<AuditFailureKeyPrefix>,
+        AuditChallenges => pair::<AuditChallengesKeyPrefix>,
+    }) {
+        return ("smolfsclient".to_string(), Some(decoded));
+    }
+
+    ("unknown".to_string(), None)
+}
+
+/// Decodes key and value of a table whose types are both `Serialize`
+fn pair<P>(key: &[u8], value: &[u8], decoders: &ModuleDecoderRegistry) -> DecodeResult
+where
+    P: DatabaseKeyPrefixConst,
+    P::Key: Serialize,
+    P::Value: Serialize,
+{
+    let key = <P::Key as DatabaseKey>::from_bytes(key, decoders)?;
+    let value = <P::Value as DatabaseValue>::from_bytes(value, decoders)?;
+    Ok((serde_json::to_value(key)?, serde_json::to_value(value)?))
+}
+
+/// Decodes the key of a table whose value isn't `Serialize`, the value is printed re-encoded as
+/// hex after checking that it decodes
+fn pair_hex<P>(key: &[u8], value: &[u8], decoders: &ModuleDecoderRegistry) -> DecodeResult
+where
+    P: DatabaseKeyPrefixConst,
+    P::Key: Serialize,
+    P::Value: Encodable,
+{
+    let key = <P::Key as DatabaseKey>::from_bytes(key, decoders)?;
+    let value = <P::Value as DatabaseValue>::from_bytes(value, decoders)?;
+    Ok((
+        serde_json::to_value(key)?,
+        Value::String(value.consensus_encode_to_vec()?.to_hex()),
+    ))
+}
+
+/// Decodes the value of a table that only holds a single entry, the key carries no information
+fn singleton<K>(_key: &[u8], value: &[u8], decoders: &ModuleDecoderRegistry) -> DecodeResult
+where
+    K: DatabaseKeyPrefixConst,
+    K::Value: Serialize,
+{
+    let value = <K::Value as DatabaseValue>::from_bytes(value, decoders)?;
+    Ok((Value::Null, serde_json::to_value(value)?))
+}
+
+/// Placeholder for prefixes that don't describe a table of their own
+fn reserved(_key: &[u8], _value: &[u8], _decoders: &ModuleDecoderRegistry) -> DecodeResult {
+    Err(format_err!("Prefix is reserved and not a table"))
+}
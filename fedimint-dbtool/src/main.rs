@@ -2,8 +2,18 @@ use anyhow::Result;
 use bitcoin_hashes::hex::ToHex;
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
+use fedimint_api::core::{
+    LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+};
 use fedimint_api::db::Database;
 use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_core::modules::ln::common::LightningDecoder;
+use fedimint_core::modules::mint::common::MintDecoder;
+use fedimint_core::modules::smolfs::common::SmolFSDecoder;
+use fedimint_core::modules::wallet::common::WalletDecoder;
+
+mod decode;
 
 #[derive(Debug, Clone, Parser)]
 struct Options {
@@ -17,6 +27,9 @@ enum DbCommand {
     List {
         #[arg(value_parser = hex_parser)]
         prefix: Bytes,
+        /// Print typed keys and values as JSON, one entry per line
+        #[arg(long)]
+        decode: bool,
     },
     Write {
         #[arg(value_parser = hex_parser)]
@@ -35,9 +48,20 @@ fn hex_parser(hex: &str) -> Result<Bytes> {
     Ok(bytes.into())
 }
 
+/// Decoders of all modules using their legacy hardcoded instance ids, which is what both
+/// `fedimintd` and the client currently use
+fn module_decoders() -> ModuleDecoderRegistry {
+    ModuleDecoderRegistry::from_iter([
+        (LEGACY_HARDCODED_INSTANCE_ID_LN, LightningDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_MINT, MintDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_WALLET, WalletDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, SmolFSDecoder.into()),
+    ])
+}
+
 async fn open_db(path: &str) -> Result<Database> {
     let rocksdb = fedimint_rocksdb::RocksDb::open(path)?;
-    Ok(Database::new(rocksdb, ModuleDecoderRegistry::default()))
+    Ok(Database::new(rocksdb, module_decoders()))
 }
 
 fn print_kv(key: &[u8], value: &[u8]) {
@@ -52,11 +76,20 @@ async fn main() {
     let mut dbtx = db.begin_transaction().await;
 
     match options.command {
-        DbCommand::List { prefix } => {
+        DbCommand::List { prefix, decode } => {
+            let decoders = module_decoders();
             let prefix_iter = dbtx.raw_find_by_prefix(&prefix).await;
             for db_res in prefix_iter {
                 let (key, value) = db_res.expect("DB error");
-                print_kv(&key, &value);
+                if decode {
+                    let entry = decode::decode_entry(&key, &value, &decoders);
+                    println!(
+                        "{}",
+                        serde_json::to_string(&entry).expect("JSON serialization can't fail")
+                    );
+                } else {
+                    print_kv(&key, &value);
+                }
             }
         }
         DbCommand::Write { key, value } => {
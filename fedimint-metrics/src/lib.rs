@@ -0,0 +1,56 @@
+//! Metrics of the server and its modules
+//!
+//! All metrics are registered with [`REGISTRY`], which `fedimintd` serves in the
+//! Prometheus text format if started with `--listen-metrics`. Metric names get
+//! the `fm_` prefix, modules should prefix them with their kind on top of that,
+//! e.g. `fm_smolfs_entries`.
+
+use once_cell::sync::Lazy;
+pub use prometheus::{
+    register_histogram_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_with_registry, Histogram, HistogramOpts,
+    IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+};
+use prometheus::{Encoder, TextEncoder};
+
+/// Registry of all metrics of this process
+pub static REGISTRY: Lazy<Registry> =
+    Lazy::new(|| Registry::new_custom(Some("fm".into()), None).expect("Prefix is valid"));
+
+/// Content type of [`encode_text`]'s output
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Current values of all registered metrics in the Prometheus text format
+pub fn encode_text() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Encoding into a vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use once_cell::sync::Lazy;
+
+    use super::{encode_text, register_int_counter_vec_with_registry, IntCounterVec, REGISTRY};
+
+    static TEST_COUNTER: Lazy<IntCounterVec> = Lazy::new(|| {
+        register_int_counter_vec_with_registry!(
+            "test_events_total",
+            "Events counted by the test",
+            &["kind"],
+            REGISTRY
+        )
+        .unwrap()
+    });
+
+    #[test]
+    fn registered_metrics_are_encoded_with_prefix() {
+        TEST_COUNTER.with_label_values(&["foo"]).inc_by(3);
+
+        let text = encode_text();
+        assert!(text.contains("# TYPE fm_test_events_total counter"));
+        assert!(text.contains("fm_test_events_total{kind=\"foo\"} 3"));
+    }
+}
@@ -17,6 +17,9 @@ use fedimint_api::task::{timeout, Elapsed, TaskGroup};
 use fedimint_api::{Amount, PeerId};
 pub use fedimint_core::config::*;
 use fedimint_core::modules::mint::MintGenParams;
+use fedimint_core::modules::smolfs::config::{
+    DEFAULT_MAX_VERSIONS, DEFAULT_TOMBSTONE_RETENTION_EPOCHS,
+};
 use fedimint_core::modules::smolfs::SmolFSConfigGenParams;
 use fedimint_wallet::WalletGenParams;
 use hbbft::crypto::serde_impl::SerdeSecret;
@@ -660,7 +663,14 @@ impl ServerConfigParams {
                     mint_amounts: ServerConfigParams::gen_denominations(max_denomination),
                 })
                 .attach(SmolFSConfigGenParams {
-                    important_param: 42,
+                    max_versions: DEFAULT_MAX_VERSIONS,
+                    tombstone_retention_epochs: DEFAULT_TOMBSTONE_RETENTION_EPOCHS,
+                    pow_difficulty: None,
+                    write_fee_per_kib: Amount::ZERO,
+                    peer_api_urls: peers
+                        .iter()
+                        .map(|(peer, params)| (*peer, params.api_url.clone()))
+                        .collect(),
                 }),
         }
     }
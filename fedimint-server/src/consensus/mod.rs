@@ -114,12 +114,22 @@ impl FedimintConsensus {
         db: Database,
         module_inits: ModuleGenRegistry,
         task_group: &mut TaskGroup,
+    ) -> anyhow::Result<Self> {
+        Self::new_with_env(cfg, db, module_inits, &Self::get_env_vars_map(), task_group).await
+    }
+
+    /// Like [`FedimintConsensus::new`], but passes `env` to the modules instead of the
+    /// `FM_` variables of the process
+    pub async fn new_with_env(
+        cfg: ServerConfig,
+        db: Database,
+        module_inits: ModuleGenRegistry,
+        env: &BTreeMap<OsString, OsString>,
+        task_group: &mut TaskGroup,
     ) -> anyhow::Result<Self> {
         let mut modules = BTreeMap::new();
         println!("Module Inits <<{module_inits:?}>>");
 
-        let env = Self::get_env_vars_map();
-
         for (module_id, module_cfg) in &cfg.consensus.modules {
             let kind = module_cfg.kind();
 
@@ -132,7 +142,7 @@ impl FedimintConsensus {
                 .init(
                     cfg.get_module_config(*module_id)?,
                     db.clone(),
-                    &env,
+                    env,
                     task_group,
                 )
                 .await?;
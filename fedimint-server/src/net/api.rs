@@ -9,7 +9,7 @@ use fedimint_api::config::ConfigResponse;
 use fedimint_api::core::ModuleInstanceId;
 use fedimint_api::server::DynServerModule;
 use fedimint_api::{
-    module::{api_endpoint, ApiEndpoint, ApiError},
+    module::{api_endpoint, audit::StorageUsageItem, ApiEndpoint, ApiError},
     task::TaskHandle,
     TransactionId,
 };
@@ -226,5 +226,11 @@ fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
                 Ok(fedimint.cfg.consensus.to_config_response(&fedimint.module_inits))
             }
         },
+        api_endpoint! {
+            "/storage_usage",
+            async |fedimint: &FedimintConsensus, _dbtx, _v: ()| -> Vec<StorageUsageItem> {
+                Ok(fedimint.audit().await.storage_usage().to_vec())
+            }
+        },
     ]
 }
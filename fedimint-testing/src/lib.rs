@@ -5,17 +5,14 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use bitcoin::util::taproot::NodeInfo;
 use fedimint_api::config::{ClientModuleConfig, ConfigGenParams, ServerModuleConfig};
 use fedimint_api::core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
 use fedimint_api::db::mem_impl::MemDatabase;
-use fedimint_api::db::{Database, DatabaseTransaction, DatabaseValue, SerializableDatabaseValue};
+use fedimint_api::db::{Database, DatabaseTransaction};
 use fedimint_api::module::interconnect::ModuleInterconect;
-use fedimint_api::module::registry::{ModuleDecoderRegistry, ModuleRegistry};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
 use fedimint_api::module::{ApiError, InputMeta, ModuleError, ModuleGen, TransactionItemAmount};
 use fedimint_api::{OutPoint, PeerId, ServerModule};
-use fedimint_smolfs::db::FinishedSmolFSEntry;
-use fedimint_smolfs::SmolFSOutputOutcome;
 
 pub mod btc;
 
@@ -144,9 +141,24 @@ where
         outputs: &[(OutPoint, Module::Output)],
     ) where
         <Module as ServerModule>::Input: Send + Sync,
+    {
+        self.consensus_round_with_items(inputs, outputs, |_| {})
+            .await
+    }
+
+    /// Like [`Self::consensus_round`], but lets `mangle_items` drop, reorder or
+    /// otherwise tamper with the proposed consensus items before every member
+    /// processes them, as happens when proposals don't make it into an epoch
+    pub async fn consensus_round_with_items<M>(
+        &mut self,
+        inputs: &[Module::Input],
+        outputs: &[(OutPoint, Module::Output)],
+        mangle_items: M,
+    ) where
+        <Module as ServerModule>::Input: Send + Sync,
+        M: FnOnce(&mut Vec<(PeerId, Module::ConsensusItem)>),
     {
         let fake_ic = FakeInterconnect::new_block_height_responder(self.block_height.clone());
-        // TODO: only include some of the proposals for realism
         let mut consensus = vec![];
         for (id, member, db, module_instance_id) in &mut self.members {
             consensus.extend(
@@ -163,6 +175,8 @@ where
             );
         }
 
+        mangle_items(&mut consensus);
+
         let peers: HashSet<PeerId> = self.members.iter().map(|p| p.0).collect();
         for (_peer, member, db, module_instance_id) in &mut self.members {
             let database = db as &mut Database;
@@ -218,55 +232,6 @@ where
         assert_all_equal(results.into_iter())
     }
 
-    pub async fn generate_fake_smolfs_entry(&mut self, outpoint: OutPoint) {
-        for (_, _, db, module_instance_id) in &mut self.members {
-            let mut dbtx = db.begin_transaction().await;
-            // let data = &outpoint.txid.to_bytes()[..];
-            // let modules = ModuleRegistry::default();
-            // let modules = ModuleRegistry::default()
-            // .get(3)
-            // .unwrap();
-            // let modules =ModuleDecoderRegistry::default().get(3)
-            // let txid = bitcoin::Txid::from_bytes(
-            // data,
-            // &modules
-            // ).unwrap();
-            let txid = outpoint.txid;
-
-            {
-                let mut module_dbtx = dbtx.with_module_prefix(*module_instance_id);
-                module_dbtx
-                    .insert_entry(
-                        &fedimint_smolfs::db::ExampleKey("npubTest".to_string()),
-                        &"npubBackup".to_string(),
-                    )
-                    .await
-                    .unwrap();
-                module_dbtx
-                    .insert_entry(&FinishedSmolFSEntry(outpoint), &SmolFSOutputOutcome(txid))
-                    .await
-                    .unwrap();
-            }
-            println!("generating fake smolfs entry");
-
-            dbtx.commit_tx().await.expect("DB Error");
-        }
-    }
-
-    pub async fn get_fake_smolfs_entry(&mut self) {
-        for (a, b, db, module_instance_id) in &mut self.members {
-            let mut dbtx = db.begin_transaction().await;
-            {
-                let mut module_dbtx = dbtx.with_module_prefix(*module_instance_id);
-                let a = module_dbtx
-                    .get_value(&fedimint_smolfs::db::ExampleKey("npubTest".to_string()))
-                    .await
-                    .unwrap();
-                println!("smolfs entry {a:?}");
-            }
-        }
-    }
-
     pub async fn generate_fake_utxo(&mut self) {
         for (_, _, db, module_instance_id) in &mut self.members {
             let mut dbtx = db.begin_transaction().await;
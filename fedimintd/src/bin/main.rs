@@ -1,19 +1,26 @@
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
-use clap::Parser;
-use fedimint_api::config::ModuleGenRegistry;
+use clap::{Parser, Subcommand};
+use fedimint_api::config::{ModuleGenRegistry, FM_DATA_DIR_ENV};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
 use fedimint_api::db::Database;
 use fedimint_api::module::DynModuleGen;
 use fedimint_api::task::{sleep, TaskGroup};
 use fedimint_ln::LightningGen;
 use fedimint_mint::MintGen;
+use fedimint_server::config::ServerConfig;
 use fedimint_server::consensus::FedimintConsensus;
 use fedimint_server::FedimintServer;
+use fedimint_smolfs::archive::{export_archive, import_archive};
+use fedimint_smolfs::blob::BlobStore;
+use fedimint_smolfs::config::SmolFSConfig;
 use fedimint_smolfs::SmolFSConfigGenerator;
 use fedimint_wallet::WalletGen;
 use fedimintd::encrypt::*;
+use fedimintd::metrics::run_metrics_http;
+use fedimintd::public::run_public_http;
 use fedimintd::ui::run_ui;
 use fedimintd::ui::UiMessage;
 use fedimintd::*;
@@ -39,18 +46,68 @@ pub struct ServerOpts {
     /// Port to run admin UI on
     #[arg(long = "listen-ui", env = "FM_LISTEN_UI")]
     pub listen_ui: Option<SocketAddr>,
+    /// Port to serve public smolfs entries on over plain HTTP
+    #[arg(long = "listen-public", env = "FM_LISTEN_PUBLIC")]
+    pub listen_public: Option<SocketAddr>,
+    /// Port to serve metrics on in the Prometheus text format, should not be reachable publicly
+    #[arg(long = "listen-metrics", env = "FM_LISTEN_METRICS")]
+    pub listen_metrics: Option<SocketAddr>,
     #[cfg(feature = "telemetry")]
     #[clap(long)]
     pub with_telemetry: bool,
 }
 
+/// Maintenance of the smolfs dataset, run as `fedimintd smolfs <command>` while
+/// the guardian is stopped
+#[derive(Parser)]
+#[command(name = "fedimintd smolfs")]
+struct SmolfsOpts {
+    #[command(subcommand)]
+    command: SmolfsCommand,
+}
+
+#[derive(Subcommand)]
+enum SmolfsCommand {
+    /// Export all entries and their blobs to a verifiable archive
+    Export {
+        /// Path to folder containing federation config files
+        data_dir: PathBuf,
+        /// Directory to write the archive to, must not contain an archive yet
+        archive: PathBuf,
+        /// Password to decrypt sensitive config files
+        #[arg(long, env = "FM_PASSWORD")]
+        password: Option<String>,
+    },
+    /// Verify an archive and import it into a guardian without smolfs entries
+    Import {
+        /// Path to folder containing federation config files
+        data_dir: PathBuf,
+        /// Directory containing the archive
+        archive: PathBuf,
+        /// Password to decrypt sensitive config files
+        #[arg(long, env = "FM_PASSWORD")]
+        password: Option<String>,
+    },
+}
+
 #[tokio::main]
 async fn main() {
     let mut args = std::env::args();
     if let Some(ref arg) = args.nth(1) {
-        if arg.as_str() == "version-hash" {
-            println!("{}", CODE_VERSION);
-            return;
+        match arg.as_str() {
+            "version-hash" => {
+                println!("{}", CODE_VERSION);
+                return;
+            }
+            "smolfs" => {
+                let opts = SmolfsOpts::parse_from(std::env::args().skip(1));
+                if let Err(e) = run_smolfs(opts.command).await {
+                    eprintln!("{e:?}");
+                    std::process::exit(1);
+                }
+                return;
+            }
+            _ => {}
         }
     }
 
@@ -202,10 +259,121 @@ async fn run(opts: ServerOpts, mut task_group: TaskGroup) -> anyhow::Result<()>
         decoders.clone(),
     );
 
-    let consensus = FedimintConsensus::new(cfg.clone(), db, module_inits, &mut task_group).await?;
+    // Serve public smolfs entries if a socket address was given for it
+    if let Some(listen_public) = opts.listen_public {
+        let mut smolfs_cfg =
+            cfg.get_module_config_typed::<SmolFSConfig>(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)?;
+        smolfs_cfg.local.resolve_blob_dir(&opts.data_dir);
+        let blobs = BlobStore::open(&smolfs_cfg.local.blob_dir)?;
+        let db = db.clone();
+        task_group
+            .spawn("public-http", move |_| async move {
+                run_public_http(listen_public, db, blobs).await;
+            })
+            .await;
+    }
+
+    // Serve metrics if a socket address was given for it
+    if let Some(listen_metrics) = opts.listen_metrics {
+        task_group
+            .spawn("metrics-http", move |_| async move {
+                run_metrics_http(listen_metrics).await;
+            })
+            .await;
+    }
+
+    // Modules storing files outside the database resolve their paths against the data dir
+    let mut env = FedimintConsensus::get_env_vars_map();
+    env.insert(
+        FM_DATA_DIR_ENV.into(),
+        opts.data_dir.clone().into_os_string(),
+    );
+    let consensus =
+        FedimintConsensus::new_with_env(cfg.clone(), db, module_inits, &env, &mut task_group)
+            .await?;
 
     FedimintServer::run(cfg, consensus, decoders, &mut task_group).await?;
     info!("<fedimint run is over>");
 
     Ok(())
 }
+
+/// Opens the config and database of the guardian in `data_dir`
+fn open_guardian(
+    data_dir: &Path,
+    password: Option<String>,
+) -> anyhow::Result<(ServerConfig, Database, SmolFSConfig)> {
+    let module_inits = ModuleGenRegistry::from(vec![
+        DynModuleGen::from(WalletGen),
+        DynModuleGen::from(MintGen),
+        DynModuleGen::from(LightningGen),
+        DynModuleGen::from(SmolFSConfigGenerator),
+    ]);
+
+    let key = get_key(password, data_dir.join(SALT_FILE))?;
+    let cfg = read_server_configs(&key, data_dir.to_path_buf())?;
+    let decoders = module_inits.decoders(cfg.iter_module_instances())?;
+    let db = Database::new(
+        fedimint_rocksdb::RocksDb::open(data_dir.join(DB_FILE))?,
+        decoders,
+    );
+    let mut smolfs_cfg =
+        cfg.get_module_config_typed::<SmolFSConfig>(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)?;
+    smolfs_cfg.local.resolve_blob_dir(data_dir);
+    Ok((cfg, db, smolfs_cfg))
+}
+
+async fn run_smolfs(command: SmolfsCommand) -> anyhow::Result<()> {
+    match command {
+        SmolfsCommand::Export {
+            data_dir,
+            archive,
+            password,
+        } => {
+            let (cfg, db, smolfs_cfg) = open_guardian(&data_dir, password)?;
+            let blobs = BlobStore::open(&smolfs_cfg.local.blob_dir)?;
+
+            let mut dbtx = db.begin_transaction().await;
+            let manifest = export_archive(
+                &mut dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+                &blobs,
+                &archive,
+                cfg.local.identity,
+                &smolfs_cfg.private.root_key,
+            )
+            .await?;
+            println!(
+                "Exported {} entries with state root {} to {}",
+                manifest.entries.len(),
+                manifest.state_root,
+                archive.display()
+            );
+        }
+        SmolfsCommand::Import {
+            data_dir,
+            archive,
+            password,
+        } => {
+            let (_cfg, db, smolfs_cfg) = open_guardian(&data_dir, password)?;
+            let blobs = BlobStore::open(&smolfs_cfg.local.blob_dir)?;
+
+            let mut dbtx = db.begin_transaction().await;
+            let manifest = import_archive(
+                &mut dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+                &blobs,
+                &archive,
+                &smolfs_cfg.consensus.root_keys,
+            )
+            .await?;
+            dbtx.commit_tx().await?;
+            println!(
+                "Imported {} entries with state root {} exported by peer {}",
+                manifest.entries.len(),
+                manifest.state_root,
+                manifest.exported_by
+            );
+        }
+    }
+
+    Ok(())
+}
@@ -13,6 +13,8 @@ use crate::encrypt::{encrypted_read, encrypted_write};
 
 pub mod distributedgen;
 pub mod encrypt;
+pub mod metrics;
+pub mod public;
 pub mod ui;
 
 /// Version of the server code (should be the same among peers)
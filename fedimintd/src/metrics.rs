@@ -0,0 +1,31 @@
+//! HTTP server exposing the metrics of the guardian in the Prometheus text format at `/metrics`,
+//! see [`fedimint_metrics`]
+//!
+//! It is meant to be scraped by a Prometheus instance of the operator and should only be bound to
+//! a local or otherwise private interface.
+
+use std::net::SocketAddr;
+
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use fedimint_metrics::{encode_text, TEXT_CONTENT_TYPE};
+use tracing::{debug, error};
+
+/// Serves metrics on `bind_addr` until the server fails
+pub async fn run_metrics_http(bind_addr: SocketAddr) {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    debug!(%bind_addr, "Starting metrics HTTP server");
+    if let Err(err) = axum::Server::bind(&bind_addr)
+        .serve(app.into_make_service())
+        .await
+    {
+        error!(?err, "Metrics HTTP server encountered an error");
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(CONTENT_TYPE, TEXT_CONTENT_TYPE)], encode_text())
+}
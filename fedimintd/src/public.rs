@@ -0,0 +1,96 @@
+//! Read-only HTTP server for public smolfs entries
+//!
+//! Every public entry is served at `/smolfs/<key>` with headers that let a simple web client
+//! verify the content without trusting this guardian:
+//!
+//! * `X-Smolfs-Version`: version of the entry
+//! * `X-Smolfs-Hash`: sha256 of the body
+//! * `X-Smolfs-Root`: root of all public entries
+//! * `X-Smolfs-Proof`: Merkle path from the entry to the root as comma separated `l:<hash>` or
+//!   `r:<hash>` steps, telling on which side the sibling is
+//! * `X-Smolfs-Root-Signatures`: comma separated `<peer>:<signature>` Schnorr signatures of the
+//!   guardians over the root
+//!
+//! The root headers are missing if the guardians haven't signed the current root yet, which is
+//! only the case for a short time after a public entry changed. See
+//! [`fedimint_smolfs::public`] for how leaves and the signed message are computed.
+
+use std::net::SocketAddr;
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use fedimint_api::db::Database;
+use fedimint_smolfs::blob::BlobStore;
+use fedimint_smolfs::public::{read_public_entry, PublicEntry, PublicTree};
+use tracing::{debug, error};
+
+#[derive(Clone)]
+struct PublicState {
+    db: Database,
+    blobs: BlobStore,
+    tree: PublicTree,
+}
+
+/// Serves public smolfs entries on `bind_addr` until the server fails
+pub async fn run_public_http(bind_addr: SocketAddr, db: Database, blobs: BlobStore) {
+    let app = Router::new()
+        .route("/smolfs/*key", get(public_entry))
+        .with_state(PublicState {
+            db,
+            blobs,
+            tree: PublicTree::default(),
+        });
+
+    debug!(%bind_addr, "Starting public smolfs HTTP server");
+    if let Err(err) = axum::Server::bind(&bind_addr)
+        .serve(app.into_make_service())
+        .await
+    {
+        error!(?err, "Public smolfs HTTP server encountered an error");
+    }
+}
+
+async fn public_entry(State(state): State<PublicState>, Path(key): Path<String>) -> Response {
+    match read_public_entry(&state.db, &state.blobs, &state.tree, &key).await {
+        Ok(Some(entry)) => (entry_headers(&entry), entry.bytes).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            error!(%key, ?err, "Failed to read public entry");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+fn entry_headers(entry: &PublicEntry) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-smolfs-version", HeaderValue::from(entry.meta.version));
+    headers.insert("x-smolfs-hash", header_value(entry.meta.hash.to_string()));
+
+    if let Some(signed_root) = &entry.signed_root {
+        let proof = entry
+            .proof
+            .steps
+            .iter()
+            .map(|step| format!("{}:{}", if step.left { "l" } else { "r" }, step.sibling))
+            .collect::<Vec<_>>()
+            .join(",");
+        let signatures = signed_root
+            .signatures
+            .iter()
+            .map(|(peer, signature)| format!("{peer}:{signature}"))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        headers.insert("x-smolfs-root", header_value(signed_root.root.to_string()));
+        headers.insert("x-smolfs-proof", header_value(proof));
+        headers.insert("x-smolfs-root-signatures", header_value(signatures));
+    }
+    headers
+}
+
+fn header_value(value: String) -> HeaderValue {
+    HeaderValue::try_from(value).expect("hex strings are valid header values")
+}
@@ -512,6 +512,7 @@
             "fedimint-rocksdb"
             "fedimint-server"
             "gateway/ln-gateway"
+            "fedimint-metrics"
             "modules"
           ];
         };
@@ -535,6 +536,7 @@
             "fedimint-server"
             "fedimint-build"
             "gateway/ln-gateway"
+            "fedimint-metrics"
             "modules"
           ];
         };
@@ -559,6 +561,7 @@
             "fedimint-build"
             "gateway/cli"
             "gateway/ln-gateway"
+            "fedimint-metrics"
             "modules"
           ];
         };
@@ -581,6 +584,7 @@
             "fedimint-rocksdb"
             "fedimint-sqlite"
             "fedimint-build"
+            "fedimint-metrics"
             "modules"
           ];
         };
@@ -601,6 +605,7 @@
             "fedimint-dbdump"
             "fedimint-rocksdb"
             "fedimint-sqlite"
+            "fedimint-metrics"
             "modules"
           ];
         };
@@ -631,6 +636,7 @@
             "fedimint-derive"
             "fedimint-server"
             "integrationtests"
+            "fedimint-metrics"
             "modules"
           ];
         };
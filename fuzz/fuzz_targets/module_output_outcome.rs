@@ -0,0 +1,9 @@
+#![no_main]
+
+use fedimint_api::core::DynOutputOutcome;
+use fedimint_fuzz::{decoders, roundtrip};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    roundtrip::<DynOutputOutcome>(data, &decoders());
+});
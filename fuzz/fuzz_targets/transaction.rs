@@ -0,0 +1,9 @@
+#![no_main]
+
+use fedimint_core::transaction::Transaction;
+use fedimint_fuzz::{decoders, roundtrip};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    roundtrip::<Transaction>(data, &decoders());
+});
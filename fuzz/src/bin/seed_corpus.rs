@@ -0,0 +1,327 @@
+//! Writes the initial corpus of the fuzz targets to `corpus/<target>/`
+//!
+//! The samples mirror the values the module and integration tests build, so the
+//! fuzzer starts out with one valid encoding of every variant it has to mutate.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use bitcoin::Txid;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_api::core::{
+    DynInput, DynModuleConsensusItem, DynOutput, DynOutputOutcome, LEGACY_HARDCODED_INSTANCE_ID_LN,
+    LEGACY_HARDCODED_INSTANCE_ID_MINT, LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+};
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_api::{Amount, Feerate, OutPoint, PeerId, TieredMulti, TransactionId};
+use fedimint_core::epoch::{ConsensusItem, EpochOutcome, EpochOutcomeSignatureShare};
+use fedimint_core::modules::ln::contracts::account::AccountContract;
+use fedimint_core::modules::ln::contracts::incoming::OfferId;
+use fedimint_core::modules::ln::contracts::{
+    AccountContractOutcome, Contract, ContractId, ContractOutcome, Preimage,
+    PreimageDecryptionShare,
+};
+use fedimint_core::modules::ln::{
+    ContractOutput, LightningConsensusItem, LightningInput, LightningOutput, LightningOutputOutcome,
+};
+use fedimint_core::modules::mint::{
+    BlindNonce, MintConsensusItem, MintInput, MintOutput, MintOutputBlindSignatures,
+    MintOutputOutcome, MintOutputSignatureShare,
+};
+use fedimint_core::modules::smolfs::delta::Delta;
+use fedimint_core::modules::smolfs::public::root_message;
+use fedimint_core::modules::smolfs::{
+    SmolFSConsensusItem, SmolFSEntry, SmolFSInput, SmolFSOutput, SmolFSOutputOutcome, SmolFSPayload,
+};
+use fedimint_core::modules::wallet::{
+    PegOut, PegOutFees, PegOutSignatureItem, RoundConsensusItem, WalletConsensusItem, WalletOutput,
+    WalletOutputOutcome,
+};
+use fedimint_core::transaction::Transaction;
+use fedimint_fuzz::{decoders, roundtrip};
+use secp256k1::{KeyPair, Secp256k1};
+use threshold_crypto::{SecretKey, SecretKeyShare};
+
+fn main() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("corpus");
+    let decoders = decoders();
+    let secp = Secp256k1::new();
+    let keypair = KeyPair::new(&secp, &mut rand::thread_rng());
+    let owner = keypair.x_only_public_key().0;
+
+    let inputs = module_inputs(&secp, &keypair);
+    let outputs = module_outputs(&secp, &keypair);
+    let outcomes = module_output_outcomes();
+    let items = module_consensus_items(&secp, &keypair);
+
+    let transactions = vec![
+        Transaction {
+            inputs: vec![],
+            outputs: vec![],
+            signature: None,
+        },
+        Transaction {
+            inputs: inputs.clone(),
+            outputs: outputs.clone(),
+            signature: Some(secp.sign_schnorr(&message(), &keypair)),
+        },
+    ];
+
+    let share = SecretKeyShare::default().sign(sha256::Hash::hash(b"epoch"));
+    let epochs = vec![
+        EpochOutcome {
+            epoch: 0,
+            last_hash: None,
+            items: vec![],
+            rejected_txs: Default::default(),
+        },
+        EpochOutcome {
+            epoch: 1,
+            last_hash: Some(sha256::Hash::hash(b"epoch 0")),
+            items: vec![
+                (
+                    PeerId::from(0),
+                    transactions
+                        .iter()
+                        .cloned()
+                        .map(ConsensusItem::Transaction)
+                        .chain(items.iter().cloned().map(ConsensusItem::Module))
+                        .collect(),
+                ),
+                (
+                    PeerId::from(1),
+                    vec![ConsensusItem::EpochOutcomeSignatureShare(
+                        EpochOutcomeSignatureShare(share),
+                    )],
+                ),
+            ],
+            rejected_txs: transactions.iter().map(Transaction::tx_hash).collect(),
+        },
+    ];
+
+    write_samples(&corpus, "module_input", &inputs, &decoders);
+    write_samples(&corpus, "module_output", &outputs, &decoders);
+    write_samples(&corpus, "module_output_outcome", &outcomes, &decoders);
+    write_samples(&corpus, "module_consensus_item", &items, &decoders);
+    write_samples(&corpus, "transaction", &transactions, &decoders);
+    write_samples(&corpus, "epoch_outcome", &epochs, &decoders);
+}
+
+/// Arbitrary message for the signatures in the samples, the decoders don't
+/// check what was signed
+fn message() -> secp256k1::Message {
+    secp256k1::Message::from_slice(&[7; 32]).expect("32 bytes")
+}
+
+fn module_inputs(secp: &Secp256k1<secp256k1::All>, keypair: &KeyPair) -> Vec<DynInput> {
+    let owner = keypair.x_only_public_key().0;
+    let entry = |payload| {
+        DynInput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+            SmolFSInput(Box::new(SmolFSEntry {
+                pubkey: "photos/cat.jpg".to_string(),
+                payload,
+                timestamp: SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 42),
+                pow_nonce: 7,
+                owner: Some(owner),
+                prepaid: Amount::from_sats(1),
+                public: true,
+                expected_version: Some(3),
+            })),
+        )
+    };
+    let base = vec![1u8; 4096];
+    let mut new = base.clone();
+    new.extend_from_slice(b"appended");
+
+    vec![
+        entry(SmolFSPayload::Inline(b"hello smolfs".to_vec())),
+        entry(SmolFSPayload::Uploaded {
+            hash: sha256::Hash::hash(&base),
+            size: base.len() as u64,
+            receipts: BTreeMap::from([(PeerId::from(0), secp.sign_schnorr(&message(), keypair))]),
+        }),
+        entry(SmolFSPayload::Delta {
+            base: sha256::Hash::hash(&base),
+            delta: Delta::compute(&base, &new),
+            hash: sha256::Hash::hash(&new),
+        }),
+        entry(SmolFSPayload::Delete),
+        DynInput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningInput {
+                contract_id: ContractId::from_inner([1; 32]),
+                amount: Amount::from_sats(1000),
+                witness: Some(Preimage([42; 32])),
+            },
+        ),
+        DynInput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_MINT, MintInput::default()),
+    ]
+}
+
+fn module_outputs(secp: &Secp256k1<secp256k1::All>, keypair: &KeyPair) -> Vec<DynOutput> {
+    let owner = keypair.x_only_public_key().0;
+    let nonce = BlindNonce(tbs::blind_message(
+        tbs::Message::from_bytes(b"nonce"),
+        tbs::BlindingKey::random(),
+    ));
+
+    vec![
+        DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+            SmolFSOutput {
+                owner,
+                amount: Amount::from_sats(10),
+            },
+        ),
+        DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningOutput::Contract(ContractOutput {
+                amount: Amount::from_sats(1000),
+                contract: Contract::Account(AccountContract { key: owner }),
+            }),
+        ),
+        DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningOutput::CancelOutgoing {
+                contract: ContractId::from_inner([2; 32]),
+                gateway_signature: secp.sign_schnorr(&message(), keypair),
+            },
+        ),
+        DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_MINT,
+            MintOutput(TieredMulti::from_iter([(Amount::from_msats(1024), nonce)])),
+        ),
+        DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            WalletOutput(PegOut {
+                recipient: bitcoin::Address::from_str("tb1qunn0thpt8uk3yk2938ypjccn3urxprt78z9ccq")
+                    .unwrap(),
+                amount: bitcoin::Amount::from_sat(42000),
+                fees: PegOutFees {
+                    fee_rate: Feerate { sats_per_kvb: 1000 },
+                    total_weight: 875,
+                },
+            }),
+        ),
+    ]
+}
+
+fn module_output_outcomes() -> Vec<DynOutputOutcome> {
+    vec![
+        DynOutputOutcome::from_typed(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, SmolFSOutputOutcome),
+        DynOutputOutcome::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningOutputOutcome::Offer {
+                id: OfferId::from_hash(sha256::Hash::hash(b"offer")),
+            },
+        ),
+        DynOutputOutcome::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningOutputOutcome::Contract {
+                id: ContractId::from_inner([3; 32]),
+                outcome: ContractOutcome::Account(AccountContractOutcome {}),
+            },
+        ),
+        DynOutputOutcome::from_typed(LEGACY_HARDCODED_INSTANCE_ID_MINT, MintOutputOutcome(None)),
+        DynOutputOutcome::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_MINT,
+            MintOutputOutcome(Some(MintOutputBlindSignatures(TieredMulti::default()))),
+        ),
+        DynOutputOutcome::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            WalletOutputOutcome(Txid::all_zeros()),
+        ),
+    ]
+}
+
+fn module_consensus_items(
+    secp: &Secp256k1<secp256k1::All>,
+    keypair: &KeyPair,
+) -> Vec<DynModuleConsensusItem> {
+    let root = sha256::Hash::hash(b"public root");
+    let share = SecretKeyShare::default()
+        .decrypt_share_no_verify(&SecretKey::random().public_key().encrypt(""));
+    let (_, _, sks) = tbs::dealer_keygen(1, 1);
+    let nonce = tbs::blind_message(
+        tbs::Message::from_bytes(b"nonce"),
+        tbs::BlindingKey::random(),
+    );
+    let ecdsa = secp.sign_ecdsa(&message(), &keypair.secret_key());
+
+    vec![
+        DynModuleConsensusItem::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+            SmolFSConsensusItem {
+                root,
+                signature: secp.sign_schnorr(&root_message(&root), keypair),
+            },
+        ),
+        DynModuleConsensusItem::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_LN,
+            LightningConsensusItem {
+                contract_id: ContractId::from_inner([4; 32]),
+                share: PreimageDecryptionShare(share),
+            },
+        ),
+        DynModuleConsensusItem::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_MINT,
+            MintConsensusItem {
+                out_point: OutPoint {
+                    txid: TransactionId::from_inner([5; 32]),
+                    out_idx: 0,
+                },
+                signatures: MintOutputSignatureShare(TieredMulti::from_iter([(
+                    Amount::from_msats(1024),
+                    (nonce, tbs::sign_blinded_msg(nonce, sks[0])),
+                )])),
+            },
+        ),
+        DynModuleConsensusItem::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            WalletConsensusItem::RoundConsensus(RoundConsensusItem {
+                block_height: 100,
+                fee_rate: Feerate { sats_per_kvb: 1000 },
+                randomness: [6; 32],
+            }),
+        ),
+        DynModuleConsensusItem::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+            WalletConsensusItem::PegOutSignature(PegOutSignatureItem {
+                txid: Txid::all_zeros(),
+                signature: vec![ecdsa],
+            }),
+        ),
+    ]
+}
+
+/// Writes every sample to a file named after the hash of its encoding, like
+/// libFuzzer names the inputs it adds to a corpus
+///
+/// Panics if a sample doesn't survive the check the fuzz targets do, since the
+/// fuzzer would report it right away.
+fn write_samples<T>(corpus: &Path, target: &str, samples: &[T], decoders: &ModuleDecoderRegistry)
+where
+    T: Encodable + Decodable + PartialEq + Debug,
+{
+    let dir = corpus.join(target);
+    std::fs::create_dir_all(&dir).expect("Failed to create corpus directory");
+    for sample in samples {
+        let bytes = sample
+            .consensus_encode_to_vec()
+            .expect("Encoding to vec can't fail");
+        let decoded = T::consensus_decode(&mut &bytes[..], decoders)
+            .unwrap_or_else(|e| panic!("Failed to decode sample {sample:?}: {e}"));
+        assert_eq!(&decoded, sample);
+        roundtrip::<T>(&bytes, decoders);
+
+        let path = dir.join(sha256::Hash::hash(&bytes).to_string());
+        std::fs::write(&path, bytes).expect("Failed to write corpus sample");
+    }
+    println!("Wrote {} samples to {}", samples.len(), dir.display());
+}
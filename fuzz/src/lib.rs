@@ -0,0 +1,53 @@
+//! Shared code of the fuzz targets in `fuzz_targets` and the corpus seeding in
+//! `src/bin/seed_corpus.rs`
+
+use std::fmt::Debug;
+
+use fedimint_api::core::{
+    LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+};
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_core::modules::ln::common::LightningDecoder;
+use fedimint_core::modules::mint::common::MintDecoder;
+use fedimint_core::modules::smolfs::common::SmolFSDecoder;
+use fedimint_core::modules::wallet::common::WalletDecoder;
+
+/// Decoders of all modules using their legacy hardcoded instance ids, which is
+/// what both `fedimintd` and the client currently use
+pub fn decoders() -> ModuleDecoderRegistry {
+    ModuleDecoderRegistry::from_iter([
+        (LEGACY_HARDCODED_INSTANCE_ID_LN, LightningDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_MINT, MintDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_WALLET, WalletDecoder.into()),
+        (LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, SmolFSDecoder.into()),
+    ])
+}
+
+/// Decodes a `T` from the start of `data` and checks that encoding it again
+/// yields exactly the bytes it was decoded from
+///
+/// Inputs that don't decode are fine, the decoder just must not panic on them.
+/// Every value has to have a single encoding though, otherwise guardians could
+/// disagree about e.g. the id of a transaction.
+pub fn roundtrip<T>(data: &[u8], decoders: &ModuleDecoderRegistry)
+where
+    T: Encodable + Decodable + PartialEq + Debug,
+{
+    let mut reader = data;
+    let value = match T::consensus_decode(&mut reader, decoders) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let consumed = &data[..data.len() - reader.len()];
+
+    let encoded = value
+        .consensus_encode_to_vec()
+        .expect("Encoding to vec can't fail");
+    assert_eq!(encoded, consumed, "{value:?} has more than one encoding");
+
+    let decoded = T::consensus_decode(&mut &encoded[..], decoders)
+        .expect("Decoding a freshly encoded value can't fail");
+    assert_eq!(decoded, value);
+}
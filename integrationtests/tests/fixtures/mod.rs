@@ -24,7 +24,8 @@ use fedimint_api::config::{ClientConfig, ModuleGenRegistry};
 use fedimint_api::core;
 use fedimint_api::core::{
     DynModuleConsensusItem as PerModuleConsensusItem, ModuleConsensusItem,
-    LEGACY_HARDCODED_INSTANCE_ID_MINT, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+    LEGACY_HARDCODED_INSTANCE_ID_MINT, LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
 use fedimint_api::db::mem_impl::MemDatabase;
 use fedimint_api::db::Database;
@@ -44,7 +45,6 @@ use fedimint_server::config::ServerConfigParams;
 use fedimint_server::config::{connect, ServerConfig};
 use fedimint_server::consensus::{ConsensusProposal, HbbftConsensusOutcome};
 use fedimint_server::consensus::{FedimintConsensus, TransactionSubmissionError};
-use fedimint_server::modules::smolfs::db::{ExampleKey, ExampleKeyPrefix};
 use fedimint_server::modules::smolfs::SmolFSConfigGenerator;
 use fedimint_server::multiplexed::PeerConnectionMultiplexer;
 use fedimint_server::net::connect::mock::MockNetwork;
@@ -70,6 +70,7 @@ use ln_gateway::{
     rpc::GatewayRequest,
     LnGateway,
 };
+use mint_client::db::ClientSecretKey;
 use mint_client::module_decode_stubs;
 use mint_client::{
     api::WsFederationApi, mint::SpendableNote, Client, GatewayClient, GatewayClientConfig,
@@ -603,6 +604,38 @@ impl UserTest<UserClientConfig> {
         .await;
         UserTest::new(Arc::new(user))
     }
+
+    /// Create a user with the same client secret but an empty database, like on a fresh device
+    pub async fn new_user_with_same_secret(
+        &self,
+        peers: Vec<PeerId>,
+    ) -> UserTest<UserClientConfig> {
+        let secret = self
+            .client
+            .db()
+            .begin_transaction()
+            .await
+            .get_value(&ClientSecretKey)
+            .await
+            .expect("DB error")
+            .expect("Client has a secret");
+        let db = Database::new(MemDatabase::new(), module_decode_stubs());
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&ClientSecretKey, &secret)
+            .await
+            .expect("DB error");
+        dbtx.commit_tx().await.expect("DB error");
+
+        let user = create_user_client(
+            self.config.clone(),
+            self.client.decoders().clone(),
+            self.client.module_gens().clone(),
+            peers,
+            db,
+        )
+        .await;
+        UserTest::new(Arc::new(user))
+    }
 }
 
 impl<T: AsRef<ClientConfig> + Clone> UserTest<T> {
@@ -880,24 +913,28 @@ impl FederationTest {
         }
         true
     }
-    pub async fn backups_for_everyone(&self, pubkey: &str, backup: &str) -> bool {
-        info!("backups for everyone");
+
+    /// Credits `amount` to the smolfs prepaid balance of `owner` directly in the
+    /// databases of federation nodes
+    pub async fn prepay_smolfs_for_everyone(
+        &self,
+        owner: secp256k1::XOnlyPublicKey,
+        amount: Amount,
+    ) -> OutPoint {
         let bytes: [u8; 32] = rand::random();
         let out_point = OutPoint {
             txid: fedimint_api::TransactionId::from_inner(bytes),
             out_idx: 0,
         };
+        let output = core::DynOutput::from_typed(
+            LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+            SmolFSOutput { owner, amount },
+        );
 
         for server in &self.servers {
             let transaction = fedimint_server::transaction::Transaction {
                 inputs: vec![],
-                outputs: vec![core::DynOutput::from_typed(
-                    3,
-                    SmolFSOutput(Box::new(SmolFSEntry {
-                        pubkey: String::from(pubkey),
-                        backup: String::from(backup),
-                    })),
-                )],
+                outputs: vec![output.clone()],
                 signature: None,
             };
             let svr = server.borrow_mut();
@@ -906,7 +943,7 @@ impl FederationTest {
                 &fedimint_server::db::AcceptedTransactionKey(out_point.txid),
                 &fedimint_server::consensus::AcceptedTransaction {
                     epoch: 1,
-                    transaction: transaction.clone(),
+                    transaction,
                 },
             )
             .await
@@ -914,25 +951,51 @@ impl FederationTest {
             svr.fedimint
                 .consensus
                 .modules
-                .get_expect(3)
+                .get_expect(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
                 .apply_output(
-                    &mut dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_MINT),
-                    &core::DynOutput::from_typed(
-                        3,
-                        SmolFSOutput(Box::new(SmolFSEntry {
-                            pubkey: String::from(pubkey),
-                            backup: String::from(backup),
-                        })),
-                    ),
+                    &mut dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS),
+                    &output,
                     out_point,
                 )
                 .await
                 .unwrap();
 
             dbtx.commit_tx().await.expect("DB Error");
-            // self.override_proposal(vec![ConsensusItem::Transaction(transaction.clone())]);
         }
-        true
+        out_point
+    }
+
+    /// Returns the smolfs prepaid balance of `owner` on every federation node
+    pub async fn smolfs_prepaid_balances(&self, owner: secp256k1::XOnlyPublicKey) -> Vec<Amount> {
+        let mut balances = vec![];
+        for server in &self.servers {
+            let svr = server.borrow_mut();
+            let mut dbtx = svr.database.begin_transaction().await;
+            let balance = dbtx
+                .with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
+                .get_value(&fedimint_smolfs::db::PrepaidBalanceKey(owner))
+                .await
+                .expect("DB Error")
+                .unwrap_or(Amount::ZERO);
+            balances.push(balance);
+        }
+        balances
+    }
+
+    /// Returns the signed root of the smolfs public entries stored on every federation node
+    pub async fn smolfs_signed_roots(&self) -> Vec<Option<fedimint_smolfs::public::SignedRoot>> {
+        let mut roots = vec![];
+        for server in &self.servers {
+            let svr = server.borrow_mut();
+            let mut dbtx = svr.database.begin_transaction().await;
+            let root = dbtx
+                .with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
+                .get_value(&fedimint_smolfs::db::SignedRootKey)
+                .await
+                .expect("DB Error");
+            roots.push(root);
+        }
+        roots
     }
 
     /// Inserts coins directly into the databases of federation nodes
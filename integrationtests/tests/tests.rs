@@ -8,8 +8,9 @@ use bitcoin::{Amount, KeyPair};
 use fedimint_api::cancellable::Cancellable;
 use fedimint_api::core::{
     ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
-    LEGACY_HARDCODED_INSTANCE_ID_WALLET,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
+use fedimint_api::encoding::Encodable;
 use fedimint_api::task::TaskGroup;
 use fedimint_api::{msats, sats, TieredMulti};
 use fedimint_ln::contracts::{Preimage, PreimageDecryptionShare};
@@ -17,7 +18,8 @@ use fedimint_ln::LightningConsensusItem;
 use fedimint_mint::{MintConsensusItem, MintOutputSignatureShare};
 use fedimint_server::consensus::TransactionSubmissionError::TransactionError;
 use fedimint_server::epoch::ConsensusItem;
-use fedimint_server::modules::smolfs::{SmolFS, SmolFSEntry, SmolFSOutputConfirmation};
+use fedimint_server::modules::smolfs::public::root_message;
+use fedimint_server::modules::smolfs::SmolFSConsensusItem;
 use fedimint_server::transaction::legacy::Output;
 use fedimint_server::transaction::TransactionError::UnbalancedTransaction;
 use fedimint_wallet::PegOutSignatureItem;
@@ -34,52 +36,58 @@ use tracing::log::info;
 use crate::fixtures::{assert_ci, peers, test, FederationTest};
 
 #[tokio::test(flavor = "multi_thread")]
-async fn make_backup() -> Result<()> {
+async fn smolfs_prepayment_credits_owner() -> Result<()> {
     test(4, |fed, _, _, _, _| async move {
-        let pubkey = String::from("npubA3e");
-        let backup = String::from("42");
-        let entry = SmolFSEntry { pubkey, backup };
-        // let ci = SmolFSOutputConfirmation(entry);
-        // fed.get_db_contents().await;
-        // fed.run_consensus_epochs(1).await;
-        let _ = fed.backups_for_everyone(&entry.pubkey, &entry.backup).await;
-        // fed.get_db_contents().await;
-        fed.broadcast_transactions().await;
+        let owner = KeyPair::new(&secp(), &mut rng()).x_only_public_key().0;
+
+        fed.prepay_smolfs_for_everyone(owner, msats(5000)).await;
         fed.run_consensus_epochs(1).await;
+
+        for balance in fed.smolfs_prepaid_balances(owner).await {
+            assert_eq!(balance, msats(5000));
+        }
     })
     .await
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn module_experimenting() -> Result<()> {
+async fn smolfs_ignores_invalid_root_signature() -> Result<()> {
     test(4, |fed, _, _, _, _| async move {
-        let entry = SmolFSEntry {
-            pubkey: String::from("npubA3e"),
-            backup: String::from("42"),
+        // Signed with a key that isn't one of the guardians' root keys
+        let key = KeyPair::new(&secp(), &mut rng());
+        let root = sha256(b"not the public root");
+        let item = SmolFSConsensusItem {
+            root,
+            signature: secp().sign_schnorr(&root_message(&root), &key),
         };
-        let ci = SmolFSOutputConfirmation(entry);
-        // let a = fed
-        //     .cfg
-        //     .get_module_config(3)
-        //     .unwrap()
-        //     .local
-        //     .is_kind(&ModuleKind::from_static_str("smolfs"));
-        // let a = fed.cfg.get_module_config(3).unwrap().local;
-        // let backup = a.value().as_object().unwrap().get("backup").unwrap();
-        // let pubkey = a.value().as_object().unwrap().get("pubkey").unwrap();
-        // info!("{a:?}");
-        // info!("backup {backup:?}");
-        // info!("pubkey {pubkey:?}");
-        let ci_vec = vec![ConsensusItem::Module(
-            fedimint_api::core::DynModuleConsensusItem::from_typed(3, ci),
-        )];
-
-        // fed.get_db_contents().await;
+        let signature = item.signature;
 
         fed.run_consensus_epochs(1).await;
-        fed.subset_peers(&[1]).override_proposal(ci_vec);
+        fed.subset_peers(&[1])
+            .override_proposal(vec![ConsensusItem::Module(
+                fedimint_api::core::DynModuleConsensusItem::from_typed(
+                    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+                    item,
+                ),
+            )]);
         fed.run_consensus_epochs(1).await;
-        // fed.get_db_contents().await;
+
+        // No guardian stored the bogus root or its signature
+        for signed_root in fed.smolfs_signed_roots().await.into_iter().flatten() {
+            assert_ne!(signed_root.root, root);
+            assert!(!signed_root
+                .signatures
+                .values()
+                .any(|stored| *stored == signature));
+        }
+
+        // Consensus keeps making progress
+        let owner = key.x_only_public_key().0;
+        fed.prepay_smolfs_for_everyone(owner, msats(1000)).await;
+        fed.run_consensus_epochs(1).await;
+        for balance in fed.smolfs_prepaid_balances(owner).await {
+            assert_eq!(balance, msats(1000));
+        }
     })
     .await
 }
@@ -1121,6 +1129,56 @@ async fn ecash_can_be_recovered() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn client_state_can_be_restored_from_backup() -> Result<()> {
+    test(2, |fed, user, bitcoin, _, lightning| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(5000)).await;
+        user.client.get_new_pegin_address(rng()).await;
+        let invoice = lightning.invoice(sats(1000), None).await;
+        user.client
+            .fund_outgoing_ln_contract(invoice, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // process transaction + sign change coins
+
+        user.client.backup_all(rng()).await.unwrap();
+        fed.run_consensus_epochs(2).await; // process backup write + sign change coins
+        user.client.fetch_all_coins().await;
+        let ln_state = user.client.ln_client().prepare_backup().await;
+        let wallet_state = user.client.wallet_client().prepare_backup().await;
+        assert!(!ln_state.outgoing_payments.is_empty());
+        assert!(!wallet_state.peg_ins.is_empty());
+
+        let restored = user.new_user_with_same_secret(peers(&[0, 1])).await;
+        let mut task_group = TaskGroup::new();
+        restored
+            .client
+            .restore_all(2, &mut task_group)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(
+            restored
+                .client
+                .ln_client()
+                .prepare_backup()
+                .await
+                .consensus_encode_to_vec()
+                .unwrap(),
+            ln_state.consensus_encode_to_vec().unwrap()
+        );
+        assert_eq!(
+            restored.client.wallet_client().prepare_backup().await,
+            wallet_state
+        );
+        assert_eq!(restored.total_coins().await, user.total_coins().await);
+
+        task_group.join_all(None).await.unwrap();
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn verifies_client_configs() -> Result<()> {
     test(2, |_, user, _, _, _| async move {
@@ -0,0 +1,470 @@
+//! Export and import of the smolfs dataset, e.g. to move a guardian to new
+//! hardware or to seed a new guardian
+//!
+//! An archive is a directory holding [`MANIFEST_FILE`] and a [`BlobStore`] under
+//! [`ARCHIVE_BLOB_DIR`] with the payloads of all retained versions. The manifest
+//! lists every entry with its retained versions and commits to them with a state
+//! root, built like the root of the public entries (see [`crate::public`]) but
+//! over all entries with
+//!
+//! ```text
+//! leaf = sha256(0x02 || consensus encoding of the ArchivedEntry)
+//! ```
+//!
+//! The exporting guardian signs the state root with its root key. The signed
+//! root of the public entries is included too if it is up to date, so the public
+//! entries can be checked against the signatures of a threshold of guardians.
+//!
+//! Only entries are archived. The change log, prepaid balances and local state
+//! like damaged blobs are not part of the dataset.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, ensure, format_err};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_api::db::DatabaseTransaction;
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::__reexports::serde_json;
+use fedimint_api::{NumPeers, PeerId};
+use secp256k1::{schnorr, KeyPair, Message, Secp256k1, SecretKey, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::blob::BlobStore;
+use crate::common::{EntryMeta, VersionMeta};
+use crate::db::{
+    BlobRefKey, EntryKey, EntryKeyPrefix, EntryOwnerKey, EntryOwnerKeyPrefix, EntryVersionKey,
+    EntryVersionKeyPrefix, PublicEntryKey, PublicEntryKeyPrefix, SignedRootKey,
+};
+use crate::public::{leaf_hash, merkle_root, SignedRoot};
+
+/// Name of the manifest inside an archive directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Name of the blob store inside an archive directory
+pub const ARCHIVE_BLOB_DIR: &str = "blobs";
+
+/// Prefixed to the state root before signing it, so the signature can't be
+/// mistaken for a signature over the root of the public entries
+const STATE_ROOT_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-state-root";
+
+/// Describes the contents of an archive
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    /// Guardian that created the archive and signed the state root
+    pub exported_by: PeerId,
+    /// All entries, sorted by key
+    pub entries: Vec<ArchivedEntry>,
+    pub state_root: sha256::Hash,
+    pub state_root_signature: schnorr::Signature,
+    /// Federation's signature over the public entries in the archive, `None` if
+    /// the guardians hadn't signed the current root yet at the time of export
+    pub signed_root: Option<SignedRoot>,
+}
+
+/// A single entry together with all of its retained versions
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ArchivedEntry {
+    pub key: String,
+    /// Current version, `None` if the entry was deleted and only old versions are
+    /// retained
+    pub meta: Option<EntryMeta>,
+    pub public: bool,
+    pub owner: Option<XOnlyPublicKey>,
+    pub versions: BTreeMap<u64, VersionMeta>,
+}
+
+impl ArchivedEntry {
+    pub(crate) fn empty(key: &str) -> ArchivedEntry {
+        ArchivedEntry {
+            key: key.to_string(),
+            meta: None,
+            public: false,
+            owner: None,
+            versions: BTreeMap::new(),
+        }
+    }
+
+    fn leaf_hash(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&[0x02]);
+        self.consensus_encode(&mut engine)
+            .expect("Hashing never fails");
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+/// Root over all entries of an archive, which have to be sorted by key
+pub fn state_root(entries: &[ArchivedEntry]) -> sha256::Hash {
+    let leaves = entries
+        .iter()
+        .map(ArchivedEntry::leaf_hash)
+        .collect::<Vec<_>>();
+    merkle_root(&leaves)
+}
+
+/// Leaves of the public entries' Merkle tree, see [`crate::public`]
+fn public_leaves(entries: &[ArchivedEntry]) -> Vec<sha256::Hash> {
+    entries
+        .iter()
+        .filter(|entry| entry.public)
+        .filter_map(|entry| entry.meta.as_ref().map(|meta| leaf_hash(&entry.key, meta)))
+        .collect()
+}
+
+/// Message a guardian signs to attest to the state root of an archive
+pub fn state_root_message(root: &sha256::Hash) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.input(STATE_ROOT_SIGNATURE_TAG);
+    engine.input(&root[..]);
+    Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("hash has right length")
+}
+
+/// Checks that entries are sorted by key and that their current versions are
+/// consistent with the retained ones
+pub fn verify_entries(entries: &[ArchivedEntry]) -> anyhow::Result<()> {
+    ensure!(
+        entries.windows(2).all(|pair| pair[0].key < pair[1].key),
+        "Entries are not sorted by key or contain duplicates"
+    );
+    for entry in entries {
+        if let Some(meta) = &entry.meta {
+            let current = entry
+                .versions
+                .get(&meta.version)
+                .ok_or_else(|| format_err!("Current version of {} is not retained", entry.key))?;
+            ensure!(
+                current.hash == meta.hash && current.size == meta.size,
+                "Current version of {} doesn't match its metadata",
+                entry.key
+            );
+        } else {
+            ensure!(!entry.public, "Deleted entry {} is public", entry.key);
+        }
+    }
+    Ok(())
+}
+
+impl ArchiveManifest {
+    /// Checks that the manifest is consistent, that the exporting guardian signed
+    /// its state root and that the public entries match the federation's signed
+    /// root
+    ///
+    /// The blobs are not checked, this happens while importing them.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+    ) -> anyhow::Result<()> {
+        verify_entries(&self.entries)?;
+        ensure!(
+            state_root(&self.entries) == self.state_root,
+            "Entries don't match the state root"
+        );
+        let exporter_key = root_keys.get(&self.exported_by).ok_or_else(|| {
+            format_err!("Archive was exported by unknown peer {}", self.exported_by)
+        })?;
+        ensure!(
+            secp.verify_schnorr(
+                &self.state_root_signature,
+                &state_root_message(&self.state_root),
+                exporter_key
+            )
+            .is_ok(),
+            "Invalid state root signature"
+        );
+
+        if let Some(signed_root) = &self.signed_root {
+            ensure!(
+                merkle_root(&public_leaves(&self.entries)) == signed_root.root,
+                "Public entries don't match the signed root"
+            );
+            let threshold = root_keys
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>()
+                .threshold();
+            ensure!(
+                signed_root.verify(secp, root_keys, threshold),
+                "Signed root lacks a threshold of valid signatures"
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// All entries with their retained versions as they would be archived, sorted
+/// by key
+pub async fn archived_entries(dbtx: &mut DatabaseTransaction<'_>) -> Vec<ArchivedEntry> {
+    let mut entries: BTreeMap<String, ArchivedEntry> = BTreeMap::new();
+
+    let versions = dbtx
+        .find_by_prefix(&EntryVersionKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error"))
+        .collect::<Vec<_>>();
+    for (version_key, version) in versions {
+        entries
+            .entry(version_key.key.clone())
+            .or_insert_with(|| ArchivedEntry::empty(&version_key.key))
+            .versions
+            .insert(version_key.version, version);
+    }
+
+    let metas = dbtx
+        .find_by_prefix(&EntryKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error"))
+        .collect::<Vec<_>>();
+    for (EntryKey(key), meta) in metas {
+        entries
+            .entry(key.clone())
+            .or_insert_with(|| ArchivedEntry::empty(&key))
+            .meta = Some(meta);
+    }
+
+    let public = dbtx
+        .find_by_prefix(&PublicEntryKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error").0 .0)
+        .collect::<Vec<_>>();
+    for key in public {
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.public = entry.meta.is_some();
+        }
+    }
+
+    let owners = dbtx
+        .find_by_prefix(&EntryOwnerKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error"))
+        .collect::<Vec<_>>();
+    for (EntryOwnerKey(key), owner) in owners {
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.owner = Some(owner);
+        }
+    }
+
+    entries.into_values().collect()
+}
+
+/// Writes all entries and the blobs of their retained versions to a new archive
+/// in `dir`
+///
+/// Fails if a blob is missing or corrupt, it has to be repaired first.
+pub async fn export_archive(
+    dbtx: &mut DatabaseTransaction<'_>,
+    blobs: &BlobStore,
+    dir: &Path,
+    our_id: PeerId,
+    root_key: &SecretKey,
+) -> anyhow::Result<ArchiveManifest> {
+    if dir.join(MANIFEST_FILE).exists() {
+        bail!("{} already contains an archive", dir.display());
+    }
+    let archive_blobs = BlobStore::open(dir.join(ARCHIVE_BLOB_DIR))?;
+
+    let entries = archived_entries(dbtx).await;
+    for entry in &entries {
+        for version in entry.versions.values() {
+            let bytes = blobs
+                .get(&version.hash)?
+                .ok_or_else(|| format_err!("Blob {} of {} is missing", version.hash, entry.key))?;
+            ensure!(
+                sha256::Hash::hash(&bytes) == version.hash,
+                "Blob {} of {} is corrupt",
+                version.hash,
+                entry.key
+            );
+            archive_blobs.put(&bytes)?;
+        }
+    }
+
+    let state_root = state_root(&entries);
+    let secp = Secp256k1::new();
+    let key = KeyPair::from_secret_key(&secp, root_key);
+    let public_root = merkle_root(&public_leaves(&entries));
+    let signed_root = dbtx
+        .get_value(&SignedRootKey)
+        .await
+        .expect("DB Error")
+        .filter(|signed| signed.root == public_root);
+
+    let manifest = ArchiveManifest {
+        exported_by: our_id,
+        entries,
+        state_root,
+        state_root_signature: secp.sign_schnorr(&state_root_message(&state_root), &key),
+        signed_root,
+    };
+    fs::write(
+        dir.join(MANIFEST_FILE),
+        serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+/// Verifies the archive in `dir` and writes its entries into the module's
+/// database and its blobs into `blobs`
+///
+/// Only empty databases can be imported into. Blobs are copied before anything
+/// is written to `dbtx`, so if this fails the transaction should be dropped; the
+/// copied blobs are unreferenced and harmless.
+pub async fn import_archive(
+    dbtx: &mut DatabaseTransaction<'_>,
+    blobs: &BlobStore,
+    dir: &Path,
+    root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+) -> anyhow::Result<ArchiveManifest> {
+    let manifest: ArchiveManifest = serde_json::from_slice(&fs::read(dir.join(MANIFEST_FILE))?)?;
+    manifest.verify(&Secp256k1::verification_only(), root_keys)?;
+
+    ensure!(
+        dbtx.find_by_prefix(&EntryVersionKeyPrefix)
+            .await
+            .next()
+            .is_none(),
+        "Database already contains smolfs entries"
+    );
+
+    let archive_blobs = BlobStore::open(dir.join(ARCHIVE_BLOB_DIR))?;
+    let mut copied = BTreeSet::new();
+    for entry in &manifest.entries {
+        for version in entry.versions.values() {
+            if !copied.insert(version.hash) {
+                continue;
+            }
+
+            let bytes = archive_blobs
+                .get(&version.hash)?
+                .ok_or_else(|| format_err!("Blob {} of {} is missing", version.hash, entry.key))?;
+            ensure!(
+                sha256::Hash::hash(&bytes) == version.hash && bytes.len() as u64 == version.size,
+                "Blob {} of {} doesn't match the manifest",
+                version.hash,
+                entry.key
+            );
+            blobs.put(&bytes)?;
+        }
+    }
+
+    insert_entries(dbtx, &manifest.entries).await;
+    if let Some(signed_root) = &manifest.signed_root {
+        dbtx.insert_entry(&SignedRootKey, signed_root)
+            .await
+            .expect("DB Error");
+    }
+
+    Ok(manifest)
+}
+
+/// Writes `entries` into a module database that doesn't contain any entries,
+/// counting the references to every blob
+pub(crate) async fn insert_entries(dbtx: &mut DatabaseTransaction<'_>, entries: &[ArchivedEntry]) {
+    let mut blob_refs: BTreeMap<sha256::Hash, u64> = BTreeMap::new();
+    for entry in entries {
+        for (version, meta) in &entry.versions {
+            dbtx.insert_new_entry(
+                &EntryVersionKey {
+                    key: entry.key.clone(),
+                    version: *version,
+                },
+                meta,
+            )
+            .await
+            .expect("DB Error");
+            *blob_refs.entry(meta.hash).or_default() += 1;
+        }
+        if let Some(meta) = &entry.meta {
+            dbtx.insert_new_entry(&EntryKey(entry.key.clone()), meta)
+                .await
+                .expect("DB Error");
+        }
+        if entry.public {
+            dbtx.insert_new_entry(&PublicEntryKey(entry.key.clone()), &())
+                .await
+                .expect("DB Error");
+        }
+        if let Some(owner) = &entry.owner {
+            dbtx.insert_new_entry(&EntryOwnerKey(entry.key.clone()), owner)
+                .await
+                .expect("DB Error");
+        }
+    }
+    for (hash, refs) in blob_refs {
+        dbtx.insert_new_entry(&BlobRefKey(hash), &refs)
+            .await
+            .expect("DB Error");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::SystemTime;
+
+    use bitcoin::hashes::{sha256, Hash};
+    use fedimint_api::PeerId;
+    use secp256k1::{KeyPair, Secp256k1};
+
+    use super::{state_root, state_root_message, ArchiveManifest, ArchivedEntry};
+    use crate::common::{EntryMeta, VersionMeta};
+
+    fn entry(key: &str, payload: &[u8]) -> ArchivedEntry {
+        let hash = sha256::Hash::hash(payload);
+        let size = payload.len() as u64;
+        ArchivedEntry {
+            key: key.to_string(),
+            meta: Some(EntryMeta {
+                hash,
+                size,
+                version: 0,
+            }),
+            public: false,
+            owner: None,
+            versions: BTreeMap::from([(
+                0,
+                VersionMeta {
+                    hash,
+                    size,
+                    timestamp: SystemTime::UNIX_EPOCH,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn manifest_verification_detects_tampering() {
+        let secp = Secp256k1::new();
+        let key = KeyPair::from_seckey_slice(&secp, &[1; 32]).unwrap();
+        let root_keys = BTreeMap::from([(PeerId::from(0), key.x_only_public_key().0)]);
+
+        let entries = vec![entry("a", b"foo"), entry("b", b"bar")];
+        let state_root = state_root(&entries);
+        let manifest = ArchiveManifest {
+            exported_by: PeerId::from(0),
+            entries,
+            state_root,
+            state_root_signature: secp.sign_schnorr(&state_root_message(&state_root), &key),
+            signed_root: None,
+        };
+        manifest.verify(&secp, &root_keys).unwrap();
+
+        let mut tampered = manifest.clone();
+        tampered.entries[1] = entry("b", b"baz");
+        assert!(tampered.verify(&secp, &root_keys).is_err());
+
+        let mut tampered = manifest.clone();
+        tampered.entries[0].owner = Some(key.x_only_public_key().0);
+        assert!(tampered.verify(&secp, &root_keys).is_err());
+
+        let mut unknown_exporter = manifest.clone();
+        unknown_exporter.exported_by = PeerId::from(1);
+        assert!(unknown_exporter.verify(&secp, &root_keys).is_err());
+
+        let mut unsorted = manifest;
+        unsorted.entries.reverse();
+        assert!(unsorted.verify(&secp, &root_keys).is_err());
+    }
+}
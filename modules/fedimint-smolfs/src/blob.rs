@@ -0,0 +1,249 @@
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bitcoin::hashes::{sha256, Hash};
+use fedimint_api::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
+
+/// Directory inside the blob store used to stage writes before they are
+/// atomically moved into place
+const TMP_DIR: &str = "tmp";
+
+/// Content-addressed store keeping smolfs payloads as files on disk
+///
+/// Blobs are named after the hex encoded sha256 hash of their contents and
+/// sharded into sub-directories by the first byte of the hash, so a blob with
+/// hash `abcd…` lives at `<dir>/ab/abcd…`. Since the name is derived from the
+/// content, writing the same blob twice is a no-op.
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    dir: PathBuf,
+    /// Bytes taken up by the blobs, shared between clones of the store
+    usage: Arc<AtomicU64>,
+}
+
+/// Result of checking a single blob against its expected hash
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum BlobStatus {
+    Ok,
+    Missing,
+    Corrupt,
+}
+
+impl BlobStore {
+    /// Opens the blob store at `dir`, creating the directory if it doesn't exist
+    ///
+    /// Walks all stored blobs once to find out how much space they take up, which is then kept
+    /// up to date as blobs are added and removed through this store or its clones.
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<BlobStore> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join(TMP_DIR))?;
+        let usage = Arc::new(AtomicU64::new(walk_disk_usage(&dir)?));
+        Ok(BlobStore { dir, usage })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Writes `bytes` to the store and returns their hash
+    pub fn put(&self, bytes: &[u8]) -> io::Result<sha256::Hash> {
+        let hash = sha256::Hash::hash(bytes);
+        if self.check(&hash)? == BlobStatus::Ok {
+            return Ok(hash);
+        }
+
+        let tmp_path = self.staging_path(&format!("{}.{}", hash, rand::random::<u64>()));
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        self.insert_staged(&tmp_path, &hash)?;
+
+        Ok(hash)
+    }
+
+    /// Removes everything left in the staging directory, only safe while nothing is being staged
+    ///
+    /// Files staged before a restart belong to upload sessions or writes that no longer exist.
+    pub fn clear_staging(&self) -> io::Result<()> {
+        for staged in fs::read_dir(self.dir.join(TMP_DIR))? {
+            match fs::remove_file(staged?.path()) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Path for staging a blob under the given name before it is inserted
+    pub fn staging_path(&self, name: &str) -> PathBuf {
+        self.dir.join(TMP_DIR).join(name)
+    }
+
+    /// Moves a staged file into the store under `hash`, the caller is
+    /// responsible for making sure the contents match the hash
+    pub fn insert_staged(&self, staging_path: &Path, hash: &sha256::Hash) -> io::Result<()> {
+        let staged = fs::File::open(staging_path)?;
+        staged.sync_all()?;
+        let size = staged.metadata()?.len();
+        let path = self.path(hash);
+        fs::create_dir_all(path.parent().expect("Blob paths always have a parent"))?;
+        let replaced = file_size(&path)?;
+        fs::rename(staging_path, path)?;
+
+        self.usage.fetch_add(size, Ordering::Relaxed);
+        self.sub_usage(replaced);
+        Ok(())
+    }
+
+    /// Reads the blob with the given hash, returns `None` if it isn't stored
+    ///
+    /// The contents are not verified against the hash, use [`BlobStore::check`]
+    /// for that.
+    pub fn get(&self, hash: &sha256::Hash) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.path(hash)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reads up to `len` bytes starting at `offset` of the blob with the given
+    /// hash, returns `None` if it isn't stored
+    pub fn read_range(
+        &self,
+        hash: &sha256::Hash,
+        offset: u64,
+        len: u64,
+    ) -> io::Result<Option<Vec<u8>>> {
+        let mut file = match fs::File::open(self.path(hash)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+        let mut bytes = vec![];
+        file.take(len).read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+
+    /// Removes the blob with the given hash if it exists
+    pub fn remove(&self, hash: &sha256::Hash) -> io::Result<()> {
+        let path = self.path(hash);
+        let size = file_size(&path)?;
+        match fs::remove_file(path) {
+            Ok(()) => {
+                self.sub_usage(size);
+                Ok(())
+            }
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Re-hashes the stored blob and compares it with the hash it is stored under
+    pub fn check(&self, hash: &sha256::Hash) -> io::Result<BlobStatus> {
+        Ok(match self.get(hash)? {
+            None => BlobStatus::Missing,
+            Some(bytes) if sha256::Hash::hash(&bytes) == *hash => BlobStatus::Ok,
+            Some(_) => BlobStatus::Corrupt,
+        })
+    }
+
+    /// Total size in bytes of all blobs in the store
+    ///
+    /// Tracked as blobs are written and removed, so it doesn't account for files changed by
+    /// other processes since the store was opened.
+    pub fn disk_usage(&self) -> u64 {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    fn sub_usage(&self, size: u64) {
+        // Saturating, the files could have been changed by somebody else in the meantime
+        let _ = self
+            .usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |usage| {
+                Some(usage.saturating_sub(size))
+            });
+    }
+
+    fn path(&self, hash: &sha256::Hash) -> PathBuf {
+        let hex = hash.to_string();
+        self.dir.join(&hex[..2]).join(hex)
+    }
+}
+
+/// Size of the file at `path`, `0` if it doesn't exist
+fn file_size(path: &Path) -> io::Result<u64> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Adds up the sizes of all blobs stored in `dir`
+fn walk_disk_usage(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for shard in fs::read_dir(dir)? {
+        let shard = shard?;
+        if !shard.file_type()?.is_dir() || shard.file_name() == TMP_DIR {
+            continue;
+        }
+        for blob in fs::read_dir(shard.path())? {
+            total += blob?.metadata()?.len();
+        }
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::{BlobStatus, BlobStore};
+
+    fn temp_store() -> BlobStore {
+        let dir = std::env::temp_dir().join(format!("smolfs-blobs-{}", rand::random::<u64>()));
+        BlobStore::open(dir).unwrap()
+    }
+
+    #[test]
+    fn put_get_roundtrip() {
+        let store = temp_store();
+        let hash = store.put(b"hello smolfs").unwrap();
+
+        assert_eq!(hash, sha256::Hash::hash(b"hello smolfs"));
+        assert_eq!(store.get(&hash).unwrap().unwrap(), b"hello smolfs");
+        assert_eq!(store.check(&hash).unwrap(), BlobStatus::Ok);
+        assert_eq!(store.disk_usage(), 12);
+
+        // Writing the blob again doesn't count it twice, reopening the store finds it on disk
+        store.put(b"hello smolfs").unwrap();
+        assert_eq!(store.disk_usage(), 12);
+        assert_eq!(BlobStore::open(store.dir()).unwrap().disk_usage(), 12);
+
+        store.remove(&hash).unwrap();
+        assert_eq!(store.disk_usage(), 0);
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn detects_missing_and_corrupt_blobs() {
+        let store = temp_store();
+        let hash = store.put(b"some data").unwrap();
+        fs::write(store.path(&hash), b"other data").unwrap();
+        assert_eq!(store.check(&hash).unwrap(), BlobStatus::Corrupt);
+
+        store.remove(&hash).unwrap();
+        assert_eq!(store.check(&hash).unwrap(), BlobStatus::Missing);
+        assert_eq!(store.get(&hash).unwrap(), None);
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+}
@@ -0,0 +1,725 @@
+//! Catch-up of a guardian that replaced another one and starts out without any
+//! smolfs data
+//!
+//! A guardian that sets `catch_up` in its local config fetches all entries from
+//! its repair peers before it answers any reads:
+//!
+//! 1. Every guardian attests its current state root by signing it with its root
+//!    key, which is served by `/state_root`. The root commits to the entries
+//!    (see [`crate::archive`]) and to the rest of the consensus state in a
+//!    [`ConsensusSnapshot`]. Once [`NumPeers::one_honest`] guardians attest the
+//!    same root, at least one honest guardian vouches for it.
+//! 2. The entries are streamed page by page from `/catchup_entries` of the
+//!    guardians that attested the root, followed by the snapshot from
+//!    `/catchup_snapshot`, and have to hash to exactly that root.
+//! 3. The blobs of all retained versions are fetched through `/fetch_blob` and
+//!    checked against their hash, like damaged blobs are repaired. The guardian
+//!    is part of the federation already, so its peers serve it the blobs.
+//! 4. If the peers still attest the same root, the received entries and snapshot
+//!    replace all of our consensus state in a single transaction. Otherwise the
+//!    federation processed epochs in the meantime and the next attempt catches
+//!    up to the new root, blobs fetched so far are kept.
+//!
+//! Consensus keeps applying inputs while we catch up, on whatever state we had
+//! before. The snapshot is taken after the same epoch we processed last, which
+//! the transaction replacing our state checks, so everything applied until then
+//! is overwritten with the state our peers agree on and the following epochs
+//! continue from it.
+//!
+//! Until then every endpoint reading entries fails with status 503, progress is
+//! reported by `/catchup_status`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{ensure, format_err};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+use fedimint_api::db::{Database, DatabaseTransaction};
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::module::ApiError;
+use fedimint_api::task::TaskHandle;
+use fedimint_api::{Amount, NumPeers, OutPoint, PeerId};
+use secp256k1::{schnorr, KeyPair, Secp256k1, SecretKey, Signing, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::archive::{
+    archived_entries, insert_entries, state_root, state_root_message, verify_entries, ArchivedEntry,
+};
+use crate::blob::{BlobStatus, BlobStore};
+use crate::common::ChangeEvent;
+use crate::db::{
+    BlobRefKey, BlobRefKeyPrefix, CatchUpStatus, CatchUpStatusKey, ChangeKey, ChangeKeyPrefix,
+    ChangeSeqKey, DamagedBlobKeyPrefix, EntryKey, EntryKeyPrefix, EntryOwnerKey,
+    EntryOwnerKeyPrefix, EntryVersionKeyPrefix, EpochCountKey, PendingBlobGcKey, PrepaidBalanceKey,
+    PrepaidBalanceKeyPrefix, PrepayOutcomeKey, PrepayOutcomeKeyPrefix, PublicEntryKey,
+    PublicEntryKeyPrefix, Tombstone, TombstoneKey, TombstoneKeyPrefix,
+};
+use crate::repair::{fetch_from_peers, request_peer, shutdown_signal, sleep_until_shutdown};
+use crate::SmolFSOutputOutcome;
+
+/// Maximum number of entries returned by a single `/catchup_entries` request
+pub const CATCH_UP_PAGE_SIZE: usize = 1000;
+/// How long to wait before retrying after a failed attempt
+const CATCH_UP_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Number of fetched blobs after which progress is recorded
+const BLOB_PROGRESS_INTERVAL: u64 = 100;
+
+/// A guardian's signature over the state root of all of its entries
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AttestedStateRoot {
+    pub root: sha256::Hash,
+    pub signature: schnorr::Signature,
+}
+
+impl AttestedStateRoot {
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        root: sha256::Hash,
+        root_key: &SecretKey,
+    ) -> AttestedStateRoot {
+        let key = KeyPair::from_secret_key(secp, root_key);
+        AttestedStateRoot {
+            root,
+            signature: secp.sign_schnorr(&state_root_message(&root), &key),
+        }
+    }
+
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, key: &XOnlyPublicKey) -> bool {
+        secp.verify_schnorr(&self.signature, &state_root_message(&self.root), key)
+            .is_ok()
+    }
+}
+
+/// Requests the entries following `after` in database order, starting with the
+/// first entry if it is `None`
+///
+/// Keys are stored behind their encoded length, so the entries of different pages
+/// aren't sorted by key and have to be sorted once all pages are received.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CatchUpEntriesRequest {
+    pub after: Option<String>,
+}
+
+/// Consensus state besides the entries, copied by a catching up guardian
+///
+/// Guardians that processed the same epochs hold the same snapshot, so it is
+/// covered by the attested state root together with the entries.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ConsensusSnapshot {
+    /// Number of epochs processed, see [`EpochCountKey`]
+    pub epoch: u64,
+    /// Sequence number of the latest change, see [`ChangeSeqKey`]
+    pub change_seq: u64,
+    /// Changes still in the change log, sorted by sequence number
+    pub changes: Vec<ChangeEvent>,
+    pub balances: BTreeMap<XOnlyPublicKey, Amount>,
+    pub tombstones: BTreeMap<String, Tombstone>,
+    /// Prepayments whose outcome can be queried
+    pub prepayments: Vec<OutPoint>,
+}
+
+/// Our current consensus state besides the entries
+pub async fn consensus_snapshot(dbtx: &mut DatabaseTransaction<'_>) -> ConsensusSnapshot {
+    let epoch = dbtx
+        .get_value(&EpochCountKey)
+        .await
+        .expect("DB Error")
+        .unwrap_or(0);
+    let change_seq = dbtx
+        .get_value(&ChangeSeqKey)
+        .await
+        .expect("DB Error")
+        .unwrap_or(0);
+    let mut changes = dbtx
+        .find_by_prefix(&ChangeKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error").1)
+        .collect::<Vec<_>>();
+    changes.sort_by_key(|change| change.seq);
+    let balances = dbtx
+        .find_by_prefix(&PrepaidBalanceKeyPrefix)
+        .await
+        .map(|res| {
+            let (PrepaidBalanceKey(owner), balance) = res.expect("DB Error");
+            (owner, balance)
+        })
+        .collect();
+    let tombstones = dbtx
+        .find_by_prefix(&TombstoneKeyPrefix)
+        .await
+        .map(|res| {
+            let (TombstoneKey(key), tombstone) = res.expect("DB Error");
+            (key, tombstone)
+        })
+        .collect();
+    let prepayments = dbtx
+        .find_by_prefix(&PrepayOutcomeKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error").0 .0)
+        .collect();
+    ConsensusSnapshot {
+        epoch,
+        change_seq,
+        changes,
+        balances,
+        tombstones,
+        prepayments,
+    }
+}
+
+/// Root over the entries, which have to be sorted by key, and the snapshot of the
+/// remaining consensus state
+pub fn catch_up_root(entries: &[ArchivedEntry], snapshot: &ConsensusSnapshot) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&state_root(entries)[..]);
+    snapshot
+        .consensus_encode(&mut engine)
+        .expect("Hashing never fails");
+    sha256::Hash::from_engine(engine)
+}
+
+/// Our current state root, signed with our root key
+pub async fn attest_state_root<C: Signing>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    secp: &Secp256k1<C>,
+    root_key: &SecretKey,
+) -> AttestedStateRoot {
+    let entries = archived_entries(dbtx).await;
+    let snapshot = consensus_snapshot(dbtx).await;
+    AttestedStateRoot::new(secp, catch_up_root(&entries, &snapshot), root_key)
+}
+
+/// At most [`CATCH_UP_PAGE_SIZE`] entries following `request.after`
+///
+/// Only the keys before the page are skipped, the entries themselves are built
+/// just for the page.
+pub async fn entries_page(
+    dbtx: &mut DatabaseTransaction<'_>,
+    request: CatchUpEntriesRequest,
+) -> Vec<ArchivedEntry> {
+    // Every entry retains at least its current version, so the version keys cover
+    // all entries
+    let mut page: Vec<ArchivedEntry> = vec![];
+    let versions = dbtx
+        .find_by_prefix(&EntryVersionKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error"))
+        .skip_while(|(version_key, _)| {
+            request
+                .after
+                .as_ref()
+                .map_or(false, |after| !is_after(&version_key.key, after))
+        });
+    for (version_key, version) in versions {
+        if page.last().map(|entry| &entry.key) != Some(&version_key.key) {
+            if page.len() == CATCH_UP_PAGE_SIZE {
+                break;
+            }
+            page.push(ArchivedEntry::empty(&version_key.key));
+        }
+        page.last_mut()
+            .expect("Pushed above")
+            .versions
+            .insert(version_key.version, version);
+    }
+
+    for entry in &mut page {
+        entry.meta = dbtx
+            .get_value(&EntryKey(entry.key.clone()))
+            .await
+            .expect("DB Error");
+        entry.public = entry.meta.is_some()
+            && dbtx
+                .get_value(&PublicEntryKey(entry.key.clone()))
+                .await
+                .expect("DB Error")
+                .is_some();
+        entry.owner = dbtx
+            .get_value(&EntryOwnerKey(entry.key.clone()))
+            .await
+            .expect("DB Error");
+    }
+    page
+}
+
+/// Whether `key` comes after `after` in database order, see [`CatchUpEntriesRequest`]
+fn is_after(key: &str, after: &str) -> bool {
+    ((key.len() as u64).to_le_bytes(), key.as_bytes())
+        > ((after.len() as u64).to_le_bytes(), after.as_bytes())
+}
+
+/// Fails with status 503 if catching up is enabled and didn't finish yet
+pub async fn ensure_caught_up(
+    dbtx: &mut DatabaseTransaction<'_>,
+    enabled: bool,
+) -> Result<(), ApiError> {
+    if !enabled {
+        return Ok(());
+    }
+    let completed = dbtx
+        .get_value(&CatchUpStatusKey)
+        .await
+        .expect("DB Error")
+        .map_or(false, |status| status.completed.is_some());
+    if !completed {
+        return Err(ApiError::new(
+            503,
+            "Still catching up with the other guardians".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Root attested by at least [`NumPeers::one_honest`] guardians, together with
+/// the guardians that attested it
+///
+/// Attestations with invalid signatures or from unknown guardians are ignored.
+/// If more than one root reaches the threshold the one attested most often
+/// wins.
+pub fn agreed_root<C: Verification>(
+    secp: &Secp256k1<C>,
+    root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+    attestations: &BTreeMap<PeerId, AttestedStateRoot>,
+) -> Option<(sha256::Hash, Vec<PeerId>)> {
+    let mut attesters: BTreeMap<sha256::Hash, Vec<PeerId>> = BTreeMap::new();
+    for (peer, attestation) in attestations {
+        let Some(key) = root_keys.get(peer) else {
+            warn!(%peer, "State root attested by unknown peer");
+            continue;
+        };
+        if !attestation.verify(secp, key) {
+            warn!(%peer, "Invalid state root attestation");
+            continue;
+        }
+        attesters.entry(attestation.root).or_default().push(*peer);
+    }
+
+    let threshold = root_keys.one_honest();
+    attesters
+        .into_iter()
+        .filter(|(_, peers)| peers.len() >= threshold)
+        .max_by_key(|(_, peers)| peers.len())
+}
+
+/// Retries catching up until it succeeds or the task group shuts down
+pub async fn run_catch_up(
+    db: Database,
+    blobs: BlobStore,
+    root_keys: BTreeMap<PeerId, XOnlyPublicKey>,
+    peers: BTreeMap<PeerId, Url>,
+    root_key: SecretKey,
+    handle: &TaskHandle,
+) {
+    let secp = Secp256k1::verification_only();
+    let mut shutdown = shutdown_signal(handle).await;
+    while !handle.is_shutting_down() {
+        let mut dbtx = db.begin_transaction().await;
+        let status = dbtx
+            .with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
+            .get_value(&CatchUpStatusKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or_default();
+        drop(dbtx);
+        if status.completed.is_some() {
+            return;
+        }
+
+        match catch_up(&db, &blobs, &secp, &root_keys, &peers, &root_key).await {
+            Ok(root) => {
+                info!(%root, "Caught up with the other guardians");
+                return;
+            }
+            Err(e) => {
+                warn!("Failed to catch up with the other guardians: {}", e);
+                update_status(&db, |status| status.last_error = Some(e.to_string())).await;
+            }
+        }
+        if !sleep_until_shutdown(&mut shutdown, CATCH_UP_RETRY_INTERVAL).await {
+            break;
+        }
+    }
+}
+
+/// Runs a single attempt, returning the root we caught up to
+async fn catch_up<C: Verification>(
+    db: &Database,
+    blobs: &BlobStore,
+    secp: &Secp256k1<C>,
+    root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+    peers: &BTreeMap<PeerId, Url>,
+    root_key: &SecretKey,
+) -> anyhow::Result<sha256::Hash> {
+    let (root, attesters) = fetch_agreed_root(secp, root_keys, peers).await?;
+    update_status(db, |status| {
+        *status = CatchUpStatus {
+            state_root: Some(root),
+            attempts: status.attempts + 1,
+            ..CatchUpStatus::default()
+        }
+    })
+    .await;
+
+    let mut state = None;
+    for peer in &attesters {
+        match fetch_state(db, &peers[peer], root).await {
+            Ok(fetched) => {
+                state = Some(fetched);
+                break;
+            }
+            Err(e) => debug!(%peer, "Failed to fetch state from peer: {}", e),
+        }
+    }
+    let (entries, snapshot) =
+        state.ok_or_else(|| format_err!("No peer delivered the state of root {root}"))?;
+
+    let hashes = entries
+        .iter()
+        .flat_map(|entry| entry.versions.values().map(|version| version.hash))
+        .collect::<BTreeSet<_>>();
+    update_status(db, |status| status.blobs_total = hashes.len() as u64).await;
+    let mut fetched = 0;
+    for hash in &hashes {
+        if blobs.check(hash)? != BlobStatus::Ok {
+            fetch_from_peers(blobs, peers, root_key, hash)
+                .await
+                .ok_or_else(|| format_err!("No peer delivered blob {hash}"))?;
+        }
+        fetched += 1;
+        if fetched % BLOB_PROGRESS_INTERVAL == 0 {
+            update_status(db, |status| status.blobs_fetched = fetched).await;
+        }
+    }
+
+    let (current, _) = fetch_agreed_root(secp, root_keys, peers).await?;
+    ensure!(
+        current == root,
+        "State root changed from {root} to {current} while catching up"
+    );
+    replace_state(db, &entries, &snapshot, hashes.len() as u64).await?;
+    Ok(root)
+}
+
+/// Asks all peers for their state root until enough of them agree on one
+async fn fetch_agreed_root<C: Verification>(
+    secp: &Secp256k1<C>,
+    root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+    peers: &BTreeMap<PeerId, Url>,
+) -> anyhow::Result<(sha256::Hash, Vec<PeerId>)> {
+    let mut attestations = BTreeMap::new();
+    for (peer, url) in peers {
+        match request_peer::<_, AttestedStateRoot>(url, "state_root", &()).await {
+            Ok(attestation) => {
+                attestations.insert(*peer, attestation);
+            }
+            Err(e) => debug!(%peer, "Failed to fetch state root from peer: {}", e),
+        }
+    }
+    agreed_root(secp, root_keys, &attestations)
+        .ok_or_else(|| format_err!("Not enough peers attest the same state root"))
+}
+
+/// Streams all entries and the consensus snapshot from a peer and checks that
+/// they hash to `root`
+async fn fetch_state(
+    db: &Database,
+    url: &Url,
+    root: sha256::Hash,
+) -> anyhow::Result<(Vec<ArchivedEntry>, ConsensusSnapshot)> {
+    let mut entries: Vec<ArchivedEntry> = vec![];
+    loop {
+        let request = CatchUpEntriesRequest {
+            after: entries.last().map(|entry| entry.key.clone()),
+        };
+        let page: Vec<ArchivedEntry> = request_peer(url, "catchup_entries", &request).await?;
+        ensure!(
+            page.len() <= CATCH_UP_PAGE_SIZE,
+            "Peer returned {} entries in a single page",
+            page.len()
+        );
+        let done = page.len() < CATCH_UP_PAGE_SIZE;
+        entries.extend(page);
+        let received = entries.len() as u64;
+        update_status(db, |status| status.entries_received = received).await;
+        if done {
+            break;
+        }
+    }
+    let snapshot: ConsensusSnapshot = request_peer(url, "catchup_snapshot", &()).await?;
+
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    verify_entries(&entries)?;
+    ensure!(
+        catch_up_root(&entries, &snapshot) == root,
+        "Entries and snapshot don't match the state root"
+    );
+    Ok((entries, snapshot))
+}
+
+/// Replaces all of our consensus state and marks catching up as completed
+///
+/// Fails if we didn't process exactly the epochs the snapshot was taken after.
+async fn replace_state(
+    db: &Database,
+    entries: &[ArchivedEntry],
+    snapshot: &ConsensusSnapshot,
+    blobs_total: u64,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+    {
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        let epoch = dbtx
+            .get_value(&EpochCountKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        ensure!(
+            epoch == snapshot.epoch,
+            "Processed {epoch} epochs, but the state root is from epoch {}",
+            snapshot.epoch
+        );
+
+        let old_blobs = dbtx
+            .find_by_prefix(&BlobRefKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error").0 .0)
+            .collect::<Vec<_>>();
+        dbtx.remove_by_prefix(&EntryVersionKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&EntryKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&PublicEntryKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&EntryOwnerKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&BlobRefKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&ChangeKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&PrepaidBalanceKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&PrepayOutcomeKeyPrefix)
+            .await
+            .expect("DB Error");
+        dbtx.remove_by_prefix(&TombstoneKeyPrefix)
+            .await
+            .expect("DB Error");
+        // All blobs of the received entries were fetched and checked above
+        dbtx.remove_by_prefix(&DamagedBlobKeyPrefix)
+            .await
+            .expect("DB Error");
+        insert_entries(&mut dbtx, entries).await;
+
+        // Blobs only our previous state referenced are deleted with the next epoch,
+        // blobs already queued for deletion stay queued
+        for hash in old_blobs {
+            if dbtx
+                .get_value(&BlobRefKey(hash))
+                .await
+                .expect("DB Error")
+                .is_none()
+            {
+                dbtx.insert_entry(&PendingBlobGcKey(hash), &())
+                    .await
+                    .expect("DB Error");
+            }
+        }
+
+        // Also written by every epoch, so an epoch committed in the meantime
+        // conflicts with this transaction
+        dbtx.insert_entry(&EpochCountKey, &snapshot.epoch)
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(&ChangeSeqKey, &snapshot.change_seq)
+            .await
+            .expect("DB Error");
+        for change in &snapshot.changes {
+            dbtx.insert_entry(&ChangeKey(change.seq), change)
+                .await
+                .expect("DB Error");
+        }
+        for (owner, balance) in &snapshot.balances {
+            dbtx.insert_entry(&PrepaidBalanceKey(*owner), balance)
+                .await
+                .expect("DB Error");
+        }
+        for (key, tombstone) in &snapshot.tombstones {
+            dbtx.insert_entry(&TombstoneKey(key.clone()), tombstone)
+                .await
+                .expect("DB Error");
+        }
+        for out_point in &snapshot.prepayments {
+            dbtx.insert_entry(&PrepayOutcomeKey(*out_point), &SmolFSOutputOutcome)
+                .await
+                .expect("DB Error");
+        }
+
+        let mut status = dbtx
+            .get_value(&CatchUpStatusKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or_default();
+        status.completed = Some(SystemTime::now());
+        status.entries_received = entries.len() as u64;
+        status.blobs_fetched = blobs_total;
+        status.last_error = None;
+        dbtx.insert_entry(&CatchUpStatusKey, &status)
+            .await
+            .expect("DB Error");
+    }
+    // Conflicts with a consensus epoch committed at the same time, in which case
+    // the next attempt catches up to the new root
+    dbtx.commit_tx().await
+}
+
+async fn update_status(db: &Database, update: impl FnOnce(&mut CatchUpStatus)) {
+    let mut dbtx = db.begin_transaction().await;
+    {
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        let mut status = dbtx
+            .get_value(&CatchUpStatusKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or_default();
+        update(&mut status);
+        dbtx.insert_entry(&CatchUpStatusKey, &status)
+            .await
+            .expect("DB Error");
+    }
+    if let Err(e) = dbtx.commit_tx().await {
+        // Only the reported progress is lost, the next update overwrites it
+        warn!("Failed to record catch-up progress: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::SystemTime;
+
+    use bitcoin::hashes::{sha256, Hash};
+    use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+    use fedimint_api::db::mem_impl::MemDatabase;
+    use fedimint_api::db::Database;
+    use fedimint_api::module::registry::ModuleDecoderRegistry;
+    use fedimint_api::PeerId;
+    use secp256k1::{KeyPair, Secp256k1, SecretKey};
+
+    use super::{agreed_root, entries_page, AttestedStateRoot, CatchUpEntriesRequest};
+    use crate::archive::{archived_entries, insert_entries, ArchivedEntry};
+    use crate::common::{EntryMeta, SmolFSDecoder, VersionMeta};
+
+    #[test]
+    fn agreed_root_requires_one_honest_peer() {
+        let secp = Secp256k1::new();
+        let keys = (0..4u8)
+            .map(|i| SecretKey::from_slice(&[i + 1; 32]).unwrap())
+            .collect::<Vec<_>>();
+        let root_keys = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let pk = KeyPair::from_secret_key(&secp, key).x_only_public_key().0;
+                (PeerId::from(i as u16), pk)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let root = sha256::Hash::hash(b"root");
+        let other = sha256::Hash::hash(b"other");
+
+        // With four guardians one of them may be malicious, so its word alone
+        // isn't enough
+        let mut attestations = BTreeMap::from([
+            (
+                PeerId::from(1),
+                AttestedStateRoot::new(&secp, root, &keys[1]),
+            ),
+            (
+                PeerId::from(2),
+                AttestedStateRoot::new(&secp, other, &keys[2]),
+            ),
+        ]);
+        assert_eq!(agreed_root(&secp, &root_keys, &attestations), None);
+
+        attestations.insert(
+            PeerId::from(3),
+            AttestedStateRoot::new(&secp, root, &keys[3]),
+        );
+        assert_eq!(
+            agreed_root(&secp, &root_keys, &attestations),
+            Some((root, vec![PeerId::from(1), PeerId::from(3)]))
+        );
+
+        // Signed with the wrong key
+        attestations.insert(
+            PeerId::from(3),
+            AttestedStateRoot::new(&secp, root, &keys[2]),
+        );
+        assert_eq!(agreed_root(&secp, &root_keys, &attestations), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn entries_pages_cover_all_entries() {
+        let db = Database::new(
+            MemDatabase::new(),
+            ModuleDecoderRegistry::from_iter([(
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+                SmolFSDecoder.into(),
+            )]),
+        );
+        // Keys of different lengths, so database order differs from key order
+        let entries = (0..2500u64)
+            .map(|i| {
+                let hash = sha256::Hash::hash(&i.to_le_bytes());
+                let versions = (0..=i % 3)
+                    .map(|version| {
+                        let meta = VersionMeta {
+                            hash,
+                            size: 8,
+                            timestamp: SystemTime::UNIX_EPOCH,
+                        };
+                        (version, meta)
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                ArchivedEntry {
+                    key: format!("{}", i * 7919),
+                    meta: (i % 5 != 0).then(|| EntryMeta {
+                        hash,
+                        size: 8,
+                        version: i % 3,
+                    }),
+                    public: i % 2 == 0 && i % 5 != 0,
+                    owner: None,
+                    versions,
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut dbtx = db.begin_transaction().await;
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        insert_entries(&mut dbtx, &entries).await;
+
+        let mut paged: Vec<ArchivedEntry> = vec![];
+        loop {
+            let request = CatchUpEntriesRequest {
+                after: paged.last().map(|entry| entry.key.clone()),
+            };
+            let page = entries_page(&mut dbtx, request).await;
+            let done = page.len() < super::CATCH_UP_PAGE_SIZE;
+            paged.extend(page);
+            if done {
+                break;
+            }
+        }
+        paged.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(paged, archived_entries(&mut dbtx).await);
+    }
+}
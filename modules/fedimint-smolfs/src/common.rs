@@ -1,10 +1,277 @@
+use std::collections::BTreeMap;
 use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use fedimint_api::core::Decoder;
-use fedimint_api::encoding::{Decodable, DecodeError};
+use fedimint_api::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_api::module::registry::ModuleDecoderRegistry;
+use fedimint_api::{Amount, PeerId};
+use secp256k1::{
+    schnorr, KeyPair, Message, Secp256k1, SecretKey, Signing, Verification, XOnlyPublicKey,
+};
+use serde::{Deserialize, Serialize};
 
-use crate::{SmolFSInput, SmolFSOutput, SmolFSOutputConfirmation, SmolFSOutputOutcome};
+use crate::{SmolFSConsensusItem, SmolFSInput, SmolFSOutput, SmolFSOutputOutcome};
+
+/// Payload bytes as returned by the read endpoints
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BlobResponse {
+    #[serde(with = "fedimint_api::hex::serde")]
+    pub bytes: Vec<u8>,
+}
+
+/// Waits for the first change to the watched entries after the change `since`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchRequest {
+    pub target: WatchTarget,
+    /// Sequence number of the last change the client has seen, `0` to get the
+    /// oldest change still in the log
+    pub since: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WatchTarget {
+    /// A single entry
+    Entry(String),
+    /// All entries whose key starts with the given prefix
+    Prefix(String),
+}
+
+impl WatchTarget {
+    pub fn matches(&self, key: &str) -> bool {
+        match self {
+            WatchTarget::Entry(entry) => entry == key,
+            WatchTarget::Prefix(prefix) => key.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchVersionRequest {
+    pub key: String,
+    pub version: u64,
+    pub offset: u64,
+    /// Number of bytes to read, at most [`MAX_CHUNK_SIZE`](crate::upload::MAX_CHUNK_SIZE)
+    pub len: u64,
+    /// Unlimited capability for the entry, needed if it is private and has an owner
+    #[serde(default)]
+    pub capability: Option<SignedReadCapability>,
+}
+
+/// Metadata of a stored entry, the payload itself lives in the guardians' blob stores
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct EntryMeta {
+    pub hash: sha256::Hash,
+    pub size: u64,
+    /// Version number of the current payload, counting up from 0 with every write
+    pub version: u64,
+}
+
+/// Retained version of an entry, the latest one is the entry's current payload
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct VersionMeta {
+    pub hash: sha256::Hash,
+    pub size: u64,
+    /// Time of the write as claimed by the client that made it
+    pub timestamp: SystemTime,
+}
+
+/// A write to an entry, as recorded in the change log
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ChangeEvent {
+    /// Position in the change log, increases by one with every write
+    pub seq: u64,
+    pub key: String,
+    /// New version of the entry
+    pub version: u64,
+    /// Hash of the new payload, all zeros if the entry was deleted
+    pub hash: sha256::Hash,
+}
+
+/// Fee for writing a payload of `size` bytes, charged per started KiB
+pub fn write_fee(fee_per_kib: Amount, size: u64) -> Amount {
+    fee_per_kib * ((size + 1023) / 1024)
+}
+
+/// Prefixed to capabilities before signing them
+const CAPABILITY_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-read-capability";
+
+/// Permission to read a single private entry, granted by the entry's owner
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct ReadCapability {
+    pub key: String,
+    /// Unix timestamp in seconds after which guardians reject the capability
+    pub expiry: u64,
+    /// Number of times the entry may be read from each guardian, unlimited if
+    /// `None`
+    pub max_reads: Option<u64>,
+}
+
+impl ReadCapability {
+    /// Message the owner signs to grant the capability
+    pub fn message(&self) -> Message {
+        Message::from_slice(&self.id()[..]).expect("hash has right length")
+    }
+
+    /// Identifies the capability when counting reads, also the signed message
+    pub fn id(&self) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        engine.input(CAPABILITY_SIGNATURE_TAG);
+        self.consensus_encode(&mut engine)
+            .expect("writing to engine can't fail");
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SignedReadCapability {
+    pub capability: ReadCapability,
+    pub signature: schnorr::Signature,
+}
+
+impl SignedReadCapability {
+    /// Checks that `owner` signed the capability
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, owner: &XOnlyPublicKey) -> bool {
+        secp.verify_schnorr(&self.signature, &self.capability.message(), owner)
+            .is_ok()
+    }
+}
+
+/// Reads `len` bytes starting at `offset` of the entry a capability was granted
+/// for
+///
+/// Guardians count the bytes they served against [`ReadCapability::max_reads`]
+/// times the entry size, so reading a large entry in chunks counts as a single
+/// read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapabilityReadRequest {
+    pub capability: SignedReadCapability,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Seconds since the unix epoch, used by signed requests that expire
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+/// Prefixed to blob requests of guardians before signing them
+const FETCH_BLOB_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-fetch-blob";
+
+/// Fetch requests signed longer ago than this are rejected, so they can't be replayed
+pub const FETCH_BLOB_REQUEST_VALIDITY: Duration = Duration::from_secs(5 * 60);
+
+/// Asks a guardian for a range of one of its blobs on behalf of another guardian repairing
+/// its store
+///
+/// Blobs are only served to guardians, everyone else has to read entries through the
+/// endpoints that check who may read them. Like ranged reads, a single request returns at
+/// most [`crate::upload::MAX_CHUNK_SIZE`] bytes, larger blobs are fetched in chunks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchBlobRequest {
+    pub hash: sha256::Hash,
+    pub offset: u64,
+    pub len: u64,
+    /// Unix timestamp in seconds the request was signed at
+    pub timestamp: u64,
+    /// Signature over the hash, range and timestamp with the requesting guardian's root key
+    pub signature: schnorr::Signature,
+}
+
+impl FetchBlobRequest {
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        root_key: &SecretKey,
+        hash: sha256::Hash,
+        offset: u64,
+        len: u64,
+        now: SystemTime,
+    ) -> FetchBlobRequest {
+        let timestamp = unix_secs(now);
+        let key = KeyPair::from_secret_key(secp, root_key);
+        FetchBlobRequest {
+            hash,
+            offset,
+            len,
+            timestamp,
+            signature: secp.sign_schnorr(&Self::message(&hash, offset, len, timestamp), &key),
+        }
+    }
+
+    fn message(hash: &sha256::Hash, offset: u64, len: u64, timestamp: u64) -> Message {
+        let mut engine = sha256::Hash::engine();
+        engine.input(FETCH_BLOB_SIGNATURE_TAG);
+        engine.input(&hash[..]);
+        engine.input(&offset.to_le_bytes());
+        engine.input(&len.to_le_bytes());
+        engine.input(&timestamp.to_le_bytes());
+        Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("hash has right length")
+    }
+
+    /// Returns the guardian that signed the request if it is recent enough
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        root_keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+        now: SystemTime,
+    ) -> Option<PeerId> {
+        if unix_secs(now).abs_diff(self.timestamp) > FETCH_BLOB_REQUEST_VALIDITY.as_secs() {
+            return None;
+        }
+        let message = Self::message(&self.hash, self.offset, self.len, self.timestamp);
+        root_keys
+            .iter()
+            .find(|(_, key)| secp.verify_schnorr(&self.signature, &message, key).is_ok())
+            .map(|(peer, _)| *peer)
+    }
+}
+
+/// Size of the chunks proof-of-retrievability challenges are answered over
+pub const CHALLENGE_CHUNK_SIZE: usize = 4096;
+
+/// Asks a guardian to prove it still holds the payload of an entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeRequest {
+    pub key: String,
+    /// Random nonce chosen by the client so answers can't be precomputed
+    pub nonce: [u8; 32],
+    /// Index of the [`CHALLENGE_CHUNK_SIZE`] sized chunk to hash
+    pub chunk: u64,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeResponse {
+    /// `sha256(nonce || chunk)`, see [`challenge_response`]
+    pub hash: sha256::Hash,
+}
+
+/// Number of chunks a payload of `len` bytes is split into for challenges, an
+/// empty payload still has one (empty) chunk
+pub fn challenge_chunk_count(len: usize) -> u64 {
+    std::cmp::max(1, (len + CHALLENGE_CHUNK_SIZE - 1) / CHALLENGE_CHUNK_SIZE) as u64
+}
+
+/// Returns the bytes of chunk `chunk` of `data`, `None` if out of range
+pub fn challenge_chunk(data: &[u8], chunk: u64) -> Option<&[u8]> {
+    if chunk >= challenge_chunk_count(data.len()) {
+        return None;
+    }
+    let start = chunk as usize * CHALLENGE_CHUNK_SIZE;
+    let end = std::cmp::min(start + CHALLENGE_CHUNK_SIZE, data.len());
+    Some(&data[start..end])
+}
+
+/// Answer to a challenge for the given nonce over the given chunk bytes
+pub fn challenge_response(nonce: &[u8; 32], chunk: &[u8]) -> ChallengeResponse {
+    let mut engine = sha256::Hash::engine();
+    engine.input(nonce);
+    engine.input(chunk);
+    ChallengeResponse {
+        hash: sha256::Hash::from_engine(engine),
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct SmolFSDecoder;
@@ -13,7 +280,7 @@ impl Decoder for SmolFSDecoder {
     type Input = SmolFSInput;
     type Output = SmolFSOutput;
     type OutputOutcome = SmolFSOutputOutcome;
-    type ConsensusItem = SmolFSOutputConfirmation;
+    type ConsensusItem = SmolFSConsensusItem;
 
     fn decode_input(&self, mut d: &mut dyn io::Read) -> Result<SmolFSInput, DecodeError> {
         SmolFSInput::consensus_decode(&mut d, &ModuleDecoderRegistry::default())
@@ -33,7 +300,7 @@ impl Decoder for SmolFSDecoder {
     fn decode_consensus_item(
         &self,
         mut r: &mut dyn io::Read,
-    ) -> Result<SmolFSOutputConfirmation, DecodeError> {
-        SmolFSOutputConfirmation::consensus_decode(&mut r, &ModuleDecoderRegistry::default())
+    ) -> Result<SmolFSConsensusItem, DecodeError> {
+        SmolFSConsensusItem::consensus_decode(&mut r, &ModuleDecoderRegistry::default())
     }
 }
@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
 use fedimint_api::config::{
     ClientModuleConfig, TypedClientModuleConfig, TypedServerModuleConfig,
     TypedServerModuleConsensusConfig,
@@ -5,14 +9,31 @@ use fedimint_api::config::{
 use fedimint_api::core::ModuleKind;
 use fedimint_api::encoding::Encodable;
 use fedimint_api::module::__reexports::serde_json;
-use fedimint_api::PeerId;
+use fedimint_api::{Amount, PeerId};
+use secp256k1::{Secp256k1, SecretKey, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::KIND;
 
+/// Directory blobs are stored in if the guardian doesn't configure one, inside its data directory
+pub const DEFAULT_BLOB_DIR: &str = "smolfs-blobs";
+/// Stop accepting uploads once stored blobs take up this many bytes (10 GiB)
+pub const DEFAULT_MAX_DISK_USAGE: u64 = 10 * 1024 * 1024 * 1024;
+/// Largest blob the API accepts in a single upload (16 MiB)
+pub const DEFAULT_MAX_UPLOAD_SIZE: u64 = 16 * 1024 * 1024;
+/// Number of epochs the versions of deleted entries are kept around for if not
+/// configured otherwise
+pub const DEFAULT_TOMBSTONE_RETENTION_EPOCHS: u64 = 10_000;
+/// Number of versions of every entry kept if not configured otherwise
+pub const DEFAULT_MAX_VERSIONS: u32 = 10;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SmolFSConfig {
+    /// Contains all configuration that is locally configurable and not secret
     pub local: SmolFSConfigLocal,
+    /// Contains all configuration that will be encrypted such as private key material
+    pub private: SmolFSConfigPrivate,
     /// Contains all configuration that needs to be the same for every federation member
     pub consensus: SmolFSConfigConsensus,
 }
@@ -20,17 +41,79 @@ pub struct SmolFSConfig {
 #[derive(Clone, Debug, Serialize, Deserialize, Encodable)]
 pub struct SmolFSConfigConsensus {
     pub merkle_root: Vec<u8>,
+    /// Number of versions of every entry that are retained, including the current one
+    pub max_versions: u32,
+    /// Number of epochs a deleted entry can still be restored in, afterwards its
+    /// retained versions are dropped
+    pub tombstone_retention_epochs: u64,
+    /// If set, every write needs a proof-of-work of at least this many leading zero
+    /// bits, growing with the payload size (see [`crate::pow`])
+    pub pow_difficulty: Option<u8>,
+    /// Fee charged for every started KiB of a written payload, paid from the
+    /// owner's prepaid balance or with e-cash (see [`crate::common::write_fee`])
+    pub write_fee_per_kib: Amount,
+    /// Keys the guardians sign the root of the public entries with
+    pub root_keys: BTreeMap<PeerId, XOnlyPublicKey>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmolFSConfigPrivate {
+    /// Our key for signing the root of the public entries
+    pub root_key: SecretKey,
 }
 
+/// Storage settings every guardian decides on for itself
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SmolFSConfigLocal {
-    pub pubkey: String,
-    pub backup: String,
+    /// Directory blobs are stored in, relative paths are resolved against the
+    /// data directory of the guardian (see [`SmolFSConfigLocal::resolve_blob_dir`])
+    pub blob_dir: PathBuf,
+    /// High-water mark in bytes, once stored blobs exceed it no new uploads are
+    /// accepted through the API
+    pub max_disk_usage: u64,
+    /// Maximum size in bytes of a single upload accepted through the API
+    pub max_upload_size: u64,
+    /// API endpoints of the other guardians that damaged blobs are repaired from
+    #[serde(default)]
+    pub repair_peers: BTreeMap<PeerId, Url>,
+    /// Set on a guardian that replaced another one to fetch all entries from the
+    /// `repair_peers` before answering reads, see [`crate::catchup`]
+    #[serde(default)]
+    pub catch_up: bool,
+}
+
+impl Default for SmolFSConfigLocal {
+    fn default() -> Self {
+        Self {
+            blob_dir: PathBuf::from(DEFAULT_BLOB_DIR),
+            max_disk_usage: DEFAULT_MAX_DISK_USAGE,
+            max_upload_size: DEFAULT_MAX_UPLOAD_SIZE,
+            repair_peers: BTreeMap::new(),
+            catch_up: false,
+        }
+    }
+}
+
+impl SmolFSConfigLocal {
+    /// Makes a relative `blob_dir` relative to the guardian's `data_dir`
+    ///
+    /// The module does this on init if it is passed the data dir as
+    /// [`fedimint_api::config::FM_DATA_DIR_ENV`], anything else opening the blob store has to
+    /// call it itself so guardians sharing a host don't share blobs.
+    pub fn resolve_blob_dir(&mut self, data_dir: &Path) {
+        if self.blob_dir.is_relative() {
+            self.blob_dir = data_dir.join(&self.blob_dir);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable)]
 pub struct SmolFSClientConfig {
     pub merkle_root: Vec<u8>,
+    pub max_versions: u32,
+    pub pow_difficulty: Option<u8>,
+    pub write_fee_per_kib: Amount,
+    pub root_keys: BTreeMap<PeerId, XOnlyPublicKey>,
 }
 
 impl TypedClientModuleConfig for SmolFSClientConfig {
@@ -45,6 +128,10 @@ impl TypedServerModuleConsensusConfig for SmolFSConfigConsensus {
             KIND,
             serde_json::to_value(&SmolFSClientConfig {
                 merkle_root: self.merkle_root.clone(),
+                max_versions: self.max_versions,
+                pow_difficulty: self.pow_difficulty,
+                write_fee_per_kib: self.write_fee_per_kib,
+                root_keys: self.root_keys.clone(),
             })
             .expect("Serialization can't fail"),
         )
@@ -53,18 +140,63 @@ impl TypedServerModuleConsensusConfig for SmolFSConfigConsensus {
 
 impl TypedServerModuleConfig for SmolFSConfig {
     type Local = SmolFSConfigLocal;
-    type Private = ();
+    type Private = SmolFSConfigPrivate;
     type Consensus = SmolFSConfigConsensus;
 
-    fn from_parts(local: Self::Local, _private: Self::Private, consensus: Self::Consensus) -> Self {
-        Self { local, consensus }
+    fn from_parts(local: Self::Local, private: Self::Private, consensus: Self::Consensus) -> Self {
+        Self {
+            local,
+            private,
+            consensus,
+        }
     }
 
     fn to_parts(self) -> (ModuleKind, Self::Local, Self::Private, Self::Consensus) {
-        (KIND, self.local, (), self.consensus)
+        (KIND, self.local, self.private, self.consensus)
     }
 
-    fn validate_config(&self, _identity: &PeerId) -> anyhow::Result<()> {
+    fn validate_config(&self, identity: &PeerId) -> anyhow::Result<()> {
+        if self.consensus.max_versions == 0 {
+            bail!("SmolFS has to retain at least one version of every entry");
+        }
+        if self.consensus.tombstone_retention_epochs == 0 {
+            bail!("SmolFS tombstone retention must be greater than zero");
+        }
+        if self
+            .consensus
+            .pow_difficulty
+            .map_or(false, |bits| bits > 32)
+        {
+            bail!("SmolFS proof-of-work difficulty must not exceed 32 bits");
+        }
+        let root_key = self
+            .private
+            .root_key
+            .x_only_public_key(&Secp256k1::signing_only())
+            .0;
+        if self.consensus.root_keys.get(identity) != Some(&root_key) {
+            bail!("SmolFS root key does not match our key in the consensus config");
+        }
+        if self.local.blob_dir.as_os_str().is_empty() {
+            bail!("SmolFS blob directory must not be empty");
+        }
+        if self.local.max_upload_size == 0 {
+            bail!("SmolFS max upload size must be greater than zero");
+        }
+        if self.local.max_upload_size > self.local.max_disk_usage {
+            bail!(
+                "SmolFS max upload size ({}) exceeds the disk usage high-water mark ({})",
+                self.local.max_upload_size,
+                self.local.max_disk_usage
+            );
+        }
+        if self.local.repair_peers.contains_key(identity) {
+            bail!("SmolFS repair peers must not contain our own peer id");
+        }
+        if self.local.catch_up && self.local.repair_peers.is_empty() {
+            bail!("SmolFS can't catch up without repair peers to fetch entries from");
+        }
+
         Ok(())
     }
 }
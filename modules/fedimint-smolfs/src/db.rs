@@ -1,13 +1,43 @@
+use std::time::SystemTime;
+
+use bitcoin::hashes::sha256;
 use fedimint_api::db::DatabaseKeyPrefixConst;
 use fedimint_api::encoding::{Decodable, Encodable};
-use serde::Serialize;
+use fedimint_api::{Amount, OutPoint};
+use secp256k1::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
+use crate::blob::BlobStatus;
+use crate::common::{ChangeEvent, EntryMeta, VersionMeta};
+use crate::public::SignedRoot;
+use crate::SmolFSOutputOutcome;
+
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     // TODO: Make sure this does not collide with other modules
+    /// Legacy entries that kept the whole payload in the database, converted into
+    /// regular entries by [`crate::migration`]
     Example = 0x80,
+    Entry = 0x81,
+    DamagedBlob = 0x82,
+    RepairStatus = 0x83,
+    EntryVersion = 0x84,
+    BlobRef = 0x85,
+    ChangeSeq = 0x86,
+    Change = 0x87,
+    PrepaidBalance = 0x88,
+    PrepayOutcome = 0x89,
+    PublicEntry = 0x8a,
+    SignedRoot = 0x8b,
+    EntryOwner = 0x8c,
+    CapabilityReads = 0x8d,
+    CatchUpStatus = 0x8e,
+    PendingBlobGc = 0x8f,
+    Tombstone = 0x90,
+    UploadedBlob = 0x91,
+    EpochCount = 0x92,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -33,3 +63,368 @@ impl DatabaseKeyPrefixConst for ExampleKeyPrefix {
     type Key = ExampleKey;
     type Value = String;
 }
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct EntryKey(pub String);
+
+impl DatabaseKeyPrefixConst for EntryKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::Entry as u8;
+    type Key = Self;
+    type Value = EntryMeta;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EntryKeyPrefix;
+
+impl DatabaseKeyPrefixConst for EntryKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::Entry as u8;
+    type Key = EntryKey;
+    type Value = EntryMeta;
+}
+
+/// Retained version of an entry, the latest one is the entry's current payload
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct EntryVersionKey {
+    pub key: String,
+    pub version: u64,
+}
+
+impl DatabaseKeyPrefixConst for EntryVersionKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::EntryVersion as u8;
+    type Key = Self;
+    type Value = VersionMeta;
+}
+
+/// All retained versions of a single entry
+#[derive(Debug, Encodable, Decodable)]
+pub struct EntryVersionPrefix(pub String);
+
+impl DatabaseKeyPrefixConst for EntryVersionPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::EntryVersion as u8;
+    type Key = EntryVersionKey;
+    type Value = VersionMeta;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EntryVersionKeyPrefix;
+
+impl DatabaseKeyPrefixConst for EntryVersionKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::EntryVersion as u8;
+    type Key = EntryVersionKey;
+    type Value = VersionMeta;
+}
+
+/// Number of retained versions referencing a blob, the blob is queued for
+/// deletion once it drops to zero
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct BlobRefKey(pub sha256::Hash);
+
+impl DatabaseKeyPrefixConst for BlobRefKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::BlobRef as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct BlobRefKeyPrefix;
+
+impl DatabaseKeyPrefixConst for BlobRefKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::BlobRef as u8;
+    type Key = BlobRefKey;
+    type Value = u64;
+}
+
+/// Blobs whose last reference was dropped, deleted from the blob store at the
+/// start of the next epoch
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PendingBlobGcKey(pub sha256::Hash);
+
+impl DatabaseKeyPrefixConst for PendingBlobGcKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PendingBlobGc as u8;
+    type Key = Self;
+    type Value = ();
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingBlobGcKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PendingBlobGcKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PendingBlobGc as u8;
+    type Key = PendingBlobGcKey;
+    type Value = ();
+}
+
+/// Blobs referenced by an entry that were found missing or corrupt on this guardian
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct DamagedBlobKey(pub sha256::Hash);
+
+impl DatabaseKeyPrefixConst for DamagedBlobKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::DamagedBlob as u8;
+    type Key = Self;
+    type Value = BlobStatus;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DamagedBlobKeyPrefix;
+
+impl DatabaseKeyPrefixConst for DamagedBlobKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::DamagedBlob as u8;
+    type Key = DamagedBlobKey;
+    type Value = BlobStatus;
+}
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct RepairStatusKey;
+
+impl DatabaseKeyPrefixConst for RepairStatusKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::RepairStatus as u8;
+    type Key = Self;
+    type Value = RepairStatus;
+}
+
+/// Progress of repairing damaged blobs from peers
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct RepairStatus {
+    /// When the last repair run finished
+    pub last_run: Option<SystemTime>,
+    /// Number of blobs still missing or corrupt after the last run
+    pub damaged: u64,
+    /// Number of blobs repaired during the last run
+    pub repaired_last_run: u64,
+    /// Number of blobs repaired since the database was created
+    pub repaired_total: u64,
+}
+
+/// Sequence number of the latest change recorded in the change log
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct ChangeSeqKey;
+
+impl DatabaseKeyPrefixConst for ChangeSeqKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::ChangeSeq as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+/// Log of the most recent writes, used to notify watching clients
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct ChangeKey(pub u64);
+
+impl DatabaseKeyPrefixConst for ChangeKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::Change as u8;
+    type Key = Self;
+    type Value = ChangeEvent;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ChangeKeyPrefix;
+
+impl DatabaseKeyPrefixConst for ChangeKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::Change as u8;
+    type Key = ChangeKey;
+    type Value = ChangeEvent;
+}
+
+/// Storage balance prepaid for an owner key, write fees are drawn from it
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PrepaidBalanceKey(pub XOnlyPublicKey);
+
+impl DatabaseKeyPrefixConst for PrepaidBalanceKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PrepaidBalance as u8;
+    type Key = Self;
+    type Value = Amount;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PrepaidBalanceKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PrepaidBalanceKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PrepaidBalance as u8;
+    type Key = PrepaidBalanceKey;
+    type Value = Amount;
+}
+
+/// Outcome of a prepayment output, recorded once it was credited
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PrepayOutcomeKey(pub OutPoint);
+
+impl DatabaseKeyPrefixConst for PrepayOutcomeKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PrepayOutcome as u8;
+    type Key = Self;
+    type Value = SmolFSOutputOutcome;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PrepayOutcomeKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PrepayOutcomeKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PrepayOutcome as u8;
+    type Key = PrepayOutcomeKey;
+    type Value = SmolFSOutputOutcome;
+}
+
+/// Entries whose current version anyone may read, see [`crate::public`]
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct PublicEntryKey(pub String);
+
+impl DatabaseKeyPrefixConst for PublicEntryKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::PublicEntry as u8;
+    type Key = Self;
+    type Value = ();
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PublicEntryKeyPrefix;
+
+impl DatabaseKeyPrefixConst for PublicEntryKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::PublicEntry as u8;
+    type Key = PublicEntryKey;
+    type Value = ();
+}
+
+/// Signatures of the guardians over the latest root of the public entries
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct SignedRootKey;
+
+impl DatabaseKeyPrefixConst for SignedRootKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::SignedRoot as u8;
+    type Key = Self;
+    type Value = SignedRoot;
+}
+
+/// Key that wrote an entry first, only it may write the entry again or grant
+/// read capabilities for it
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct EntryOwnerKey(pub String);
+
+impl DatabaseKeyPrefixConst for EntryOwnerKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::EntryOwner as u8;
+    type Key = Self;
+    type Value = XOnlyPublicKey;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EntryOwnerKeyPrefix;
+
+impl DatabaseKeyPrefixConst for EntryOwnerKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::EntryOwner as u8;
+    type Key = EntryOwnerKey;
+    type Value = XOnlyPublicKey;
+}
+
+/// Number of bytes this guardian served for a read capability, identified by
+/// [`ReadCapability::id`](crate::common::ReadCapability::id)
+///
+/// Not part of consensus, every guardian counts the reads it served itself.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct CapabilityReadsKey(pub sha256::Hash);
+
+impl DatabaseKeyPrefixConst for CapabilityReadsKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::CapabilityReads as u8;
+    type Key = Self;
+    type Value = u64;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct CapabilityReadsKeyPrefix;
+
+impl DatabaseKeyPrefixConst for CapabilityReadsKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::CapabilityReads as u8;
+    type Key = CapabilityReadsKey;
+    type Value = u64;
+}
+
+/// Progress of catching up with the other guardians, only present if catching
+/// up was enabled in the local config (see [`crate::catchup`])
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct CatchUpStatusKey;
+
+impl DatabaseKeyPrefixConst for CatchUpStatusKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::CatchUpStatus as u8;
+    type Key = Self;
+    type Value = CatchUpStatus;
+}
+
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct CatchUpStatus {
+    /// When catching up finished, reads are only answered afterwards
+    pub completed: Option<SystemTime>,
+    /// State root attested by our peers that the current attempt catches up to
+    pub state_root: Option<sha256::Hash>,
+    /// Number of attempts started so far, a new one is started if the peers'
+    /// state changes before an attempt finished
+    pub attempts: u64,
+    /// Number of entries received from peers during the current attempt
+    pub entries_received: u64,
+    /// Number of distinct blobs referenced by the received entries
+    pub blobs_total: u64,
+    /// Number of those blobs that are stored locally already
+    pub blobs_fetched: u64,
+    /// Why the last attempt failed, if it did
+    pub last_error: Option<String>,
+}
+
+/// Epoch an entry was deleted in, removed again if the entry is written before the
+/// tombstone is purged
+///
+/// Once [`tombstone_retention_epochs`](crate::config::SmolFSConfigConsensus::tombstone_retention_epochs)
+/// passed, the entry's retained versions are dropped together with the tombstone.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct TombstoneKey(pub String);
+
+impl DatabaseKeyPrefixConst for TombstoneKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::Tombstone as u8;
+    type Key = Self;
+    type Value = Tombstone;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TombstoneKeyPrefix;
+
+impl DatabaseKeyPrefixConst for TombstoneKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::Tombstone as u8;
+    type Key = TombstoneKey;
+    type Value = Tombstone;
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct Tombstone {
+    /// Value of [`EpochCountKey`] in the epoch the deletion was applied in
+    pub deleted_epoch: u64,
+}
+
+/// Time a payload finished uploading to this guardian, see [`crate::upload`]
+///
+/// Not part of consensus, uploads nobody references are removed after
+/// [`UPLOAD_RETENTION`](crate::upload::UPLOAD_RETENTION).
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct UploadedBlobKey(pub sha256::Hash);
+
+impl DatabaseKeyPrefixConst for UploadedBlobKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::UploadedBlob as u8;
+    type Key = Self;
+    type Value = SystemTime;
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UploadedBlobKeyPrefix;
+
+impl DatabaseKeyPrefixConst for UploadedBlobKeyPrefix {
+    const DB_PREFIX: u8 = DbKeyPrefix::UploadedBlob as u8;
+    type Key = UploadedBlobKey;
+    type Value = SystemTime;
+}
+
+/// Number of consensus epochs the module processed, the clock tombstones are
+/// purged by
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Serialize)]
+pub struct EpochCountKey;
+
+impl DatabaseKeyPrefixConst for EpochCountKey {
+    const DB_PREFIX: u8 = DbKeyPrefix::EpochCount as u8;
+    type Key = Self;
+    type Value = u64;
+}
@@ -0,0 +1,187 @@
+//! Binary deltas between two versions of a payload
+//!
+//! A [`Delta`] describes a new payload as a sequence of ranges copied from the
+//! previous version and literal bytes inserted in between. Applying a delta is
+//! fully deterministic, so every guardian holding the base version arrives at
+//! the same result, which is additionally pinned by its hash in the
+//! transaction.
+
+use std::collections::HashMap;
+
+use fedimint_api::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Size of the blocks of the base payload that [`Delta::compute`] looks for in
+/// the new payload
+const BLOCK_SIZE: usize = 64;
+
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct Delta {
+    pub ops: Vec<DeltaOp>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum DeltaOp {
+    /// Copy `len` bytes starting at `offset` of the base payload
+    Copy { offset: u64, len: u64 },
+    /// Insert literal bytes
+    Insert(#[serde(with = "fedimint_api::hex::serde")] Vec<u8>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
+pub enum DeltaError {
+    #[error("Copy of {len} bytes at offset {offset} exceeds base of {base_size} bytes")]
+    CopyOutOfRange {
+        offset: u64,
+        len: u64,
+        base_size: u64,
+    },
+}
+
+impl Delta {
+    /// Computes a delta turning `base` into `new`
+    ///
+    /// Looks up every block of [`BLOCK_SIZE`] bytes of `new` among the aligned
+    /// blocks of `base` and extends matches as far as possible, everything else
+    /// is inserted literally. This is simple rather than optimal, but finds
+    /// unchanged regions even if they moved.
+    pub fn compute(base: &[u8], new: &[u8]) -> Delta {
+        let mut blocks = HashMap::new();
+        for (idx, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+            blocks.entry(block).or_insert(idx * BLOCK_SIZE);
+        }
+
+        let mut delta = Delta::default();
+        let mut literal = vec![];
+        let mut pos = 0;
+        while pos < new.len() {
+            let matched = new
+                .get(pos..pos + BLOCK_SIZE)
+                .and_then(|block| blocks.get(block));
+            let Some(&offset) = matched else {
+                literal.push(new[pos]);
+                pos += 1;
+                continue;
+            };
+
+            let len = base[offset..]
+                .iter()
+                .zip(&new[pos..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if !literal.is_empty() {
+                delta
+                    .ops
+                    .push(DeltaOp::Insert(std::mem::take(&mut literal)));
+            }
+            delta.ops.push(DeltaOp::Copy {
+                offset: offset as u64,
+                len: len as u64,
+            });
+            pos += len;
+        }
+        if !literal.is_empty() {
+            delta.ops.push(DeltaOp::Insert(literal));
+        }
+
+        delta
+    }
+
+    /// Applies the delta to `base`, returning the new payload
+    pub fn apply(&self, base: &[u8]) -> Result<Vec<u8>, DeltaError> {
+        self.check_ranges(base.len() as u64)?;
+
+        let mut result = Vec::with_capacity(self.result_size() as usize);
+        for op in &self.ops {
+            match op {
+                DeltaOp::Copy { offset, len } => {
+                    result.extend_from_slice(&base[*offset as usize..(*offset + *len) as usize])
+                }
+                DeltaOp::Insert(bytes) => result.extend_from_slice(bytes),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Checks that all copied ranges lie within a base of `base_size` bytes
+    pub fn check_ranges(&self, base_size: u64) -> Result<(), DeltaError> {
+        for op in &self.ops {
+            if let DeltaOp::Copy { offset, len } = op {
+                if offset.checked_add(*len).map_or(true, |end| end > base_size) {
+                    return Err(DeltaError::CopyOutOfRange {
+                        offset: *offset,
+                        len: *len,
+                        base_size,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Size of the payload resulting from applying the delta
+    pub fn result_size(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Copy { len, .. } => *len,
+                DeltaOp::Insert(bytes) => bytes.len() as u64,
+            })
+            .sum()
+    }
+
+    /// Number of literal bytes carried by the delta
+    pub fn inserted_size(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Copy { .. } => 0,
+                DeltaOp::Insert(bytes) => bytes.len() as u64,
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delta, DeltaError, DeltaOp};
+
+    #[test]
+    fn compute_apply_roundtrip() {
+        let base = (0..4096u32)
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<u8>>();
+        let mut new = base.clone();
+        new[1000] ^= 0xff;
+        new.splice(5000..5000, b"inserted".iter().copied());
+        new.truncate(12000);
+
+        let delta = Delta::compute(&base, &new);
+        assert!(delta.inserted_size() < 200);
+        assert_eq!(delta.result_size(), new.len() as u64);
+        assert_eq!(delta.apply(&base).unwrap(), new);
+
+        assert_eq!(Delta::compute(&[], b"new").apply(&[]).unwrap(), b"new");
+    }
+
+    #[test]
+    fn rejects_out_of_range_copies() {
+        let delta = Delta {
+            ops: vec![DeltaOp::Copy {
+                offset: u64::MAX,
+                len: 2,
+            }],
+        };
+        assert_eq!(
+            delta.apply(b"base"),
+            Err(DeltaError::CopyOutOfRange {
+                offset: u64::MAX,
+                len: 2,
+                base_size: 4,
+            })
+        );
+    }
+}
@@ -1,65 +1,203 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fmt::{self};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
+use archive::ArchivedEntry;
 use async_trait::async_trait;
-use common::SmolFSDecoder;
-use db::{ExampleKey, ExampleKeyPrefix};
-use fedimint_api::cancellable::Cancellable;
+use bitcoin::hashes::sha256;
+use blob::{BlobStatus, BlobStore};
+use catchup::{AttestedStateRoot, CatchUpEntriesRequest, ConsensusSnapshot};
+use common::{
+    challenge_chunk_count, challenge_response, unix_secs, write_fee, BlobResponse,
+    CapabilityReadRequest, ChallengeRequest, ChallengeResponse, ChangeEvent, EntryMeta,
+    FetchBlobRequest, FetchVersionRequest, SignedReadCapability, SmolFSDecoder, VersionMeta,
+    WatchRequest, CHALLENGE_CHUNK_SIZE,
+};
+use db::{
+    BlobRefKey, CapabilityReadsKey, CatchUpStatus, CatchUpStatusKey, ChangeKey, ChangeSeqKey,
+    DamagedBlobKey, DamagedBlobKeyPrefix, EntryKey, EntryKeyPrefix, EntryOwnerKey, EntryVersionKey,
+    EntryVersionKeyPrefix, EntryVersionPrefix, EpochCountKey, PendingBlobGcKey,
+    PendingBlobGcKeyPrefix, PrepaidBalanceKey, PrepaidBalanceKeyPrefix, PrepayOutcomeKey,
+    PublicEntryKey, RepairStatus, RepairStatusKey, SignedRootKey, Tombstone, TombstoneKey,
+    TombstoneKeyPrefix, UploadedBlobKey,
+};
+use delta::{Delta, DeltaError};
+use fedimint_api::cancellable::{Cancellable, Cancelled};
 use fedimint_api::config::{
     ConfigGenParams, DkgPeerMsg, ModuleGenParams, ServerModuleConfig, TypedServerModuleConfig,
+    FM_DATA_DIR_ENV,
 };
 use fedimint_api::config::{ModuleConfigResponse, TypedServerModuleConsensusConfig};
-use fedimint_api::core::{ModuleInstanceId, ModuleKind};
+use fedimint_api::core::{ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_SMOLFS};
+use fedimint_api::db::migration::apply_migrations;
 use fedimint_api::db::{Database, DatabaseTransaction};
 use fedimint_api::encoding::{Decodable, Encodable};
 use fedimint_api::module::__reexports::serde_json;
 use fedimint_api::module::audit::Audit;
 use fedimint_api::module::interconnect::ModuleInterconect;
 use fedimint_api::module::{
-    api_endpoint, ApiEndpoint, InputMeta, InputMetadata, ModuleError, ModuleGen,
-    TransactionItemAmount,
+    api_endpoint, ApiEndpoint, ApiError, InputMeta, InputMetadata, IntoModuleError, ModuleError,
+    ModuleGen, TransactionItemAmount,
 };
 use fedimint_api::net::peers::MuxPeerConnections;
 use fedimint_api::server::DynServerModule;
-use fedimint_api::task::TaskGroup;
-use fedimint_api::{plugin_types_trait_impl, OutPoint, PeerId, ServerModule};
+use fedimint_api::task::{sleep, timeout, TaskGroup};
+use fedimint_api::{
+    plugin_types_trait_impl, Amount, BitcoinHash, NumPeers, OutPoint, PeerId, ServerModule,
+};
 use impl_tools::autoimpl;
-use secp256k1::All;
+use public::{root_message, PublicTree, SignedRoot};
+use rand::rngs::OsRng;
+use secp256k1::{schnorr, All, KeyPair, Secp256k1, XOnlyPublicKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::watch;
 use tracing::{debug, error, info, warn};
+use upload::{
+    upload_receipt_message, ReadRequest, UploadFinalizeRequest, UploadPushRequest, UploadSessions,
+    UploadStartRequest, MAX_CHUNK_SIZE,
+};
+use url::Url;
 
-use crate::config::{SmolFSConfig, SmolFSConfigConsensus, SmolFSConfigLocal};
+use crate::config::{SmolFSConfig, SmolFSConfigConsensus, SmolFSConfigLocal, SmolFSConfigPrivate};
 
+pub mod archive;
+pub mod blob;
+pub mod catchup;
 pub mod common;
 pub mod config;
 pub mod db;
+pub mod delta;
+pub mod metrics;
+pub mod migration;
+pub mod pow;
+pub mod public;
+pub mod repair;
+pub mod upload;
 
 const KIND: ModuleKind = ModuleKind::from_static_str("smolfs");
 
+/// Number of most recent writes kept in the change log for watching clients
+pub const CHANGE_LOG_SIZE: u64 = 10_000;
+/// How long a watch request waits for a change before returning empty-handed
+pub const WATCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a pending watch request checks whether the epoch announcing a change was committed
+const WATCH_COMMIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 /// SmolFS module
 #[derive(Debug)]
 pub struct SmolFS {
     pub cfg: SmolFSConfig,
+    /// Used by long-polling endpoints that need to see writes committed after the
+    /// request arrived
+    pub db: Database,
+    pub blobs: BlobStore,
+    pub uploads: UploadSessions,
+    secp: Secp256k1<All>,
+    /// Change log sequence number at the start of the current epoch, used to
+    /// count the writes of the epoch
+    epoch_start_seq: AtomicU64,
+    /// Latest change log sequence number of a processed epoch, which wakes pending
+    /// watch requests. Announced right before the epoch is committed.
+    last_change_seq: watch::Sender<u64>,
+    public_tree: PublicTree,
 }
-#[autoimpl(Deref, DerefMut using self.0)]
+
+/// A guardian's signature over the root of the public entries, see [`public`]
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
-pub struct SmolFSOutputConfirmation(pub SmolFSEntry);
+pub struct SmolFSConsensusItem {
+    pub root: sha256::Hash,
+    pub signature: schnorr::Signature,
+}
+
+/// Largest payload that may be included in a transaction directly, bigger ones
+/// have to be uploaded through the streaming upload API first
+pub const MAX_INLINE_PAYLOAD_SIZE: u64 = 64 * 1024;
 
-#[derive(
-    Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable, Default,
-)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct SmolFSEntry {
     pub pubkey: String,
-    pub backup: String,
+    pub payload: SmolFSPayload,
+    /// Time of the write as claimed by the client, only used to tell versions apart
+    pub timestamp: SystemTime,
+    /// Nonce of the proof-of-work, only checked if the federation requires one
+    pub pow_nonce: u64,
+    /// Owner whose prepaid balance pays for the write, has to sign the transaction
+    ///
+    /// The first owner to write an entry keeps it, later writes have to come from
+    /// the same owner. Writes without an owner are rejected.
+    ///
+    /// Entries migrated from legacy entries have no owner and are read-only until
+    /// they are claimed by a write whose owner is the key their name is the hex
+    /// encoding of.
+    pub owner: Option<XOnlyPublicKey>,
+    /// Part of the write fee drawn from the owner's prepaid balance, the rest has to
+    /// be paid by other inputs of the transaction
+    pub prepaid: Amount,
+    /// Whether anyone may read this version of the entry, see [`public`]
+    pub public: bool,
+    /// Version the entry has to be at for the write to be accepted, `None` to write
+    /// whatever the entry is at
+    ///
+    /// Deleted entries aren't at any version, so a write expecting one fails.
+    pub expected_version: Option<u64>,
 }
 
-#[derive(Debug, Clone)]
-pub struct SmolFSVerificationCache {
-    valid_users: HashMap<String, String>,
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub enum SmolFSPayload {
+    /// Payload included in the transaction, at most [`MAX_INLINE_PAYLOAD_SIZE`] bytes
+    Inline(#[serde(with = "fedimint_api::hex::serde")] Vec<u8>),
+    /// Payload uploaded to the guardians beforehand through the streaming upload
+    /// API, guardians that didn't receive it repair it from their peers
+    ///
+    /// Needs the upload receipts of a threshold of guardians, unless it restores a
+    /// version of the entry that is still retained.
+    Uploaded {
+        hash: sha256::Hash,
+        size: u64,
+        receipts: BTreeMap<PeerId, schnorr::Signature>,
+    },
+    /// Delta against the current payload of the entry, which has to have the hash
+    /// `base`. The hash of the result is included so all guardians agree on the new
+    /// entry without having to apply the delta during validation.
+    Delta {
+        base: sha256::Hash,
+        delta: Delta,
+        hash: sha256::Hash,
+    },
+    /// Deletes the entry, its retained versions are kept so it can still be
+    /// restored until its tombstone is purged
+    Delete,
+}
+
+impl SmolFSPayload {
+    pub fn hash(&self) -> sha256::Hash {
+        match self {
+            SmolFSPayload::Inline(bytes) => sha256::Hash::hash(bytes),
+            SmolFSPayload::Uploaded { hash, .. } => *hash,
+            SmolFSPayload::Delta { hash, .. } => *hash,
+            SmolFSPayload::Delete => sha256::Hash::all_zeros(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        match self {
+            SmolFSPayload::Inline(bytes) => bytes.len() as u64,
+            SmolFSPayload::Uploaded { size, .. } => *size,
+            SmolFSPayload::Delta { delta, .. } => delta.result_size(),
+            SmolFSPayload::Delete => 0,
+        }
+    }
 }
 
+/// Inputs are checked against the database only, so there is nothing to cache
+#[derive(Debug, Clone)]
+pub struct SmolFSVerificationCache;
+
 #[derive(Debug)]
 pub struct SmolFSConfigGenerator;
 
@@ -75,11 +213,71 @@ impl ModuleGen for SmolFSConfigGenerator {
     async fn init(
         &self,
         cfg: ServerModuleConfig,
-        _db: Database,
-        _env: &BTreeMap<OsString, OsString>,
-        _task_group: &mut TaskGroup,
+        db: Database,
+        env: &BTreeMap<OsString, OsString>,
+        task_group: &mut TaskGroup,
     ) -> anyhow::Result<DynServerModule> {
-        Ok(SmolFS::new(cfg.to_typed()?).into())
+        let mut cfg: SmolFSConfig = cfg.to_typed()?;
+        if let Some(data_dir) = env.get(OsStr::new(FM_DATA_DIR_ENV)) {
+            cfg.local.resolve_blob_dir(Path::new(data_dir));
+        }
+        let smolfs = SmolFS::new(cfg, db.clone())?;
+        smolfs.blobs.clear_staging()?;
+
+        let mut dbtx = db.begin_transaction().await;
+        {
+            let mut module_dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+            apply_migrations(
+                &mut module_dbtx,
+                &smolfs,
+                KIND.as_str(),
+                migration::DATABASE_VERSION,
+                migration::migrations(),
+            )
+            .await?;
+            smolfs.check_blob_integrity(&mut module_dbtx).await;
+            update_storage_metrics(&mut module_dbtx).await;
+        }
+        dbtx.commit_tx().await.expect("DB Error");
+
+        if smolfs.cfg.local.catch_up {
+            let catch_up_db = db.clone();
+            let catch_up_blobs = smolfs.blobs.clone();
+            let root_keys = smolfs.cfg.consensus.root_keys.clone();
+            let peers = smolfs.cfg.local.repair_peers.clone();
+            let root_key = smolfs.cfg.private.root_key;
+            task_group
+                .spawn("smolfs catch-up", move |handle| async move {
+                    catchup::run_catch_up(
+                        catch_up_db,
+                        catch_up_blobs,
+                        root_keys,
+                        peers,
+                        root_key,
+                        &handle,
+                    )
+                    .await;
+                })
+                .await;
+        }
+        let repair_db = db.clone();
+        let repair_blobs = smolfs.blobs.clone();
+        let repair_peers = smolfs.cfg.local.repair_peers.clone();
+        let root_key = smolfs.cfg.private.root_key;
+        task_group
+            .spawn("smolfs repair", move |handle| async move {
+                repair::run_repair(repair_db, repair_blobs, repair_peers, root_key, &handle).await;
+            })
+            .await;
+        let uploads = smolfs.uploads.clone();
+        let upload_blobs = smolfs.blobs.clone();
+        task_group
+            .spawn("smolfs upload cleanup", |handle| async move {
+                upload::run_session_cleanup(uploads, db, upload_blobs, &handle).await;
+            })
+            .await;
+
+        Ok(smolfs.into())
     }
 
     fn trusted_dealer_gen(
@@ -89,17 +287,33 @@ impl ModuleGen for SmolFSConfigGenerator {
     ) -> BTreeMap<PeerId, ServerModuleConfig> {
         let params = params
             .get::<SmolFSConfigGenParams>()
-            .expect("Invalid mint params");
-        let mint_cfg: BTreeMap<_, SmolFSConfig> = peers
+            .expect("Invalid smolfs params");
+        let secp = Secp256k1::new();
+        let keys = peers
+            .iter()
+            .map(|&peer| (peer, secp.generate_keypair(&mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let root_keys = keys
             .iter()
-            .map(|&peer| {
+            .map(|(peer, (_, pk))| (*peer, pk.x_only_public_key().0))
+            .collect::<BTreeMap<_, _>>();
+
+        let mint_cfg: BTreeMap<_, SmolFSConfig> = keys
+            .iter()
+            .map(|(&peer, (sk, _))| {
                 let config = SmolFSConfig {
                     local: SmolFSConfigLocal {
-                        pubkey: String::new(),
-                        backup: String::new(),
+                        repair_peers: repair_peers(&params.peer_api_urls, &peer),
+                        ..SmolFSConfigLocal::default()
                     },
+                    private: SmolFSConfigPrivate { root_key: *sk },
                     consensus: SmolFSConfigConsensus {
                         merkle_root: vec![],
+                        max_versions: params.max_versions,
+                        tombstone_retention_epochs: params.tombstone_retention_epochs,
+                        pow_difficulty: params.pow_difficulty,
+                        write_fee_per_kib: params.write_fee_per_kib,
+                        root_keys: root_keys.clone(),
                     },
                 };
                 (peer, config)
@@ -114,24 +328,53 @@ impl ModuleGen for SmolFSConfigGenerator {
 
     async fn distributed_gen(
         &self,
-        _connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
-        _our_id: &PeerId,
-        _instance_id: ModuleInstanceId,
-        _peers: &[PeerId],
+        connections: &MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        our_id: &PeerId,
+        instance_id: ModuleInstanceId,
+        peers: &[PeerId],
         params: &ConfigGenParams,
         _task_group: &mut TaskGroup,
     ) -> anyhow::Result<Cancellable<ServerModuleConfig>> {
-        let _params = params
+        let params = params
             .get::<SmolFSConfigGenParams>()
-            .expect("Invalid mint params");
+            .expect("Invalid smolfs params");
+
+        let (root_key, pk) = Secp256k1::new().generate_keypair(&mut OsRng);
+        if let Err(Cancelled) = connections
+            .send(peers, instance_id, DkgPeerMsg::PublicKey(pk))
+            .await
+        {
+            return Ok(Err(Cancelled));
+        }
+
+        let mut root_keys = BTreeMap::from([(*our_id, pk.x_only_public_key().0)]);
+        while root_keys.len() < peers.len() {
+            match connections.receive(instance_id).await {
+                Ok((peer, DkgPeerMsg::PublicKey(key))) => {
+                    root_keys.insert(peer, key.x_only_public_key().0);
+                }
+                Ok((peer, msg)) => {
+                    anyhow::bail!("Invalid message received from: {peer}: {msg:?}");
+                }
+                _ => {
+                    return Ok(Err(Cancelled));
+                }
+            }
+        }
 
         let server = SmolFSConfig {
             local: SmolFSConfigLocal {
-                pubkey: String::new(),
-                backup: String::new(),
+                repair_peers: repair_peers(&params.peer_api_urls, our_id),
+                ..SmolFSConfigLocal::default()
             },
+            private: SmolFSConfigPrivate { root_key },
             consensus: SmolFSConfigConsensus {
                 merkle_root: vec![],
+                max_versions: params.max_versions,
+                tombstone_retention_epochs: params.tombstone_retention_epochs,
+                pow_difficulty: params.pow_difficulty,
+                write_fee_per_kib: params.write_fee_per_kib,
+                root_keys,
             },
         };
 
@@ -157,8 +400,25 @@ impl ModuleGen for SmolFSConfigGenerator {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SmolFSConfigGenParams {
-    //TODO:Change to max size of buffer
-    pub important_param: u64,
+    /// Number of versions of every entry to retain
+    pub max_versions: u32,
+    /// Number of epochs the versions of deleted entries are retained for
+    pub tombstone_retention_epochs: u64,
+    /// Base proof-of-work difficulty required for writes, `None` to not require any
+    pub pow_difficulty: Option<u8>,
+    /// Fee charged per started KiB of written payload
+    pub write_fee_per_kib: Amount,
+    /// API endpoints of all guardians, used to repair damaged blobs
+    pub peer_api_urls: BTreeMap<PeerId, Url>,
+}
+
+/// API endpoints of every guardian except `our_id`
+fn repair_peers(peer_api_urls: &BTreeMap<PeerId, Url>, our_id: &PeerId) -> BTreeMap<PeerId, Url> {
+    peer_api_urls
+        .iter()
+        .filter(|(peer, _)| *peer != our_id)
+        .map(|(peer, url)| (*peer, url.clone()))
+        .collect()
 }
 
 impl ModuleGenParams for SmolFSConfigGenParams {
@@ -166,9 +426,7 @@ impl ModuleGenParams for SmolFSConfigGenParams {
 }
 
 #[autoimpl(Deref, DerefMut using self.0)]
-#[derive(
-    Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable, Default,
-)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
 pub struct SmolFSInput(pub Box<SmolFSEntry>);
 
 impl fmt::Display for SmolFSInput {
@@ -177,14 +435,16 @@ impl fmt::Display for SmolFSInput {
     }
 }
 
-#[derive(
-    Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable, Default,
-)]
-pub struct SmolFSOutput();
+/// Prepays storage for `owner`, anyone can pay for any owner
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct SmolFSOutput {
+    pub owner: XOnlyPublicKey,
+    pub amount: Amount,
+}
 
 impl fmt::Display for SmolFSOutput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DummyOutput")
+        write!(f, "SmolFS prepayment of {} for {}", self.amount, self.owner)
     }
 }
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
@@ -192,13 +452,13 @@ pub struct SmolFSOutputOutcome;
 
 impl fmt::Display for SmolFSOutputOutcome {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DummyOutputOutcome")
+        write!(f, "SmolFS prepayment credited")
     }
 }
 
-impl fmt::Display for SmolFSOutputConfirmation {
+impl fmt::Display for SmolFSConsensusItem {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "DummyOutputConfirmation")
+        write!(f, "SmolFS public root signature for {}", self.root)
     }
 }
 
@@ -210,7 +470,7 @@ impl ServerModule for SmolFS {
     type Input = SmolFSInput;
     type Output = SmolFSOutput;
     type OutputOutcome = SmolFSOutputOutcome;
-    type ConsensusItem = SmolFSOutputConfirmation;
+    type ConsensusItem = SmolFSConsensusItem;
     type VerificationCache = SmolFSVerificationCache;
 
     fn decoder(&self) -> Self::Decoder {
@@ -218,7 +478,6 @@ impl ServerModule for SmolFS {
     }
 
     async fn await_consensus_proposal(&self, dbtx: &mut DatabaseTransaction<'_>) {
-        info!("await consensus proposal");
         if self.consensus_proposal(dbtx).await.is_empty() {
             std::future::pending().await
         }
@@ -228,162 +487,1690 @@ impl ServerModule for SmolFS {
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
     ) -> Vec<Self::ConsensusItem> {
-        info!("consensus proposal");
-        dbtx.find_by_prefix(&ExampleKeyPrefix)
-            .await
-            .map(|res| {
-                let res = res.expect("DB Error");
-                SmolFSOutputConfirmation(SmolFSEntry {
-                    pubkey: res.0 .0,
-                    backup: res.1,
-                })
-            })
-            // .chain(std::iter::once(round_ci))
-            .collect()
+        // Sign the root of the public entries until our signature made it into the
+        // consensus, this only triggers a new epoch after public entries changed
+        let root = self.public_tree.root(dbtx).await;
+        let key = KeyPair::from_secret_key(&self.secp, &self.cfg.private.root_key);
+        let our_key = key.x_only_public_key().0;
+        let signed = dbtx
+            .get_value(&SignedRootKey)
+            .await
+            .expect("DB Error")
+            .filter(|signed| signed.root == root)
+            .unwrap_or_default();
+        let already_signed = signed
+            .signatures
+            .keys()
+            .any(|peer| self.cfg.consensus.root_keys.get(peer) == Some(&our_key));
+        if already_signed {
+            return vec![];
+        }
+
+        vec![SmolFSConsensusItem {
+            root,
+            signature: self.secp.sign_schnorr(&root_message(&root), &key),
+        }]
     }
 
     async fn begin_consensus_epoch<'a, 'b>(
         &'a self,
         dbtx: &mut DatabaseTransaction<'b>,
-        _consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
+        consensus_items: Vec<(PeerId, Self::ConsensusItem)>,
     ) {
-        info!("begin consensus epoch");
-        let pubkey = self.cfg.local.pubkey.clone();
-        let backup = self.cfg.local.backup.clone();
-        let a = dbtx
-            .insert_entry(&ExampleKey(pubkey), &backup)
+        self.collect_garbage(dbtx).await;
+        self.purge_tombstones(dbtx).await;
+
+        let seq = dbtx
+            .get_value(&ChangeSeqKey)
             .await
             .expect("DB Error")
-            .unwrap();
-        println!("{self:?}");
-        println!("{:?}", a);
+            .unwrap_or(0);
+        self.epoch_start_seq.store(seq, Ordering::Relaxed);
+
+        // Transactions of this epoch weren't applied yet, so guardians that are up
+        // to date all signed this root
+        let root = self.public_tree.root(dbtx).await;
+        let mut signed = dbtx
+            .get_value(&SignedRootKey)
+            .await
+            .expect("DB Error")
+            .filter(|signed| signed.root == root)
+            .unwrap_or(SignedRoot {
+                root,
+                signatures: BTreeMap::new(),
+            });
+
+        let message = root_message(&root);
+        for (peer, item) in consensus_items {
+            let Some(key) = self.cfg.consensus.root_keys.get(&peer) else {
+                warn!(%peer, "Root signature from unknown peer");
+                continue;
+            };
+            if item.root != root {
+                debug!(%peer, "Ignoring signature over outdated root");
+                continue;
+            }
+            if self
+                .secp
+                .verify_schnorr(&item.signature, &message, key)
+                .is_err()
+            {
+                warn!(%peer, "Invalid root signature");
+                continue;
+            }
+            signed.signatures.insert(peer, item.signature);
+        }
+
+        if !signed.signatures.is_empty() {
+            dbtx.insert_entry(&SignedRootKey, &signed)
+                .await
+                .expect("DB Error");
+        }
     }
 
     fn build_verification_cache<'a>(
         &'a self,
-        inputs: impl Iterator<Item = &'a Self::Input> + Send,
+        _inputs: impl Iterator<Item = &'a Self::Input> + Send,
     ) -> Self::VerificationCache {
-        info!("build verification");
-
-        // why not
-        let valid_users = inputs
-            .flat_map(|inputs| {
-                let mut h = HashMap::new();
-                h.entry(inputs.pubkey.clone())
-                    .or_insert(inputs.backup.clone());
-                h
-            })
-            .collect();
-
-        SmolFSVerificationCache { valid_users }
+        SmolFSVerificationCache
     }
 
     async fn validate_input<'a, 'b>(
         &self,
         _interconnect: &dyn ModuleInterconect,
-        _dbtx: &mut DatabaseTransaction<'b>,
+        dbtx: &mut DatabaseTransaction<'b>,
         _verification_cache: &Self::VerificationCache,
-        _input: &'a Self::Input,
+        input: &'a Self::Input,
     ) -> Result<InputMeta, ModuleError> {
-        info!("validate input");
-        // TODO attach a payment to the backup, include details here
-        // fill the pubkey vectors with payments destined to the guardians
-        // make ecash wallet for fed module then use interconnect to pay to it
-        Ok(InputMeta {
-            amount: TransactionItemAmount {
-                amount: fedimint_api::Amount::from_sats(0),
-                fee: fedimint_api::Amount::from_sats(0),
-            },
-            puk_keys: vec![],
-        })
+        // Only called when a transaction is submitted to us, so every rejected write
+        // is counted once
+        self.check_input(dbtx, input)
+            .await
+            .map_err(|e| {
+                metrics::WRITES_REJECTED
+                    .with_label_values(&["submit", e.reason()])
+                    .inc();
+                e
+            })
+            .into_module_error_other()
     }
 
     async fn apply_input<'a, 'b, 'c>(
         &'a self,
-        interconnect: &'a dyn ModuleInterconect,
+        _interconnect: &'a dyn ModuleInterconect,
         dbtx: &mut DatabaseTransaction<'c>,
         input: &'b Self::Input,
-        cache: &Self::VerificationCache,
+        _cache: &Self::VerificationCache,
     ) -> Result<InputMeta, ModuleError> {
-        info!("Applying input");
+        // Applied once per epoch the transaction is part of, writes rejected here
+        // passed submission and are counted separately from those rejected there
         let meta = self
-            .validate_input(interconnect, dbtx, cache, input)
-            .await?;
-        let input = input.to_owned().0;
-        let pubkey = input.pubkey;
-        let backup = input.backup;
-        let key = ExampleKey(pubkey);
-        let value = backup;
-        dbtx.insert_new_entry(&key, &value).await.expect("DB Error");
+            .check_input(dbtx, input)
+            .await
+            .map_err(|e| {
+                metrics::WRITES_REJECTED
+                    .with_label_values(&["apply", e.reason()])
+                    .inc();
+                e
+            })
+            .into_module_error_other()?;
+        let hash = input.payload.hash();
+        if matches!(
+            input.payload,
+            SmolFSPayload::Inline(_) | SmolFSPayload::Delta { .. }
+        ) {
+            self.record_consensus_blob(hash).await;
+        }
+        let status = match &input.payload {
+            SmolFSPayload::Inline(bytes) => self.blobs.put(bytes).map(|_| BlobStatus::Ok),
+            SmolFSPayload::Uploaded { .. } => self.blobs.check(&hash),
+            SmolFSPayload::Delta { base, delta, .. } => self.apply_delta(base, delta, &hash),
+            SmolFSPayload::Delete => Ok(BlobStatus::Ok),
+        };
+        // The entry is accepted by consensus either way, a blob we failed to store
+        // or never received is treated like one that went missing later
+        let status = status.unwrap_or_else(|e| {
+            error!(%hash, "Failed to store blob: {}", e);
+            BlobStatus::Missing
+        });
+        if status != BlobStatus::Ok {
+            dbtx.insert_entry(&DamagedBlobKey(hash), &status)
+                .await
+                .expect("DB Error");
+        }
+        if let Some(owner) = input.owner {
+            let balance = self.prepaid_balance(dbtx, owner).await;
+            dbtx.insert_entry(&PrepaidBalanceKey(owner), &(balance - input.prepaid))
+                .await
+                .expect("DB Error");
+            dbtx.insert_entry(&EntryOwnerKey(input.pubkey.clone()), &owner)
+                .await
+                .expect("DB Error");
+        }
+        if input.payload == SmolFSPayload::Delete {
+            self.delete_entry(dbtx, input.pubkey.clone()).await;
+            return Ok(meta);
+        }
+        self.commit_version(dbtx, input).await;
+        if input.public {
+            dbtx.insert_entry(&PublicEntryKey(input.pubkey.clone()), &())
+                .await
+                .expect("DB Error");
+        } else {
+            dbtx.remove_entry(&PublicEntryKey(input.pubkey.clone()))
+                .await
+                .expect("DB Error");
+        }
         Ok(meta)
     }
 
     async fn validate_output(
         &self,
         _dbtx: &mut DatabaseTransaction,
-        _output: &Self::Output,
+        output: &Self::Output,
     ) -> Result<TransactionItemAmount, ModuleError> {
-        unimplemented!()
+        if output.amount == Amount::ZERO {
+            return Err(SmolFSError::EmptyPrepayment).into_module_error_other();
+        }
+        Ok(TransactionItemAmount {
+            amount: output.amount,
+            fee: Amount::ZERO,
+        })
     }
 
     async fn apply_output<'a, 'b>(
         &'a self,
-        _dbtx: &mut DatabaseTransaction<'b>,
-        _output: &'a Self::Output,
-        _out_point: OutPoint,
+        dbtx: &mut DatabaseTransaction<'b>,
+        output: &'a Self::Output,
+        out_point: OutPoint,
     ) -> Result<TransactionItemAmount, ModuleError> {
-        unimplemented!()
+        let amount = self.validate_output(dbtx, output).await?;
+        let balance = self.prepaid_balance(dbtx, output.owner).await;
+        dbtx.insert_entry(&PrepaidBalanceKey(output.owner), &(balance + output.amount))
+            .await
+            .expect("DB Error");
+        dbtx.insert_new_entry(&PrepayOutcomeKey(out_point), &SmolFSOutputOutcome)
+            .await
+            .expect("DB Error");
+        Ok(amount)
     }
 
     async fn end_consensus_epoch<'a, 'b>(
         &'a self,
         _consensus_peers: &HashSet<PeerId>,
-        _dbtx: &mut DatabaseTransaction<'b>,
+        dbtx: &mut DatabaseTransaction<'b>,
     ) -> Vec<PeerId> {
+        let seq = dbtx
+            .get_value(&ChangeSeqKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        let writes = seq.saturating_sub(self.epoch_start_seq.load(Ordering::Relaxed));
+        metrics::WRITES_ACCEPTED.inc_by(writes);
+        metrics::WRITES_PER_EPOCH.observe(writes as f64);
+        if writes > 0 {
+            update_storage_metrics(dbtx).await;
+            self.last_change_seq.send_replace(seq);
+        }
         vec![]
     }
 
     async fn output_status(
         &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _out_point: OutPoint,
+        dbtx: &mut DatabaseTransaction<'_>,
+        out_point: OutPoint,
     ) -> Option<Self::OutputOutcome> {
-        None
+        dbtx.get_value(&PrepayOutcomeKey(out_point))
+            .await
+            .expect("DB Error")
     }
 
-    async fn audit(&self, _dbtx: &mut DatabaseTransaction<'_>, _audit: &mut Audit) {}
+    async fn audit(&self, dbtx: &mut DatabaseTransaction<'_>, audit: &mut Audit) {
+        // Prepaid balances are owed to their owners until spent on writes
+        audit
+            .add_items(dbtx, &PrepaidBalanceKeyPrefix, |_, balance| {
+                -(balance.msats as i64)
+            })
+            .await;
+
+        let (entries, bytes) = storage_usage(dbtx).await;
+        audit.add_storage_usage(KIND.as_str(), entries, bytes);
+    }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
-        vec![api_endpoint! {
-            "/smolfs",
-            async |_module: &SmolFS, _dbtx, _request: String| -> () {
-                Ok(())
-            }
-        }]
+        vec![
+            api_endpoint! {
+                "/smolfsget",
+                async |module: &SmolFS, dbtx, pubkey: String| -> Option<BlobResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    record_read("smolfsget", module.get_entry(dbtx, pubkey).await)
+                }
+            },
+            api_endpoint! {
+                "/entry_meta",
+                async |module: &SmolFS, dbtx, key: String| -> Option<EntryMeta> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(dbtx.get_value(&EntryKey(key)).await.expect("DB Error"))
+                }
+            },
+            api_endpoint! {
+                "/list",
+                async |module: &SmolFS, dbtx, prefix: String| -> BTreeMap<String, EntryMeta> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(dbtx
+                        .find_by_prefix(&EntryKeyPrefix)
+                        .await
+                        .map(|res| {
+                            let (key, meta) = res.expect("DB Error");
+                            (key.0, meta)
+                        })
+                        .filter(|(key, _)| key.starts_with(prefix.as_str()))
+                        .collect())
+                }
+            },
+            api_endpoint! {
+                "/versions",
+                async |module: &SmolFS, dbtx, key: String| -> BTreeMap<u64, VersionMeta> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(dbtx
+                        .find_by_prefix(&EntryVersionPrefix(key))
+                        .await
+                        .map(|res| {
+                            let (key, meta) = res.expect("DB Error");
+                            (key.version, meta)
+                        })
+                        .collect())
+                }
+            },
+            api_endpoint! {
+                "/watch",
+                async |module: &SmolFS, dbtx, request: WatchRequest| -> Option<ChangeEvent> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(module.watch(request).await)
+                }
+            },
+            api_endpoint! {
+                "/fetch_version",
+                async |module: &SmolFS, dbtx, request: FetchVersionRequest| -> Option<BlobResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    record_read("fetch_version", module.fetch_version(dbtx, request).await)
+                }
+            },
+            api_endpoint! {
+                "/read",
+                async |module: &SmolFS, dbtx, request: ReadRequest| -> Option<BlobResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    record_read("read", module.read_entry(dbtx, request).await)
+                }
+            },
+            api_endpoint! {
+                "/read_capability",
+                async |module: &SmolFS, dbtx, request: CapabilityReadRequest| -> Option<BlobResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    record_read("read_capability", module.read_with_capability(dbtx, request).await)
+                }
+            },
+            api_endpoint! {
+                "/entry_owner",
+                async |module: &SmolFS, dbtx, key: String| -> Option<XOnlyPublicKey> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(dbtx.get_value(&EntryOwnerKey(key)).await.expect("DB Error"))
+                }
+            },
+            api_endpoint! {
+                "/upload_start",
+                async |module: &SmolFS, dbtx, request: UploadStartRequest| -> u64 {
+                    module.start_upload(dbtx, request).await
+                }
+            },
+            api_endpoint! {
+                "/upload_push",
+                async |module: &SmolFS, _dbtx, request: UploadPushRequest| -> u64 {
+                    module.uploads.push(request)
+                }
+            },
+            api_endpoint! {
+                "/upload_finalize",
+                async |module: &SmolFS, dbtx, request: UploadFinalizeRequest| -> schnorr::Signature {
+                    module.finalize_upload(dbtx, request).await
+                }
+            },
+            api_endpoint! {
+                "/fetch_blob",
+                async |module: &SmolFS, dbtx, request: FetchBlobRequest| -> Option<BlobResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    record_read("fetch_blob", module.fetch_blob(request))
+                }
+            },
+            api_endpoint! {
+                "/challenge",
+                async |module: &SmolFS, dbtx, request: ChallengeRequest| -> Option<ChallengeResponse> {
+                    module.ensure_caught_up(dbtx).await?;
+                    module.answer_challenge(dbtx, request).await
+                }
+            },
+            api_endpoint! {
+                "/signed_root",
+                async |_module: &SmolFS, dbtx, _request: ()| -> Option<SignedRoot> {
+                    Ok(dbtx.get_value(&SignedRootKey).await.expect("DB Error"))
+                }
+            },
+            api_endpoint! {
+                "/prepaid_balance",
+                async |module: &SmolFS, dbtx, owner: XOnlyPublicKey| -> Amount {
+                    Ok(module.prepaid_balance(dbtx, owner).await)
+                }
+            },
+            api_endpoint! {
+                "/repair_status",
+                async |_module: &SmolFS, dbtx, _request: ()| -> RepairStatus {
+                    Ok(dbtx.get_value(&RepairStatusKey).await.expect("DB Error").unwrap_or_default())
+                }
+            },
+            api_endpoint! {
+                "/catchup_status",
+                async |_module: &SmolFS, dbtx, _request: ()| -> Option<CatchUpStatus> {
+                    Ok(dbtx.get_value(&CatchUpStatusKey).await.expect("DB Error"))
+                }
+            },
+            api_endpoint! {
+                "/state_root",
+                async |module: &SmolFS, dbtx, _request: ()| -> AttestedStateRoot {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(catchup::attest_state_root(dbtx, &module.secp, &module.cfg.private.root_key).await)
+                }
+            },
+            api_endpoint! {
+                "/catchup_entries",
+                async |module: &SmolFS, dbtx, request: CatchUpEntriesRequest| -> Vec<ArchivedEntry> {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(catchup::entries_page(dbtx, request).await)
+                }
+            },
+            api_endpoint! {
+                "/catchup_snapshot",
+                async |module: &SmolFS, dbtx, _request: ()| -> ConsensusSnapshot {
+                    module.ensure_caught_up(dbtx).await?;
+                    Ok(catchup::consensus_snapshot(dbtx).await)
+                }
+            },
+        ]
     }
 }
 
 impl SmolFS {
-    /// Create new module instance
-    pub fn new(cfg: SmolFSConfig) -> SmolFS {
-        SmolFS { cfg }
+    /// Create new module instance, opening the blob store configured in the local config
+    pub fn new(cfg: SmolFSConfig, db: Database) -> anyhow::Result<SmolFS> {
+        let blobs = BlobStore::open(&cfg.local.blob_dir)?;
+        Ok(SmolFS {
+            cfg,
+            db,
+            blobs,
+            uploads: UploadSessions::default(),
+            secp: Secp256k1::new(),
+            epoch_start_seq: AtomicU64::new(0),
+            last_change_seq: watch::channel(0).0,
+            public_tree: PublicTree::default(),
+        })
+    }
+
+    /// Checks a write against the consensus state, see
+    /// [`ServerModule::validate_input`]
+    async fn check_input(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        input: &SmolFSInput,
+    ) -> Result<InputMeta, SmolFSError> {
+        if let Some(base_difficulty) = self.cfg.consensus.pow_difficulty {
+            let required = pow::required_bits(base_difficulty, input.payload.size());
+            let actual = pow::pow_bits(input);
+            if actual < required {
+                return Err(SmolFSError::InsufficientProofOfWork { required, actual });
+            }
+        }
+        if let Some(expected) = input.expected_version {
+            let actual = dbtx
+                .get_value(&EntryKey(input.pubkey.clone()))
+                .await
+                .expect("DB Error")
+                .map(|meta| meta.version);
+            if actual != Some(expected) {
+                return Err(SmolFSError::VersionMismatch {
+                    key: input.pubkey.clone(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+        match &input.payload {
+            SmolFSPayload::Inline(bytes) => {
+                if bytes.len() as u64 > MAX_INLINE_PAYLOAD_SIZE {
+                    return Err(SmolFSError::InlinePayloadTooLarge(bytes.len() as u64));
+                }
+            }
+            SmolFSPayload::Uploaded {
+                hash,
+                size,
+                receipts,
+            } => {
+                if !self
+                    .is_retained_version(dbtx, &input.pubkey, hash, *size)
+                    .await
+                {
+                    let root_keys = &self.cfg.consensus.root_keys;
+                    let message = upload_receipt_message(hash, *size);
+                    let valid = receipts
+                        .iter()
+                        .filter(|(peer, signature)| {
+                            root_keys.get(peer).map_or(false, |key| {
+                                self.secp.verify_schnorr(signature, &message, key).is_ok()
+                            })
+                        })
+                        .count();
+                    if valid < root_keys.threshold() {
+                        return Err(SmolFSError::InsufficientUploadReceipts {
+                            valid,
+                            required: root_keys.threshold(),
+                        });
+                    }
+                }
+            }
+            SmolFSPayload::Delete => {
+                if dbtx
+                    .get_value(&EntryKey(input.pubkey.clone()))
+                    .await
+                    .expect("DB Error")
+                    .is_none()
+                {
+                    return Err(SmolFSError::EntryNotFound(input.pubkey.clone()));
+                }
+            }
+            SmolFSPayload::Delta { base, delta, .. } => {
+                let encoded_size = delta
+                    .consensus_encode_to_vec()
+                    .expect("Encoding to vec can't fail")
+                    .len() as u64;
+                if encoded_size > MAX_INLINE_PAYLOAD_SIZE {
+                    return Err(SmolFSError::InlinePayloadTooLarge(encoded_size));
+                }
+                // Only depends on the consensus state, the base blob itself might be
+                // damaged on some guardians
+                let current = dbtx
+                    .get_value(&EntryKey(input.pubkey.clone()))
+                    .await
+                    .expect("DB Error")
+                    .filter(|meta| meta.hash == *base)
+                    .ok_or(SmolFSError::DeltaBaseMismatch(*base))?;
+                delta
+                    .check_ranges(current.size)
+                    .map_err(SmolFSError::InvalidDelta)?;
+            }
+        }
+
+        // The write fee is charged as the input's fee, the prepaid part is brought in
+        // as the input's amount and everything else has to come from other inputs
+        let fee = write_fee(self.cfg.consensus.write_fee_per_kib, input.payload.size());
+        if input.prepaid > fee {
+            return Err(SmolFSError::PrepaidExceedsFee {
+                prepaid: input.prepaid,
+                fee,
+            });
+        }
+        let Some(owner) = input.owner else {
+            return Err(SmolFSError::MissingOwner);
+        };
+        let entry_owner = dbtx
+            .get_value(&EntryOwnerKey(input.pubkey.clone()))
+            .await
+            .expect("DB Error");
+        match entry_owner {
+            Some(entry_owner) if entry_owner != owner => {
+                return Err(SmolFSError::NotEntryOwner(input.pubkey.clone()));
+            }
+            Some(_) => {}
+            // Entries without an owner were migrated from legacy entries that anyone
+            // could write, so only the key the entry is named after may claim them
+            None if self.entry_exists(dbtx, &input.pubkey).await
+                && XOnlyPublicKey::from_str(&input.pubkey).ok() != Some(owner) =>
+            {
+                return Err(SmolFSError::UnclaimedEntry(input.pubkey.clone()));
+            }
+            None => {}
+        }
+        let balance = self.prepaid_balance(dbtx, owner).await;
+        if balance < input.prepaid {
+            return Err(SmolFSError::InsufficientPrepaidBalance {
+                balance,
+                required: input.prepaid,
+            });
+        }
+
+        Ok(InputMeta {
+            amount: TransactionItemAmount {
+                amount: input.prepaid,
+                fee,
+            },
+            puk_keys: vec![owner],
+        })
+    }
+
+    /// Whether `key` has a current or retained version
+    async fn entry_exists(&self, dbtx: &mut DatabaseTransaction<'_>, key: &str) -> bool {
+        dbtx.find_by_prefix(&EntryVersionPrefix(key.to_string()))
+            .await
+            .next()
+            .is_some()
+    }
+
+    /// Whether `key` retains a version with the given payload, which then doesn't have to be
+    /// uploaded again to be restored
+    async fn is_retained_version(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        key: &str,
+        hash: &sha256::Hash,
+        size: u64,
+    ) -> bool {
+        dbtx.find_by_prefix(&EntryVersionPrefix(key.to_string()))
+            .await
+            .map(|res| res.expect("DB Error").1)
+            .any(|version| version.hash == *hash && version.size == size)
+    }
+
+    /// Fails while this guardian is still catching up, see [`catchup`]
+    async fn ensure_caught_up(&self, dbtx: &mut DatabaseTransaction<'_>) -> Result<(), ApiError> {
+        catchup::ensure_caught_up(dbtx, self.cfg.local.catch_up).await
+    }
+
+    async fn prepaid_balance(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        owner: XOnlyPublicKey,
+    ) -> Amount {
+        dbtx.get_value(&PrepaidBalanceKey(owner))
+            .await
+            .expect("DB Error")
+            .unwrap_or(Amount::ZERO)
     }
+
+    /// Number of epochs processed so far, including the current one
+    async fn epoch_count(&self, dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+        dbtx.get_value(&EpochCountKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0)
+    }
+
+    /// Makes the payload of `entry` the new current version of the entry, dropping
+    /// versions that exceed the configured retention
+    async fn commit_version(&self, dbtx: &mut DatabaseTransaction<'_>, entry: &SmolFSEntry) {
+        let key = entry.pubkey.clone();
+        let hash = entry.payload.hash();
+        let size = entry.payload.size();
+        // Continues after the latest retained version so deleted entries don't
+        // reuse version numbers
+        let version = dbtx
+            .find_by_prefix(&EntryVersionPrefix(key.clone()))
+            .await
+            .map(|res| res.expect("DB Error").0.version)
+            .max()
+            .map_or(0, |latest| latest + 1);
+
+        dbtx.insert_entry(
+            &EntryVersionKey {
+                key: key.clone(),
+                version,
+            },
+            &VersionMeta {
+                hash,
+                size,
+                timestamp: entry.timestamp,
+            },
+        )
+        .await
+        .expect("DB Error");
+        self.add_blob_ref(dbtx, hash).await;
+        dbtx.remove_entry(&TombstoneKey(key.clone()))
+            .await
+            .expect("DB Error");
+
+        let retained = u64::from(self.cfg.consensus.max_versions);
+        let expired = dbtx
+            .find_by_prefix(&EntryVersionPrefix(key.clone()))
+            .await
+            .map(|res| res.expect("DB Error"))
+            .filter(|(version_key, _)| version_key.version + retained <= version)
+            .collect::<Vec<_>>();
+        for (version_key, meta) in expired {
+            dbtx.remove_entry(&version_key).await.expect("DB Error");
+            self.remove_blob_ref(dbtx, meta.hash).await;
+        }
+
+        dbtx.insert_entry(
+            &EntryKey(key.clone()),
+            &EntryMeta {
+                hash,
+                size,
+                version,
+            },
+        )
+        .await
+        .expect("DB Error");
+        self.record_change(dbtx, key, version, hash).await;
+    }
+
+    /// Removes the entry `key`, keeping its retained versions until its tombstone is
+    /// purged, see [`SmolFS::purge_tombstones`]
+    async fn delete_entry(&self, dbtx: &mut DatabaseTransaction<'_>, key: String) {
+        let meta = dbtx
+            .get_value(&EntryKey(key.clone()))
+            .await
+            .expect("DB Error")
+            .expect("checked during validation");
+        dbtx.remove_entry(&EntryKey(key.clone()))
+            .await
+            .expect("DB Error");
+        dbtx.remove_entry(&PublicEntryKey(key.clone()))
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(
+            &TombstoneKey(key.clone()),
+            &Tombstone {
+                deleted_epoch: self.epoch_count(dbtx).await,
+            },
+        )
+        .await
+        .expect("DB Error");
+        self.record_change(dbtx, key, meta.version, sha256::Hash::all_zeros())
+            .await;
+    }
+
+    /// Appends a write to the change log, dropping the oldest change once the log
+    /// is full
+    async fn record_change(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        key: String,
+        version: u64,
+        hash: sha256::Hash,
+    ) {
+        let seq = dbtx
+            .get_value(&ChangeSeqKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0)
+            + 1;
+        dbtx.insert_entry(&ChangeSeqKey, &seq)
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(
+            &ChangeKey(seq),
+            &ChangeEvent {
+                seq,
+                key,
+                version,
+                hash,
+            },
+        )
+        .await
+        .expect("DB Error");
+        if seq > CHANGE_LOG_SIZE {
+            dbtx.remove_entry(&ChangeKey(seq - CHANGE_LOG_SIZE))
+                .await
+                .expect("DB Error");
+        }
+    }
+
+    /// Waits up to [`WATCH_TIMEOUT`] for a change matching the request, returning
+    /// the oldest one still in the change log
+    ///
+    /// Only a single change is returned at a time so that guardians at slightly
+    /// different points in consensus still give the same answer. Every change is
+    /// looked up once, after that the request sleeps until a new epoch is committed.
+    async fn watch(&self, request: WatchRequest) -> Option<ChangeEvent> {
+        let started = Instant::now();
+        let mut announced = self.last_change_seq.subscribe();
+        let mut next = request.since.saturating_add(1);
+        loop {
+            let mut dbtx = self.db.begin_transaction().await;
+            let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+            let committed = dbtx
+                .get_value(&ChangeSeqKey)
+                .await
+                .expect("DB Error")
+                .unwrap_or(0);
+            next = std::cmp::max(next, committed.saturating_sub(CHANGE_LOG_SIZE) + 1);
+            while next <= committed {
+                let change = dbtx.get_value(&ChangeKey(next)).await.expect("DB Error");
+                next += 1;
+                if let Some(change) = change.filter(|change| request.target.matches(&change.key)) {
+                    return Some(change);
+                }
+            }
+            drop(dbtx);
+
+            let remaining = WATCH_TIMEOUT.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return None;
+            }
+            if *announced.borrow_and_update() > committed {
+                // The epoch was processed but isn't committed yet
+                sleep(std::cmp::min(WATCH_COMMIT_POLL_INTERVAL, remaining)).await;
+            } else if timeout(remaining, announced.changed()).await.is_err() {
+                return None;
+            }
+        }
+    }
+
+    async fn add_blob_ref(&self, dbtx: &mut DatabaseTransaction<'_>, hash: sha256::Hash) {
+        let refs = dbtx
+            .get_value(&BlobRefKey(hash))
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        dbtx.insert_entry(&BlobRefKey(hash), &(refs + 1))
+            .await
+            .expect("DB Error");
+    }
+
+    /// Drops a reference to a blob, queueing it for deletion once no retained version uses it
+    async fn remove_blob_ref(&self, dbtx: &mut DatabaseTransaction<'_>, hash: sha256::Hash) {
+        let refs = dbtx
+            .get_value(&BlobRefKey(hash))
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        if refs > 1 {
+            dbtx.insert_entry(&BlobRefKey(hash), &(refs - 1))
+                .await
+                .expect("DB Error");
+            return;
+        }
+
+        dbtx.remove_entry(&BlobRefKey(hash))
+            .await
+            .expect("DB Error");
+        dbtx.remove_entry(&DamagedBlobKey(hash))
+            .await
+            .expect("DB Error");
+        dbtx.insert_entry(&PendingBlobGcKey(hash), &())
+            .await
+            .expect("DB Error");
+    }
+
+    /// Deletes the blobs whose last reference was dropped in an earlier epoch
+    ///
+    /// Deleting them while applying the epoch that dropped the reference would lose
+    /// the blobs if that epoch isn't committed. Here the drop is committed already and,
+    /// unlike a background task, we can't race with an epoch referencing them again.
+    async fn collect_garbage(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let pending = dbtx
+            .find_by_prefix(&PendingBlobGcKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error").0)
+            .collect::<Vec<_>>();
+        for key in pending {
+            let referenced = dbtx
+                .get_value(&BlobRefKey(key.0))
+                .await
+                .expect("DB Error")
+                .is_some();
+            // Uploaded again in the meantime, removed by the upload cleanup if no entry
+            // is written with it
+            let uploaded = dbtx
+                .get_value(&UploadedBlobKey(key.0))
+                .await
+                .expect("DB Error")
+                .is_some();
+            if !referenced && !uploaded {
+                if let Err(e) = self.blobs.remove(&key.0) {
+                    // Only wastes disk space, the blob is unreachable either way
+                    warn!(hash = %key.0, "Failed to remove unreferenced blob: {}", e);
+                }
+            }
+            dbtx.remove_entry(&key).await.expect("DB Error");
+        }
+    }
+
+    /// Drops the retained versions of entries deleted more than
+    /// [`SmolFSConfigConsensus::tombstone_retention_epochs`] epochs ago, after which
+    /// they can't be restored anymore
+    ///
+    /// Epochs are counted in consensus, so all guardians purge in the same epoch. The
+    /// blobs are deleted once the purge is committed, like those of expired versions.
+    async fn purge_tombstones(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let epoch = self.epoch_count(dbtx).await + 1;
+        dbtx.insert_entry(&EpochCountKey, &epoch)
+            .await
+            .expect("DB Error");
+
+        let retention = self.cfg.consensus.tombstone_retention_epochs;
+        let expired = dbtx
+            .find_by_prefix(&TombstoneKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error"))
+            .filter(|(_, tombstone)| tombstone.deleted_epoch + retention < epoch)
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+        for key in expired {
+            let versions = dbtx
+                .find_by_prefix(&EntryVersionPrefix(key.0.clone()))
+                .await
+                .map(|res| res.expect("DB Error"))
+                .collect::<Vec<_>>();
+            for (version_key, meta) in versions {
+                dbtx.remove_entry(&version_key).await.expect("DB Error");
+                self.remove_blob_ref(dbtx, meta.hash).await;
+            }
+            dbtx.remove_entry(&key).await.expect("DB Error");
+        }
+    }
+
+    /// Re-hashes the blobs of all retained versions, recording missing or corrupt
+    /// ones and forgetting damage that has been repaired since the last check
+    pub async fn check_blob_integrity(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let entries = dbtx
+            .find_by_prefix(&EntryVersionKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error").1)
+            .collect::<Vec<_>>();
+        dbtx.remove_by_prefix(&DamagedBlobKeyPrefix)
+            .await
+            .expect("DB Error");
+
+        let mut damaged = 0;
+        for VersionMeta { hash, .. } in entries {
+            let status = self.blobs.check(&hash).unwrap_or_else(|e| {
+                error!(%hash, "Failed to read blob: {}", e);
+                BlobStatus::Missing
+            });
+            if status != BlobStatus::Ok {
+                warn!(%hash, ?status, "Damaged blob found during integrity check");
+                dbtx.insert_entry(&DamagedBlobKey(hash), &status)
+                    .await
+                    .expect("DB Error");
+                damaged += 1;
+            }
+        }
+        info!(damaged, "SmolFS blob integrity check finished");
+    }
+
+    /// Applies a delta to our copy of the `base` blob, storing the result if it has
+    /// the expected hash
+    ///
+    /// If the base blob is damaged or the delta doesn't produce the announced hash
+    /// the result is reported as missing, so it gets fetched from our peers like
+    /// any other damaged blob.
+    fn apply_delta(
+        &self,
+        base: &sha256::Hash,
+        delta: &Delta,
+        hash: &sha256::Hash,
+    ) -> std::io::Result<BlobStatus> {
+        if self.blobs.check(hash)? == BlobStatus::Ok {
+            return Ok(BlobStatus::Ok);
+        }
+        let base_bytes = match self.blobs.get(base)? {
+            Some(bytes) if sha256::Hash::hash(&bytes) == *base => bytes,
+            _ => {
+                warn!(%base, "Can't apply delta to damaged base blob");
+                return Ok(BlobStatus::Missing);
+            }
+        };
+
+        let result = match delta.apply(&base_bytes) {
+            Ok(result) if sha256::Hash::hash(&result) == *hash => result,
+            Ok(_) => {
+                warn!(%base, %hash, "Delta doesn't produce the announced hash");
+                return Ok(BlobStatus::Missing);
+            }
+            Err(e) => {
+                warn!(%base, %hash, "Failed to apply delta: {}", e);
+                return Ok(BlobStatus::Missing);
+            }
+        };
+        self.blobs.put(&result)?;
+        Ok(BlobStatus::Ok)
+    }
+
+    /// Serves a range of a blob to a peer repairing its store
+    ///
+    /// The blob is checked when its first chunk is requested, so damaged blobs are reported
+    /// as missing. Later chunks are served without hashing the whole blob again, the peer
+    /// verifies the assembled blob anyway.
+    fn fetch_blob(&self, request: FetchBlobRequest) -> Result<Option<BlobResponse>, ApiError> {
+        let peer = request
+            .verify(&self.secp, &self.cfg.consensus.root_keys, SystemTime::now())
+            .ok_or_else(|| ApiError::new(403, "Blobs are only served to guardians".to_string()))?;
+        if request.len > MAX_CHUNK_SIZE {
+            return Err(ApiError::bad_request(format!(
+                "Read of {} bytes exceeds the maximum of {MAX_CHUNK_SIZE}",
+                request.len
+            )));
+        }
+        debug!(%peer, hash = %request.hash, offset = request.offset, "Serving blob to peer");
+        let hash = request.hash;
+        if request.offset == 0 {
+            match self.blobs.check(&hash) {
+                Ok(BlobStatus::Ok) => {}
+                Ok(_) => return Ok(None),
+                Err(e) => return Err(ApiError::new(500, format!("Failed to read blob: {e}"))),
+            }
+        }
+        let bytes = self
+            .blobs
+            .read_range(&hash, request.offset, request.len)
+            .map_err(|e| ApiError::new(500, format!("Failed to read blob: {e}")))?;
+        Ok(bytes.map(|bytes| BlobResponse { bytes }))
+    }
+
+    /// Proves we still hold the payload of an entry by hashing the requested chunk
+    /// together with the client's nonce
+    ///
+    /// Only the challenged chunk is read from disk. Damaged blobs are not recorded here since
+    /// anyone can call this endpoint, the integrity check on startup finds them. Failed
+    /// challenges are counted in [`metrics::CHALLENGES_FAILED`] so operators notice them
+    /// before the next restart.
+    async fn answer_challenge(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: ChallengeRequest,
+    ) -> Result<Option<ChallengeResponse>, ApiError> {
+        let Some(meta) = dbtx
+            .get_value(&EntryKey(request.key))
+            .await
+            .expect("DB Error")
+        else {
+            return Ok(None);
+        };
+        if request.chunk >= challenge_chunk_count(meta.size as usize) {
+            return Err(ApiError::bad_request(format!(
+                "Chunk {} out of range",
+                request.chunk
+            )));
+        }
+
+        let offset = request.chunk * CHALLENGE_CHUNK_SIZE as u64;
+        match self
+            .blobs
+            .read_range(&meta.hash, offset, CHALLENGE_CHUNK_SIZE as u64)
+        {
+            Ok(Some(chunk)) => Ok(Some(challenge_response(&request.nonce, &chunk))),
+            Ok(None) => {
+                warn!(hash = %meta.hash, "Failed challenge due to missing blob");
+                metrics::CHALLENGES_FAILED
+                    .with_label_values(&["missing"])
+                    .inc();
+                Ok(None)
+            }
+            Err(e) => {
+                metrics::CHALLENGES_FAILED
+                    .with_label_values(&["read_error"])
+                    .inc();
+                Err(ApiError::new(500, format!("Failed to read blob: {e}")))
+            }
+        }
+    }
+
+    async fn fetch_version(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: FetchVersionRequest,
+    ) -> Result<Option<BlobResponse>, ApiError> {
+        self.authorize_read(dbtx, &request.key, request.capability.as_ref())
+            .await?;
+        let Some(meta) = dbtx
+            .get_value(&EntryVersionKey {
+                key: request.key,
+                version: request.version,
+            })
+            .await
+            .expect("DB Error")
+        else {
+            return Ok(None);
+        };
+        self.read_blob_range(&meta.hash, request.offset, request.len)
+            .map(Some)
+    }
+
+    async fn get_entry(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        pubkey: String,
+    ) -> Result<Option<BlobResponse>, ApiError> {
+        self.authorize_read(dbtx, &pubkey, None).await?;
+        let Some(meta) = dbtx.get_value(&EntryKey(pubkey)).await.expect("DB Error") else {
+            return Ok(None);
+        };
+        self.read_small_blob(&meta.hash, meta.size).map(Some)
+    }
+
+    /// Reads a whole blob that is small enough to be returned in a single response
+    fn read_small_blob(&self, hash: &sha256::Hash, size: u64) -> Result<BlobResponse, ApiError> {
+        if size > MAX_CHUNK_SIZE {
+            return Err(ApiError::bad_request(format!(
+                "Entry of {size} bytes is too large, use ranged reads"
+            )));
+        }
+        let bytes = self
+            .blobs
+            .get(hash)
+            .map_err(|e| ApiError::new(500, format!("Failed to read blob: {e}")))?
+            .ok_or_else(|| ApiError::not_found(format!("Blob {hash} is missing")))?;
+        Ok(BlobResponse { bytes })
+    }
+
+    async fn read_entry(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: ReadRequest,
+    ) -> Result<Option<BlobResponse>, ApiError> {
+        self.authorize_read(dbtx, &request.key, request.capability.as_ref())
+            .await?;
+        self.read_entry_range(dbtx, &request.key, request.offset, request.len)
+            .await
+    }
+
+    /// Reads a range of the current payload of `key` without checking who may read it
+    async fn read_entry_range(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        key: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Option<BlobResponse>, ApiError> {
+        let Some(meta) = dbtx
+            .get_value(&EntryKey(key.to_string()))
+            .await
+            .expect("DB Error")
+        else {
+            return Ok(None);
+        };
+        self.read_blob_range(&meta.hash, offset, len).map(Some)
+    }
+
+    /// Reads at most [`MAX_CHUNK_SIZE`] bytes of a blob starting at `offset`
+    fn read_blob_range(
+        &self,
+        hash: &sha256::Hash,
+        offset: u64,
+        len: u64,
+    ) -> Result<BlobResponse, ApiError> {
+        if len > MAX_CHUNK_SIZE {
+            return Err(ApiError::bad_request(format!(
+                "Read of {len} bytes exceeds the maximum of {MAX_CHUNK_SIZE}"
+            )));
+        }
+        let bytes = self
+            .blobs
+            .read_range(hash, offset, len)
+            .map_err(|e| ApiError::new(500, format!("Failed to read blob: {e}")))?
+            .ok_or_else(|| ApiError::not_found(format!("Blob {hash} is missing")))?;
+        Ok(BlobResponse { bytes })
+    }
+
+    /// Reads an entry on behalf of someone holding a read capability signed by the
+    /// entry's owner
+    async fn read_with_capability(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: CapabilityReadRequest,
+    ) -> Result<Option<BlobResponse>, ApiError> {
+        let capability = &request.capability.capability;
+        let owner = dbtx
+            .get_value(&EntryOwnerKey(capability.key.clone()))
+            .await
+            .expect("DB Error")
+            .ok_or_else(|| ApiError::not_found(format!("Entry {} has no owner", capability.key)))?;
+        self.check_capability(&request.capability, &capability.key, &owner)?;
+
+        let Some(meta) = dbtx
+            .get_value(&EntryKey(capability.key.clone()))
+            .await
+            .expect("DB Error")
+        else {
+            return Ok(None);
+        };
+        let Some(max_reads) = capability.max_reads else {
+            return self
+                .read_entry_range(dbtx, &capability.key, request.offset, request.len)
+                .await;
+        };
+        // Reads are counted in bytes served so reading in chunks counts as a single
+        // read, every guardian counts locally and the api transaction is committed
+        // after the request was answered
+        let served_key = CapabilityReadsKey(capability.id());
+        let served = dbtx
+            .get_value(&served_key)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        if served.saturating_add(request.len.min(meta.size)) > max_reads.saturating_mul(meta.size) {
+            return Err(ApiError::new(403, "Read capability used up".to_string()));
+        }
+
+        let response = self
+            .read_entry_range(dbtx, &capability.key, request.offset, request.len)
+            .await?;
+        if let Some(response) = &response {
+            dbtx.insert_entry(&served_key, &(served + response.bytes.len() as u64))
+                .await
+                .expect("DB Error");
+        }
+        Ok(response)
+    }
+
+    /// Reads of private entries that have an owner need an unexpired capability signed by the
+    /// owner, everything else may be read by anyone
+    ///
+    /// Only unlimited capabilities are accepted here, limited ones have to be used through
+    /// `/read_capability` which counts the reads.
+    async fn authorize_read(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        key: &str,
+        capability: Option<&SignedReadCapability>,
+    ) -> Result<(), ApiError> {
+        let Some(owner) = dbtx
+            .get_value(&EntryOwnerKey(key.to_string()))
+            .await
+            .expect("DB Error")
+        else {
+            return Ok(());
+        };
+        let public = dbtx
+            .get_value(&PublicEntryKey(key.to_string()))
+            .await
+            .expect("DB Error")
+            .is_some();
+        if public {
+            return Ok(());
+        }
+
+        let capability = capability.ok_or_else(|| {
+            ApiError::new(
+                403,
+                format!("Entry {key} is private, reading it needs a read capability"),
+            )
+        })?;
+        self.check_capability(capability, key, &owner)?;
+        if capability.capability.max_reads.is_some() {
+            return Err(ApiError::new(
+                403,
+                "Limited read capabilities can only be used through /read_capability".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that `capability` grants reading `key` and was signed by `owner`
+    fn check_capability(
+        &self,
+        capability: &SignedReadCapability,
+        key: &str,
+        owner: &XOnlyPublicKey,
+    ) -> Result<(), ApiError> {
+        if capability.capability.key != key {
+            return Err(ApiError::new(
+                403,
+                format!("Read capability wasn't granted for entry {key}"),
+            ));
+        }
+        if capability.capability.expiry < unix_secs(SystemTime::now()) {
+            return Err(ApiError::new(403, "Read capability expired".to_string()));
+        }
+        if !capability.verify(&self.secp, owner) {
+            return Err(ApiError::new(
+                403,
+                "Read capability not signed by the entry owner".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Opens an upload session if the upload fits the limits from our local config and the
+    /// owner's prepaid balance pays for the reserved space
+    async fn start_upload(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: UploadStartRequest,
+    ) -> Result<u64, ApiError> {
+        if !request.verify(&self.secp, SystemTime::now()) {
+            return Err(ApiError::new(
+                403,
+                "Upload start request isn't signed by its owner".to_string(),
+            ));
+        }
+        if request.size > self.cfg.local.max_upload_size {
+            metrics::WRITES_REJECTED
+                .with_label_values(&["upload", "upload_too_large"])
+                .inc();
+            return Err(ApiError::bad_request(format!(
+                "Upload of {} bytes exceeds the maximum of {}",
+                request.size, self.cfg.local.max_upload_size
+            )));
+        }
+        let available = self
+            .cfg
+            .local
+            .max_disk_usage
+            .saturating_sub(self.blobs.disk_usage());
+        let balance = self.prepaid_balance(dbtx, request.owner).await;
+        let fee_per_kib = self.cfg.consensus.write_fee_per_kib;
+        let max_owner_size = if fee_per_kib == Amount::ZERO {
+            u64::MAX
+        } else {
+            (balance.msats / fee_per_kib.msats).saturating_mul(1024)
+        };
+        self.uploads.start(
+            &self.blobs,
+            request.owner,
+            request.size,
+            available,
+            max_owner_size,
+        )
+    }
+
+    /// Records a blob that is about to be stored while applying an input like an
+    /// upload, in its own transaction
+    ///
+    /// The blob is written before the epoch is committed. Should the input be rolled
+    /// back, nothing references the blob and it is removed like an upload that was
+    /// never written to an entry.
+    async fn record_consensus_blob(&self, hash: sha256::Hash) {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
+            .insert_entry(&UploadedBlobKey(hash), &SystemTime::now())
+            .await
+            .expect("DB Error");
+        if let Err(e) = dbtx.commit_tx().await {
+            // Only conflicts with an upload of the same payload, which records it too
+            warn!(%hash, "Failed to record blob written by consensus: {}", e);
+        }
+    }
+
+    /// Moves a finished upload into the blob store and returns our receipt for it
+    async fn finalize_upload(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: UploadFinalizeRequest,
+    ) -> Result<schnorr::Signature, ApiError> {
+        let hash = request.hash;
+        let size = self.uploads.finalize(&self.blobs, request)?;
+        dbtx.insert_entry(&UploadedBlobKey(hash), &SystemTime::now())
+            .await
+            .expect("DB Error");
+
+        let key = KeyPair::from_secret_key(&self.secp, &self.cfg.private.root_key);
+        Ok(self
+            .secp
+            .sign_schnorr(&upload_receipt_message(&hash, size), &key))
+    }
+}
+
+/// Counts a read answered through `endpoint` in [`metrics::API_READS`] and the
+/// bytes served in [`metrics::API_READ_BYTES`]
+fn record_read(
+    endpoint: &str,
+    response: Result<Option<BlobResponse>, ApiError>,
+) -> Result<Option<BlobResponse>, ApiError> {
+    if let Ok(blob) = &response {
+        metrics::API_READS.with_label_values(&[endpoint]).inc();
+        let bytes = blob.as_ref().map_or(0, |blob| blob.bytes.len() as u64);
+        metrics::API_READ_BYTES
+            .with_label_values(&[endpoint])
+            .inc_by(bytes);
+    }
+    response
+}
+
+/// Number of entries and bytes of all retained versions
+async fn storage_usage(dbtx: &mut DatabaseTransaction<'_>) -> (u64, u64) {
+    let entries = dbtx.find_by_prefix(&EntryKeyPrefix).await.count() as u64;
+    // Versions share blobs with identical payloads, which are only stored once
+    let blobs = dbtx
+        .find_by_prefix(&EntryVersionKeyPrefix)
+        .await
+        .map(|res| {
+            let (_, meta) = res.expect("DB Error");
+            (meta.hash, meta.size)
+        })
+        .collect::<HashMap<_, _>>();
+    (entries, blobs.values().sum())
+}
+
+async fn update_storage_metrics(dbtx: &mut DatabaseTransaction<'_>) {
+    let (entries, bytes) = storage_usage(dbtx).await;
+    metrics::ENTRIES.set(entries as i64);
+    metrics::STORED_BYTES.set(bytes as i64);
 }
 
-// Must be unique.
-// TODO: we need to provide guidence for allocating these
-pub const MODULE_KEY_SMOLFS: u16 = 128;
 plugin_types_trait_impl!(
-    MODULE_KEY_SMOLFS,
+    LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
     SmolFSInput,
     SmolFSOutput,
     SmolFSOutputOutcome,
-    SmolFSOutputConfirmation,
+    SmolFSConsensusItem,
     SmolFSVerificationCache
 );
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Error)]
 pub enum SmolFSError {
-    #[error("Something went wrong")]
-    SomethingDummyWentWrong,
+    #[error("Inline payload of {0} bytes exceeds the maximum of {MAX_INLINE_PAYLOAD_SIZE}")]
+    InlinePayloadTooLarge(u64),
+    #[error("Delta base {0} is not the current payload of the entry")]
+    DeltaBaseMismatch(sha256::Hash),
+    #[error("Invalid delta: {0}")]
+    InvalidDelta(DeltaError),
+    #[error("Proof-of-work has {actual} leading zero bits, {required} are required")]
+    InsufficientProofOfWork { required: u32, actual: u32 },
+    #[error("Prepaid amount {prepaid} exceeds the write fee of {fee}")]
+    PrepaidExceedsFee { prepaid: Amount, fee: Amount },
+    #[error("Prepaid balance of {balance} is less than the required {required}")]
+    InsufficientPrepaidBalance { balance: Amount, required: Amount },
+    #[error("Writes require an owner")]
+    MissingOwner,
+    #[error("Prepayments must not be empty")]
+    EmptyPrepayment,
+    #[error("Entry {0} belongs to a different owner")]
+    NotEntryOwner(String),
+    #[error("Entry {0} has no owner and can only be claimed by the key it is named after")]
+    UnclaimedEntry(String),
+    #[error("Entry {0} doesn't exist")]
+    EntryNotFound(String),
+    #[error("Entry {key} is at version {actual:?}, expected {expected}")]
+    VersionMismatch {
+        key: String,
+        expected: u64,
+        actual: Option<u64>,
+    },
+    #[error("Upload has {valid} valid receipts, {required} are required")]
+    InsufficientUploadReceipts { valid: usize, required: usize },
+}
+
+impl SmolFSError {
+    /// Short label of the error for [`metrics::WRITES_REJECTED`]
+    ///
+    /// Transaction signatures are checked by the server, writes to an entry of
+    /// another owner are rejected as `not_owner` by the module.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            SmolFSError::InlinePayloadTooLarge(_) => "too_large",
+            SmolFSError::DeltaBaseMismatch(_) => "stale_version",
+            SmolFSError::InvalidDelta(_) => "invalid_delta",
+            SmolFSError::InsufficientProofOfWork { .. } => "insufficient_pow",
+            SmolFSError::PrepaidExceedsFee { .. } => "invalid_fee",
+            SmolFSError::InsufficientPrepaidBalance { .. } => "quota",
+            SmolFSError::MissingOwner => "not_owner",
+            SmolFSError::EmptyPrepayment => "invalid_fee",
+            SmolFSError::NotEntryOwner(_) => "not_owner",
+            SmolFSError::UnclaimedEntry(_) => "not_owner",
+            SmolFSError::EntryNotFound(_) => "not_found",
+            SmolFSError::VersionMismatch { .. } => "stale_version",
+            SmolFSError::InsufficientUploadReceipts { .. } => "unverified_upload",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use async_trait::async_trait;
+    use bitcoin::hashes::{sha256, Hash};
+    use fedimint_api::config::ConfigGenParams;
+    use fedimint_api::core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_SMOLFS};
+    use fedimint_api::db::mem_impl::MemDatabase;
+    use fedimint_api::db::Database;
+    use fedimint_api::module::__reexports::serde_json;
+    use fedimint_api::module::interconnect::ModuleInterconect;
+    use fedimint_api::module::registry::ModuleDecoderRegistry;
+    use fedimint_api::module::{ApiError, ModuleGen};
+    use fedimint_api::task::sleep;
+    use fedimint_api::{Amount, PeerId, ServerModule};
+    use secp256k1::{KeyPair, Secp256k1, XOnlyPublicKey};
+
+    use crate::common::{ChangeEvent, SmolFSDecoder, WatchRequest, WatchTarget};
+    use crate::config::{SmolFSConfig, DEFAULT_TOMBSTONE_RETENTION_EPOCHS};
+    use crate::db::{ChangeKey, ChangeSeqKey};
+    use crate::{
+        SmolFS, SmolFSConfigGenParams, SmolFSConfigGenerator, SmolFSEntry, SmolFSError,
+        SmolFSInput, SmolFSPayload, SmolFSVerificationCache, CHANGE_LOG_SIZE,
+    };
+
+    struct NoInterconnect;
+
+    #[async_trait]
+    impl ModuleInterconect for NoInterconnect {
+        async fn call(
+            &self,
+            _module_id: ModuleInstanceId,
+            _path: String,
+            _data: serde_json::Value,
+        ) -> Result<serde_json::Value, ApiError> {
+            unimplemented!("SmolFS doesn't call other modules")
+        }
+    }
+
+    fn new_module() -> SmolFS {
+        let params = ConfigGenParams::new().attach(SmolFSConfigGenParams {
+            max_versions: 3,
+            tombstone_retention_epochs: DEFAULT_TOMBSTONE_RETENTION_EPOCHS,
+            pow_difficulty: None,
+            write_fee_per_kib: Amount::ZERO,
+            peer_api_urls: Default::default(),
+        });
+        let peer = PeerId::from(0);
+        let mut cfg: SmolFSConfig = SmolFSConfigGenerator
+            .trusted_dealer_gen(&[peer], &params)
+            .remove(&peer)
+            .unwrap()
+            .to_typed()
+            .unwrap();
+        cfg.local.blob_dir =
+            std::env::temp_dir().join(format!("smolfs-module-{}", rand::random::<u64>()));
+        let db = Database::new(
+            MemDatabase::new(),
+            ModuleDecoderRegistry::from_iter([(
+                LEGACY_HARDCODED_INSTANCE_ID_SMOLFS,
+                SmolFSDecoder.into(),
+            )]),
+        );
+        SmolFS::new(cfg, db).unwrap()
+    }
+
+    fn owner_key(seed: u8) -> XOnlyPublicKey {
+        KeyPair::from_seckey_slice(&Secp256k1::new(), &[seed; 32])
+            .unwrap()
+            .x_only_public_key()
+            .0
+    }
+
+    fn write(key: &str, owner: Option<XOnlyPublicKey>) -> SmolFSInput {
+        SmolFSInput(Box::new(SmolFSEntry {
+            pubkey: key.to_string(),
+            payload: SmolFSPayload::Inline(key.as_bytes().to_vec()),
+            timestamp: UNIX_EPOCH,
+            pow_nonce: 0,
+            owner,
+            prepaid: Amount::ZERO,
+            public: false,
+            expected_version: None,
+        }))
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn watch_wakes_once_the_epoch_is_committed() {
+        let smolfs = new_module();
+        let watch = smolfs.watch(WatchRequest {
+            target: WatchTarget::Entry("watched".to_string()),
+            since: 0,
+        });
+        let epoch = async {
+            // Lets the watch request go to sleep on the empty change log first
+            sleep(Duration::from_millis(100)).await;
+            let mut dbtx = smolfs.db.begin_transaction().await;
+            {
+                let mut module_dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+                smolfs.begin_consensus_epoch(&mut module_dbtx, vec![]).await;
+                for key in ["other", "watched"] {
+                    smolfs
+                        .apply_input(
+                            &NoInterconnect,
+                            &mut module_dbtx,
+                            &write(key, Some(owner_key(1))),
+                            &SmolFSVerificationCache,
+                        )
+                        .await
+                        .unwrap();
+                }
+                smolfs
+                    .end_consensus_epoch(&HashSet::new(), &mut module_dbtx)
+                    .await;
+            }
+            // The epoch is announced but not committed yet, the watch request has to
+            // wait for the commit to see it
+            sleep(Duration::from_millis(100)).await;
+            dbtx.commit_tx().await.expect("DB Error");
+        };
+
+        let (change, ()) = tokio::join!(watch, epoch);
+        assert_eq!(
+            change,
+            Some(ChangeEvent {
+                seq: 2,
+                key: "watched".to_string(),
+                version: 0,
+                hash: sha256::Hash::hash(b"watched"),
+            })
+        );
+        std::fs::remove_dir_all(&smolfs.cfg.local.blob_dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn watch_skips_changes_dropped_from_the_log() {
+        let smolfs = new_module();
+        let change = |seq| ChangeEvent {
+            seq,
+            key: "watched".to_string(),
+            version: seq,
+            hash: sha256::Hash::all_zeros(),
+        };
+        let latest = CHANGE_LOG_SIZE + 5;
+        let mut dbtx = smolfs.db.begin_transaction().await;
+        {
+            let mut module_dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+            module_dbtx
+                .insert_entry(&ChangeSeqKey, &latest)
+                .await
+                .unwrap();
+            // A change older than the log keeps must not be returned even if it is
+            // still stored
+            for seq in [3, 6] {
+                module_dbtx
+                    .insert_entry(&ChangeKey(seq), &change(seq))
+                    .await
+                    .unwrap();
+            }
+        }
+        dbtx.commit_tx().await.expect("DB Error");
+
+        let oldest = smolfs
+            .watch(WatchRequest {
+                target: WatchTarget::Entry("watched".to_string()),
+                since: 0,
+            })
+            .await;
+        assert_eq!(oldest, Some(change(6)));
+        std::fs::remove_dir_all(&smolfs.cfg.local.blob_dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn unowned_entries_are_read_only_until_claimed() {
+        let smolfs = new_module();
+        let legacy_owner = owner_key(2);
+        let mut dbtx = smolfs.db.begin_transaction().await;
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        // Left behind by the migration of legacy entries
+        for key in ["legacy".to_string(), legacy_owner.to_string()] {
+            let entry = *write(&key, None).0;
+            smolfs.commit_version(&mut dbtx, &entry).await;
+        }
+
+        assert!(matches!(
+            smolfs.check_input(&mut dbtx, &write("new", None)).await,
+            Err(SmolFSError::MissingOwner)
+        ));
+        assert!(smolfs
+            .check_input(&mut dbtx, &write("new", Some(owner_key(1))))
+            .await
+            .is_ok());
+        assert!(matches!(
+            smolfs
+                .check_input(&mut dbtx, &write("legacy", Some(owner_key(1))))
+                .await,
+            Err(SmolFSError::UnclaimedEntry(_))
+        ));
+        assert!(matches!(
+            smolfs
+                .check_input(
+                    &mut dbtx,
+                    &write(&legacy_owner.to_string(), Some(owner_key(1)))
+                )
+                .await,
+            Err(SmolFSError::UnclaimedEntry(_))
+        ));
+        assert!(smolfs
+            .check_input(
+                &mut dbtx,
+                &write(&legacy_owner.to_string(), Some(legacy_owner))
+            )
+            .await
+            .is_ok());
+        std::fs::remove_dir_all(&smolfs.cfg.local.blob_dir).unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn writes_expecting_another_version_are_rejected() {
+        let smolfs = new_module();
+        let owner = Some(owner_key(1));
+        let expecting = |version| {
+            let mut input = write("versioned", owner);
+            input.0.expected_version = version;
+            input
+        };
+        let mut dbtx = smolfs.db.begin_transaction().await;
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+
+        assert!(matches!(
+            smolfs.check_input(&mut dbtx, &expecting(Some(0))).await,
+            Err(SmolFSError::VersionMismatch { actual: None, .. })
+        ));
+        for _ in 0..2 {
+            smolfs.commit_version(&mut dbtx, &expecting(None).0).await;
+        }
+        assert!(smolfs
+            .check_input(&mut dbtx, &expecting(Some(1)))
+            .await
+            .is_ok());
+        assert!(matches!(
+            smolfs.check_input(&mut dbtx, &expecting(Some(0))).await,
+            Err(SmolFSError::VersionMismatch {
+                expected: 0,
+                actual: Some(1),
+                ..
+            })
+        ));
+
+        // Deleted entries aren't at any version
+        smolfs
+            .delete_entry(&mut dbtx, "versioned".to_string())
+            .await;
+        assert!(matches!(
+            smolfs.check_input(&mut dbtx, &expecting(Some(1))).await,
+            Err(SmolFSError::VersionMismatch { actual: None, .. })
+        ));
+        std::fs::remove_dir_all(&smolfs.cfg.local.blob_dir).unwrap();
+    }
 }
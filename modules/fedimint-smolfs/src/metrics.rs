@@ -0,0 +1,133 @@
+//! Metrics of the module, registered with [`fedimint_metrics::REGISTRY`]
+//!
+//! Counters only describe what this guardian saw, e.g. a rejected write is
+//! counted by every guardian the client submitted it to.
+
+use fedimint_metrics::{
+    register_histogram_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, register_int_gauge_with_registry, Histogram, IntCounter,
+    IntCounterVec, IntGauge, REGISTRY,
+};
+use once_cell::sync::Lazy;
+
+/// Number of entries that currently have a payload
+pub static ENTRIES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "smolfs_entries",
+        "Number of entries that currently have a payload",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Bytes of all retained versions, counting identical payloads once
+pub static STORED_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "smolfs_stored_bytes",
+        "Bytes of all retained versions, counting identical payloads once",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Writes and deletes accepted by consensus
+pub static WRITES_ACCEPTED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        "smolfs_writes_accepted_total",
+        "Writes and deletes accepted by consensus",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Writes accepted per consensus epoch
+pub static WRITES_PER_EPOCH: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram_with_registry!(
+        "smolfs_writes_per_epoch",
+        "Writes and deletes accepted per consensus epoch",
+        vec![0.0, 1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Writes and uploads rejected by this guardian, labeled with the stage they were
+/// rejected at and the reason (see [`crate::SmolFSError::reason`])
+///
+/// A write is rejected at most once per stage: uploads when they are started
+/// (`upload`), writes when they are submitted to this guardian (`submit`) and
+/// writes that passed submission somewhere but failed when consensus applied
+/// them (`apply`), e.g. because an earlier write of the same epoch changed the
+/// entry.
+pub static WRITES_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "smolfs_writes_rejected_total",
+        "Writes and uploads rejected by this guardian",
+        &["stage", "reason"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Reads answered through the API, labeled with the endpoint
+pub static API_READS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "smolfs_api_reads_total",
+        "Reads answered through the API",
+        &["endpoint"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Payload bytes served through the API, labeled with the endpoint
+pub static API_READ_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "smolfs_api_read_bytes_total",
+        "Payload bytes served through the API",
+        &["endpoint"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Retrievability challenges this guardian couldn't answer, labeled with the reason
+pub static CHALLENGES_FAILED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        "smolfs_challenges_failed_total",
+        "Retrievability challenges this guardian couldn't answer",
+        &["reason"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Blobs still missing or corrupt after the last repair run
+pub static BLOBS_DAMAGED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        "smolfs_blobs_damaged",
+        "Blobs still missing or corrupt after the last repair run",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Blobs repaired from peers
+pub static BLOBS_REPAIRED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        "smolfs_blobs_repaired_total",
+        "Blobs repaired from peers",
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Repair runs that finished
+pub static REPAIR_RUNS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        "smolfs_repair_runs_total",
+        "Repair runs that finished",
+        REGISTRY
+    )
+    .unwrap()
+});
@@ -0,0 +1,77 @@
+//! Migrations of the smolfs database, see [`fedimint_api::db::migration`]
+
+use std::time::SystemTime;
+
+use fedimint_api::db::migration::{DatabaseVersion, MigrationMap};
+use fedimint_api::db::DatabaseTransaction;
+use fedimint_api::Amount;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tracing::{info, warn};
+
+use crate::db::{EntryKey, EntryVersionPrefix, ExampleKey, ExampleKeyPrefix};
+use crate::{SmolFS, SmolFSEntry, SmolFSPayload};
+
+/// Version of the database layout this binary works with
+pub const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+
+/// All migrations of the smolfs database keyed by the version they start from
+pub fn migrations() -> MigrationMap<SmolFS> {
+    MigrationMap::from([(DatabaseVersion(0), migrate_to_v1 as _)])
+}
+
+/// Turns legacy entries that kept their payload in the database into regular
+/// entries with the payload in the blob store
+///
+/// The converted entries have no owner, aren't public and are timestamped with
+/// the unix epoch so every guardian ends up with the same state. Legacy entries
+/// whose key has been written since are dropped.
+fn migrate_to_v1<'r>(
+    dbtx: &'r mut DatabaseTransaction<'_>,
+    smolfs: &'r SmolFS,
+) -> BoxFuture<'r, anyhow::Result<()>> {
+    async move {
+        let legacy = dbtx
+            .find_by_prefix(&ExampleKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error"))
+            .collect::<Vec<_>>();
+
+        for (ExampleKey(key), payload) in legacy {
+            dbtx.remove_entry(&ExampleKey(key.clone())).await?;
+
+            let superseded = dbtx.get_value(&EntryKey(key.clone())).await?.is_some()
+                || dbtx
+                    .find_by_prefix(&EntryVersionPrefix(key.clone()))
+                    .await
+                    .next()
+                    .is_some();
+            if superseded {
+                warn!(%key, "Dropping legacy entry that was written again since");
+                continue;
+            }
+
+            let payload = payload.into_bytes();
+            smolfs.blobs.put(&payload)?;
+            info!(%key, "Migrating legacy entry");
+            smolfs
+                .commit_version(
+                    dbtx,
+                    &SmolFSEntry {
+                        pubkey: key,
+                        payload: SmolFSPayload::Inline(payload),
+                        timestamp: SystemTime::UNIX_EPOCH,
+                        pow_nonce: 0,
+                        owner: None,
+                        prepaid: Amount::ZERO,
+                        public: false,
+                        expected_version: None,
+                    },
+                )
+                .await;
+        }
+
+        Ok(())
+    }
+    .boxed()
+}
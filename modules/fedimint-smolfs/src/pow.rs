@@ -0,0 +1,105 @@
+//! Hashcash-style proof-of-work that federations can require for writes
+//!
+//! The work commits to everything identifying a write (key, payload hash and
+//! size, timestamp) so it can't be reused for another write. Its difficulty is
+//! the number of leading zero bits of `sha256(write || nonce)`, starting at the
+//! configured base difficulty and growing by one bit every time the payload
+//! size doubles beyond [`POW_SIZE_STEP`].
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_api::encoding::Encodable;
+
+use crate::SmolFSEntry;
+
+/// Payloads up to this size only need the base difficulty
+pub const POW_SIZE_STEP: u64 = 64 * 1024;
+
+/// Number of leading zero bits required for a payload of `size` bytes
+pub fn required_bits(base_difficulty: u8, size: u64) -> u32 {
+    let steps = size.saturating_sub(1) / POW_SIZE_STEP;
+    u32::from(base_difficulty) + (u64::BITS - steps.leading_zeros())
+}
+
+/// Number of leading zero bits of the entry's proof-of-work hash
+pub fn pow_bits(entry: &SmolFSEntry) -> u32 {
+    leading_zero_bits(&hash_with_nonce(pow_engine(entry), entry.pow_nonce))
+}
+
+/// Searches for a nonce giving the entry at least `bits` leading zero bits
+///
+/// This takes about `2^bits` hashes, callers should run it on a blocking
+/// thread.
+pub fn solve(mut entry: SmolFSEntry, bits: u32) -> SmolFSEntry {
+    let engine = pow_engine(&entry);
+    entry.pow_nonce = (0..)
+        .find(|nonce| leading_zero_bits(&hash_with_nonce(engine.clone(), *nonce)) >= bits)
+        .expect("Finding a nonce for any reasonable difficulty can't fail");
+    entry
+}
+
+fn pow_engine(entry: &SmolFSEntry) -> sha256::HashEngine {
+    let mut engine = sha256::Hash::engine();
+    entry
+        .pubkey
+        .consensus_encode(&mut engine)
+        .expect("Hashing can't fail");
+    engine.input(&entry.payload.hash()[..]);
+    engine.input(&entry.payload.size().to_le_bytes());
+    entry
+        .timestamp
+        .consensus_encode(&mut engine)
+        .expect("Hashing can't fail");
+    engine
+}
+
+fn hash_with_nonce(mut engine: sha256::HashEngine, nonce: u64) -> sha256::Hash {
+    engine.input(&nonce.to_le_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+fn leading_zero_bits(hash: &sha256::Hash) -> u32 {
+    let mut bits = 0;
+    for byte in &hash[..] {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use fedimint_api::Amount;
+
+    use super::{pow_bits, required_bits, solve, POW_SIZE_STEP};
+    use crate::{SmolFSEntry, SmolFSPayload};
+
+    #[test]
+    fn difficulty_grows_with_size() {
+        assert_eq!(required_bits(8, 0), 8);
+        assert_eq!(required_bits(8, POW_SIZE_STEP), 8);
+        assert_eq!(required_bits(8, POW_SIZE_STEP + 1), 9);
+        assert_eq!(required_bits(8, 2 * POW_SIZE_STEP + 1), 10);
+        assert_eq!(required_bits(8, 4 * POW_SIZE_STEP), 10);
+    }
+
+    #[test]
+    fn solved_entry_verifies() {
+        let entry = SmolFSEntry {
+            pubkey: "key".to_string(),
+            payload: SmolFSPayload::Inline(b"payload".to_vec()),
+            timestamp: SystemTime::UNIX_EPOCH,
+            pow_nonce: 0,
+            owner: None,
+            prepaid: Amount::ZERO,
+            public: false,
+            expected_version: None,
+        };
+
+        let solved = solve(entry, 12);
+        assert!(pow_bits(&solved) >= 12);
+    }
+}
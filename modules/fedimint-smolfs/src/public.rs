@@ -0,0 +1,503 @@
+//! Public entries that anyone can read and verify without trusting the guardian
+//! serving them
+//!
+//! Guardians commit to all public entries with a Merkle tree over their current
+//! versions, sorted by key. Every guardian signs the root of that tree once it
+//! changed and shares the signature as a consensus item, so every guardian ends
+//! up with the same [`SignedRoot`]. Readers check the signatures against the
+//! guardian keys from the client config and the entry against the root using its
+//! [`MerkleProof`].
+//!
+//! ```text
+//! leaf = sha256(0x00 || key_len (u64 LE) || key || version (u64 LE) || payload_hash)
+//! node = sha256(0x01 || left || right)
+//! ```
+//!
+//! A node without a sibling is carried up to the next level unchanged rather than
+//! paired with itself, so no two sets of entries share a root. The root of no
+//! entries is all zeros.
+//!
+//! Guardians keep the tree in memory as a [`PublicTree`] and only rehash what
+//! changed since it was last used.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+use fedimint_api::db::{Database, DatabaseTransaction};
+use fedimint_api::encoding::{Decodable, Encodable};
+use fedimint_api::PeerId;
+use secp256k1::{schnorr, Message, Secp256k1, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::blob::BlobStore;
+use crate::common::EntryMeta;
+use crate::db::{
+    CatchUpStatusKey, ChangeKey, ChangeSeqKey, EntryKey, PublicEntryKey, PublicEntryKeyPrefix,
+    SignedRootKey,
+};
+use crate::CHANGE_LOG_SIZE;
+
+/// Prefixed to the root before signing it, so the signatures can't be mistaken
+/// for anything else the guardian keys might sign
+const ROOT_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-public-root";
+
+/// Root of the public entries signed by the guardians
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct SignedRoot {
+    pub root: sha256::Hash,
+    pub signatures: BTreeMap<PeerId, schnorr::Signature>,
+}
+
+impl SignedRoot {
+    /// Checks that at least `threshold` of the guardians in `keys` signed the root
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+        threshold: usize,
+    ) -> bool {
+        let message = root_message(&self.root);
+        let valid = self
+            .signatures
+            .iter()
+            .filter(|(peer, signature)| {
+                keys.get(peer).map_or(false, |key| {
+                    secp.verify_schnorr(signature, &message, key).is_ok()
+                })
+            })
+            .count();
+        valid >= threshold
+    }
+}
+
+/// Message guardians sign to attest to a root
+pub fn root_message(root: &sha256::Hash) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.input(ROOT_SIGNATURE_TAG);
+    engine.input(&root[..]);
+    Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("hash has right length")
+}
+
+/// Leaf committing to the current version of the public entry `key`
+pub fn leaf_hash(key: &str, meta: &EntryMeta) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[0x00]);
+    engine.input(&(key.len() as u64).to_le_bytes());
+    engine.input(key.as_bytes());
+    engine.input(&meta.version.to_le_bytes());
+    engine.input(&meta.hash[..]);
+    sha256::Hash::from_engine(engine)
+}
+
+fn node_hash(left: &sha256::Hash, right: &sha256::Hash) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    engine.input(&[0x01]);
+    engine.input(&left[..]);
+    engine.input(&right[..]);
+    sha256::Hash::from_engine(engine)
+}
+
+/// All levels of the tree over `leaves`, starting with the leaves and ending with
+/// the root
+fn merkle_levels(leaves: Vec<sha256::Hash>) -> Vec<Vec<sha256::Hash>> {
+    let mut levels = vec![leaves];
+    loop {
+        let level = levels.last().expect("starts with the leaves");
+        if level.len() <= 1 {
+            return levels;
+        }
+        let next = level
+            .chunks(2)
+            .map(|pair| {
+                pair.get(1)
+                    .map_or(pair[0], |right| node_hash(&pair[0], right))
+            })
+            .collect();
+        levels.push(next);
+    }
+}
+
+fn levels_root(levels: &[Vec<sha256::Hash>]) -> sha256::Hash {
+    levels
+        .last()
+        .and_then(|level| level.first())
+        .copied()
+        .unwrap_or_else(sha256::Hash::all_zeros)
+}
+
+/// Merkle root over `leaves`
+pub fn merkle_root(leaves: &[sha256::Hash]) -> sha256::Hash {
+    levels_root(&merkle_levels(leaves.to_vec()))
+}
+
+/// Path from a leaf to the root
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub sibling: sha256::Hash,
+    /// Whether the sibling is the left node of the pair
+    pub left: bool,
+}
+
+impl MerkleProof {
+    /// Proof for the leaf at `index`, which has to be in range
+    pub fn new(leaves: &[sha256::Hash], index: usize) -> MerkleProof {
+        Self::from_levels(&merkle_levels(leaves.to_vec()), index)
+    }
+
+    fn from_levels(levels: &[Vec<sha256::Hash>], mut index: usize) -> MerkleProof {
+        let mut proof = MerkleProof::default();
+        for level in &levels[..levels.len() - 1] {
+            // Nodes without a sibling are carried up without a step
+            let sibling = index ^ 1;
+            if let Some(node) = level.get(sibling) {
+                proof.steps.push(ProofStep {
+                    sibling: *node,
+                    left: sibling < index,
+                });
+            }
+            index /= 2;
+        }
+        proof
+    }
+
+    /// Root the proof leads to starting from `leaf`
+    pub fn root(&self, leaf: sha256::Hash) -> sha256::Hash {
+        self.steps.iter().fold(leaf, |node, step| {
+            if step.left {
+                node_hash(&step.sibling, &node)
+            } else {
+                node_hash(&node, &step.sibling)
+            }
+        })
+    }
+}
+
+/// Current versions of all public entries, sorted by key
+pub async fn public_entries(dbtx: &mut DatabaseTransaction<'_>) -> Vec<(String, EntryMeta)> {
+    let keys = dbtx
+        .find_by_prefix(&PublicEntryKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error").0 .0)
+        .collect::<Vec<_>>();
+
+    let mut entries = vec![];
+    for key in keys {
+        if let Some(meta) = dbtx
+            .get_value(&EntryKey(key.clone()))
+            .await
+            .expect("DB Error")
+        {
+            entries.push((key, meta));
+        }
+    }
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    entries
+}
+
+/// Root over the current versions of all public entries
+///
+/// Recomputes the whole tree, guardians use their [`PublicTree`] instead.
+pub async fn public_root(dbtx: &mut DatabaseTransaction<'_>) -> sha256::Hash {
+    let leaves = public_entries(dbtx)
+        .await
+        .iter()
+        .map(|(key, meta)| leaf_hash(key, meta))
+        .collect::<Vec<_>>();
+    merkle_root(&leaves)
+}
+
+/// Merkle tree over the public entries kept in memory between requests
+///
+/// Every use brings the tree up to the state of the given transaction by looking
+/// at the writes recorded in the change log since the last use. Only the paths of
+/// changed leaves are rehashed, unless entries became public or stopped being
+/// public, which shifts the leaves after them. Catching up replaces all entries
+/// without touching the change log, so the tree is rebuilt after that.
+#[derive(Debug, Clone, Default)]
+pub struct PublicTree(Arc<Mutex<Option<CachedTree>>>);
+
+#[derive(Debug)]
+struct CachedTree {
+    /// Last change log sequence number included in the tree
+    seq: u64,
+    /// When catching up last completed when the tree was built
+    caught_up: Option<SystemTime>,
+    /// Keys of the leaves, sorted
+    keys: Vec<String>,
+    levels: Vec<Vec<sha256::Hash>>,
+}
+
+impl PublicTree {
+    /// Root over the current versions of all public entries
+    pub async fn root(&self, dbtx: &mut DatabaseTransaction<'_>) -> sha256::Hash {
+        let mut cached = self.0.lock().await;
+        levels_root(&Self::update(&mut cached, dbtx).await.levels)
+    }
+
+    /// Root and proof of the public entry `key`, `None` if it isn't public
+    pub async fn proof(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        key: &str,
+    ) -> Option<(sha256::Hash, MerkleProof)> {
+        let mut cached = self.0.lock().await;
+        let tree = Self::update(&mut cached, dbtx).await;
+        let index = tree.keys.binary_search_by(|k| k.as_str().cmp(key)).ok()?;
+        Some((
+            levels_root(&tree.levels),
+            MerkleProof::from_levels(&tree.levels, index),
+        ))
+    }
+
+    async fn update<'c>(
+        cached: &'c mut Option<CachedTree>,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> &'c CachedTree {
+        let seq = dbtx
+            .get_value(&ChangeSeqKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or(0);
+        let caught_up = dbtx
+            .get_value(&CatchUpStatusKey)
+            .await
+            .expect("DB Error")
+            .and_then(|status| status.completed);
+
+        // A transaction that started before the tree was last updated sees an
+        // older state, the tree is rebuilt for it
+        let usable = cached.as_ref().map_or(false, |tree| {
+            tree.caught_up == caught_up && tree.seq <= seq && seq - tree.seq <= CHANGE_LOG_SIZE
+        });
+        if !usable || !Self::apply_changes(cached.as_mut().expect("usable"), dbtx, seq).await {
+            let entries = public_entries(dbtx).await;
+            let leaves = entries
+                .iter()
+                .map(|(key, meta)| leaf_hash(key, meta))
+                .collect();
+            *cached = Some(CachedTree {
+                seq,
+                caught_up,
+                keys: entries.into_iter().map(|(key, _)| key).collect(),
+                levels: merkle_levels(leaves),
+            });
+        }
+        cached.as_ref().expect("just updated")
+    }
+
+    /// Applies the writes after `tree.seq` up to `seq`, returns false if some of
+    /// them already dropped out of the change log
+    async fn apply_changes(
+        tree: &mut CachedTree,
+        dbtx: &mut DatabaseTransaction<'_>,
+        seq: u64,
+    ) -> bool {
+        let mut changed = BTreeSet::new();
+        for change_seq in tree.seq + 1..=seq {
+            match dbtx
+                .get_value(&ChangeKey(change_seq))
+                .await
+                .expect("DB Error")
+            {
+                Some(change) => changed.insert(change.key),
+                None => return false,
+            };
+        }
+
+        let mut shifted = false;
+        let mut updated = vec![];
+        for key in changed {
+            let public = dbtx
+                .get_value(&PublicEntryKey(key.clone()))
+                .await
+                .expect("DB Error")
+                .is_some();
+            let meta = if public {
+                dbtx.get_value(&EntryKey(key.clone()))
+                    .await
+                    .expect("DB Error")
+            } else {
+                None
+            };
+            let leaf = meta.map(|meta| leaf_hash(&key, &meta));
+
+            match (tree.keys.binary_search(&key), leaf) {
+                (Ok(index), Some(leaf)) => {
+                    tree.levels[0][index] = leaf;
+                    updated.push(index);
+                }
+                (Ok(index), None) => {
+                    tree.keys.remove(index);
+                    tree.levels[0].remove(index);
+                    shifted = true;
+                }
+                (Err(index), Some(leaf)) => {
+                    tree.keys.insert(index, key);
+                    tree.levels[0].insert(index, leaf);
+                    shifted = true;
+                }
+                (Err(_), None) => {}
+            }
+        }
+
+        if shifted {
+            let leaves = std::mem::take(&mut tree.levels[0]);
+            tree.levels = merkle_levels(leaves);
+        } else {
+            for index in updated {
+                tree.rehash_path(index);
+            }
+        }
+        tree.seq = seq;
+        true
+    }
+}
+
+impl CachedTree {
+    fn rehash_path(&mut self, mut index: usize) {
+        for level in 0..self.levels.len() - 1 {
+            let left = index & !1;
+            let nodes = &self.levels[level];
+            let parent = nodes
+                .get(left + 1)
+                .map_or(nodes[left], |right| node_hash(&nodes[left], right));
+            index /= 2;
+            self.levels[level + 1][index] = parent;
+        }
+    }
+}
+
+/// A public entry together with everything needed to verify it
+#[derive(Debug, Clone)]
+pub struct PublicEntry {
+    pub bytes: Vec<u8>,
+    pub meta: EntryMeta,
+    pub proof: MerkleProof,
+    /// Signed root the proof leads to, `None` if the guardians haven't signed the
+    /// current root yet
+    pub signed_root: Option<SignedRoot>,
+}
+
+impl PublicEntry {
+    /// Checks the payload against the entry's hash and the entry against the
+    /// signed root
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        key: &str,
+        keys: &BTreeMap<PeerId, XOnlyPublicKey>,
+        threshold: usize,
+    ) -> bool {
+        let Some(signed_root) = &self.signed_root else {
+            return false;
+        };
+        sha256::Hash::hash(&self.bytes) == self.meta.hash
+            && self.proof.root(leaf_hash(key, &self.meta)) == signed_root.root
+            && signed_root.verify(secp, keys, threshold)
+    }
+}
+
+/// Reads the public entry `key` outside of the module, e.g. to serve it over
+/// plain HTTP
+///
+/// Returns `None` if there is no such entry or it isn't public.
+pub async fn read_public_entry(
+    db: &Database,
+    blobs: &BlobStore,
+    tree: &PublicTree,
+    key: &str,
+) -> io::Result<Option<PublicEntry>> {
+    let mut dbtx = db.begin_transaction().await;
+    let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+
+    let Some((root, proof)) = tree.proof(&mut dbtx, key).await else {
+        return Ok(None);
+    };
+    let Some(meta) = dbtx
+        .get_value(&EntryKey(key.to_string()))
+        .await
+        .expect("DB Error")
+    else {
+        return Ok(None);
+    };
+    let signed_root = dbtx
+        .get_value(&SignedRootKey)
+        .await
+        .expect("DB Error")
+        .filter(|signed| signed.root == root);
+
+    let Some(bytes) = blobs.get(&meta.hash)? else {
+        return Ok(None);
+    };
+    Ok(Some(PublicEntry {
+        bytes,
+        meta,
+        proof,
+        signed_root,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::{sha256, Hash};
+
+    use super::{merkle_levels, merkle_root, CachedTree, MerkleProof};
+
+    #[test]
+    fn proofs_lead_to_root() {
+        for count in 1..10u8 {
+            let leaves = (0..count)
+                .map(|i| sha256::Hash::hash(&[i]))
+                .collect::<Vec<_>>();
+            let root = merkle_root(&leaves);
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = MerkleProof::new(&leaves, index);
+                assert_eq!(proof.root(*leaf), root);
+                assert_ne!(proof.root(sha256::Hash::hash(b"other")), root);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_node_is_not_duplicated() {
+        let leaves = (0..3u8)
+            .map(|i| sha256::Hash::hash(&[i]))
+            .collect::<Vec<_>>();
+        let mut duplicated = leaves.clone();
+        duplicated.push(leaves[2]);
+        assert_ne!(merkle_root(&leaves), merkle_root(&duplicated));
+    }
+
+    #[test]
+    fn rehashed_path_matches_rebuilt_tree() {
+        for count in 1..10u8 {
+            let mut leaves = (0..count)
+                .map(|i| sha256::Hash::hash(&[i]))
+                .collect::<Vec<_>>();
+            let mut tree = CachedTree {
+                seq: 0,
+                caught_up: None,
+                keys: vec![],
+                levels: merkle_levels(leaves.clone()),
+            };
+            for index in 0..leaves.len() {
+                leaves[index] = sha256::Hash::hash(&[index as u8, 0xff]);
+                tree.levels[0][index] = leaves[index];
+                tree.rehash_path(index);
+                assert_eq!(tree.levels, merkle_levels(leaves.clone()));
+            }
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Repairs blobs this guardian lost or that got corrupted by fetching them from
+//! other guardians.
+//!
+//! Blobs are requested through the public API of our peers (see the
+//! `/fetch_blob` endpoint) since the peer-to-peer connections are only
+//! available to modules during distributed key generation. Requests are signed
+//! with our root key, peers only serve blobs to other guardians. Blobs are
+//! fetched in chunks of at most [`MAX_CHUNK_SIZE`] bytes into a staging file and
+//! verified against their content hash before they are stored, so a malicious
+//! peer can at worst fail to help us.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{bail, ensure};
+use bitcoin::hashes::{sha256, HashEngine};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+use fedimint_api::db::Database;
+use fedimint_api::module::__reexports::serde_json;
+use fedimint_api::task::{sleep, TaskHandle};
+use fedimint_api::{BitcoinHash, PeerId};
+use futures::channel::oneshot;
+use futures::future::{select, BoxFuture, Either};
+use jsonrpsee_core::client::ClientT;
+#[cfg(target_family = "wasm")]
+use jsonrpsee_wasm_client::WasmClientBuilder as WsClientBuilder;
+#[cfg(not(target_family = "wasm"))]
+use jsonrpsee_ws_client::WsClientBuilder;
+use secp256k1::{Secp256k1, SecretKey};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::blob::BlobStore;
+use crate::common::{BlobResponse, FetchBlobRequest};
+use crate::db::{DamagedBlobKey, DamagedBlobKeyPrefix, RepairStatus, RepairStatusKey};
+use crate::metrics;
+use crate::upload::MAX_CHUNK_SIZE;
+
+/// How long to wait between two repair runs
+const REPAIR_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically tries to repair all blobs recorded as damaged until the task
+/// group shuts down
+pub async fn run_repair(
+    db: Database,
+    blobs: BlobStore,
+    peers: BTreeMap<PeerId, Url>,
+    root_key: SecretKey,
+    handle: &TaskHandle,
+) {
+    let mut shutdown = shutdown_signal(handle).await;
+    while !handle.is_shutting_down() {
+        repair_damaged_blobs(&db, &blobs, &peers, &root_key).await;
+        if !sleep_until_shutdown(&mut shutdown, REPAIR_INTERVAL).await {
+            break;
+        }
+    }
+}
+
+/// Resolves once the task group shuts down, see [`sleep_until_shutdown`]
+pub(crate) async fn shutdown_signal(handle: &TaskHandle) -> BoxFuture<'static, ()> {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    handle
+        .on_shutdown(Box::new(|| {
+            Box::pin(async {
+                let _ = shutdown_tx.send(());
+            })
+        }))
+        .await;
+    Box::pin(async move {
+        let _ = shutdown_rx.await;
+    })
+}
+
+/// Sleeps for `duration`, returns `false` as soon as the task group shuts down instead
+pub(crate) async fn sleep_until_shutdown(
+    shutdown: &mut BoxFuture<'static, ()>,
+    duration: Duration,
+) -> bool {
+    matches!(
+        select(Box::pin(sleep(duration)), shutdown).await,
+        Either::Left(_)
+    )
+}
+
+/// Runs a single repair pass over all blobs recorded as damaged
+pub async fn repair_damaged_blobs(
+    db: &Database,
+    blobs: &BlobStore,
+    peers: &BTreeMap<PeerId, Url>,
+    root_key: &SecretKey,
+) {
+    let mut dbtx = db.begin_transaction().await;
+    let damaged = dbtx
+        .with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS)
+        .find_by_prefix(&DamagedBlobKeyPrefix)
+        .await
+        .map(|res| res.expect("DB Error").0 .0)
+        .collect::<Vec<_>>();
+    drop(dbtx);
+
+    let mut repaired = vec![];
+    for hash in &damaged {
+        if let Some(peer) = fetch_from_peers(blobs, peers, root_key, hash).await {
+            info!(%hash, %peer, "Repaired blob");
+            repaired.push(*hash);
+        }
+    }
+
+    let mut dbtx = db.begin_transaction().await;
+    {
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        for hash in &repaired {
+            dbtx.remove_entry(&DamagedBlobKey(*hash))
+                .await
+                .expect("DB Error");
+        }
+
+        let previous = dbtx
+            .get_value(&RepairStatusKey)
+            .await
+            .expect("DB Error")
+            .unwrap_or_default();
+        let status = RepairStatus {
+            last_run: Some(SystemTime::now()),
+            damaged: (damaged.len() - repaired.len()) as u64,
+            repaired_last_run: repaired.len() as u64,
+            repaired_total: previous.repaired_total + repaired.len() as u64,
+        };
+        dbtx.insert_entry(&RepairStatusKey, &status)
+            .await
+            .expect("DB Error");
+    }
+    metrics::REPAIR_RUNS.inc();
+    metrics::BLOBS_REPAIRED.inc_by(repaired.len() as u64);
+    metrics::BLOBS_DAMAGED.set((damaged.len() - repaired.len()) as i64);
+    if let Err(e) = dbtx.commit_tx().await {
+        // Will be picked up again on the next run, the repaired blobs are on disk already
+        warn!("Failed to record repair progress: {}", e);
+    }
+}
+
+/// Tries to fetch the blob from one peer after the other, returning the peer
+/// that delivered a valid copy
+pub(crate) async fn fetch_from_peers(
+    blobs: &BlobStore,
+    peers: &BTreeMap<PeerId, Url>,
+    root_key: &SecretKey,
+    hash: &sha256::Hash,
+) -> Option<PeerId> {
+    let secp = Secp256k1::signing_only();
+    for (peer, url) in peers {
+        let fetch_chunk = |offset, len| {
+            let request =
+                FetchBlobRequest::new(&secp, root_key, *hash, offset, len, SystemTime::now());
+            async move {
+                let response: Option<BlobResponse> =
+                    request_peer(url, "fetch_blob", &request).await?;
+                Ok(response.map(|blob| blob.bytes))
+            }
+        };
+        match fetch_chunked(blobs, hash, fetch_chunk).await {
+            Ok(true) => return Some(*peer),
+            Ok(false) => debug!(%hash, %peer, "Peer doesn't have blob"),
+            Err(e) => warn!(%hash, %peer, "Failed to fetch blob from peer: {}", e),
+        }
+    }
+
+    None
+}
+
+/// Fetches the blob chunk by chunk into a staging file and stores it once its hash checks
+/// out, returns `false` if the peer doesn't have the blob
+///
+/// `fetch_chunk` is called with the offset and length of every chunk, a chunk shorter than
+/// requested ends the blob.
+async fn fetch_chunked<F, Fut>(
+    blobs: &BlobStore,
+    hash: &sha256::Hash,
+    mut fetch_chunk: F,
+) -> anyhow::Result<bool>
+where
+    F: FnMut(u64, u64) -> Fut,
+    Fut: Future<Output = anyhow::Result<Option<Vec<u8>>>>,
+{
+    let staging_path = blobs.staging_path(&format!("repair-{hash}-{}", rand::random::<u64>()));
+    let result: anyhow::Result<bool> = async {
+        let mut file = File::create(&staging_path)?;
+        let mut engine = sha256::Hash::engine();
+        let mut offset = 0;
+        loop {
+            let Some(chunk) = fetch_chunk(offset, MAX_CHUNK_SIZE).await? else {
+                if offset == 0 {
+                    return Ok(false);
+                }
+                bail!("Blob disappeared after {offset} bytes");
+            };
+            ensure!(
+                chunk.len() as u64 <= MAX_CHUNK_SIZE,
+                "Peer returned a chunk of {} bytes",
+                chunk.len()
+            );
+            file.write_all(&chunk)?;
+            engine.input(&chunk);
+            offset += chunk.len() as u64;
+            if (chunk.len() as u64) < MAX_CHUNK_SIZE {
+                break;
+            }
+        }
+        drop(file);
+
+        ensure!(
+            sha256::Hash::from_engine(engine) == *hash,
+            "Peer returned blob with wrong hash"
+        );
+        blobs.insert_staged(&staging_path, hash)?;
+        Ok(true)
+    }
+    .await;
+
+    if !matches!(result, Ok(true)) {
+        // Nothing to clean up if the staged blob was moved into place
+        if let Err(e) = fs::remove_file(&staging_path) {
+            debug!(path = ?staging_path, "Failed to remove staged blob: {}", e);
+        }
+    }
+    result
+}
+
+/// Calls an endpoint of the smolfs module through a peer's public API
+pub(crate) async fn request_peer<P, R>(url: &Url, endpoint: &str, param: &P) -> anyhow::Result<R>
+where
+    P: Serialize,
+    R: DeserializeOwned,
+{
+    let client = WsClientBuilder::default()
+        .build(url_to_string_with_default_port(url))
+        .await?;
+    let method = format!("/module/{LEGACY_HARDCODED_INSTANCE_ID_SMOLFS}/{endpoint}");
+    let params = [serde_json::to_value(param)?];
+    Ok(client.request(&method, &params[..]).await?)
+}
+
+/// jsonrpsee requires an explicit port, see the function of the same name in
+/// the client library
+fn url_to_string_with_default_port(url: &Url) -> String {
+    format!(
+        "{}://{}:{}{}",
+        url.scheme(),
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default(),
+        url.path()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bitcoin::hashes::{sha256, Hash};
+    use futures::executor::block_on;
+
+    use super::fetch_chunked;
+    use crate::blob::{BlobStatus, BlobStore};
+    use crate::upload::MAX_CHUNK_SIZE;
+
+    fn temp_store() -> BlobStore {
+        let dir = std::env::temp_dir().join(format!("smolfs-repair-{}", rand::random::<u64>()));
+        BlobStore::open(dir).unwrap()
+    }
+
+    /// Serves ranges of `bytes` like the `/fetch_blob` endpoint of a peer
+    fn serve(bytes: &[u8], offset: u64, len: u64) -> anyhow::Result<Option<Vec<u8>>> {
+        assert!(len <= MAX_CHUNK_SIZE);
+        let start = std::cmp::min(offset as usize, bytes.len());
+        let end = std::cmp::min(start + len as usize, bytes.len());
+        Ok(Some(bytes[start..end].to_vec()))
+    }
+
+    #[test]
+    fn fetches_blobs_larger_than_a_chunk() {
+        let store = temp_store();
+        let chunk = MAX_CHUNK_SIZE as usize;
+        for size in [0, 10, chunk, 2 * chunk, 2 * chunk + 123] {
+            let bytes = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+            let hash = sha256::Hash::hash(&bytes);
+
+            let mut requests = 0;
+            let fetched = block_on(fetch_chunked(&store, &hash, |offset, len| {
+                requests += 1;
+                futures::future::ready(serve(&bytes, offset, len))
+            }))
+            .unwrap();
+            assert!(fetched);
+            assert_eq!(requests, size / chunk + 1);
+            assert_eq!(store.check(&hash).unwrap(), BlobStatus::Ok);
+        }
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn rejects_missing_and_wrong_blobs() {
+        let store = temp_store();
+        let bytes = vec![7; MAX_CHUNK_SIZE as usize + 1];
+        let hash = sha256::Hash::hash(b"something else");
+
+        let missing = block_on(fetch_chunked(&store, &hash, |_, _| {
+            futures::future::ready(Ok(None))
+        }));
+        assert!(!missing.unwrap());
+
+        let wrong = block_on(fetch_chunked(&store, &hash, |offset, len| {
+            futures::future::ready(serve(&bytes, offset, len))
+        }));
+        assert!(wrong.is_err());
+        assert_eq!(store.check(&hash).unwrap(), BlobStatus::Missing);
+
+        // Nothing is left behind in the staging directory
+        assert_eq!(fs::read_dir(store.staging_path("")).unwrap().count(), 0);
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+}
@@ -0,0 +1,457 @@
+//! Chunked uploads of payloads too large to fit into a single API request or
+//! transaction.
+//!
+//! A client starts a session announcing the total size, pushes consecutive
+//! ranges of bytes and finally commits the upload by naming the hash it
+//! expects. Bytes are staged in the blob store's temporary directory and only
+//! moved into place once the hash checks out. Sessions that see no activity
+//! for [`UPLOAD_SESSION_TIMEOUT`] are dropped together with their staged data.
+//!
+//! Sessions are started by an owner, the space reserved by all sessions of an
+//! owner has to be covered by its prepaid balance. Every guardian that stored
+//! the payload hands out a receipt, writes referencing an uploaded payload need
+//! receipts of a threshold of guardians so the claimed size can be trusted.
+//! Uploads that aren't written to an entry within [`UPLOAD_RETENTION`] are
+//! removed again.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use fedimint_api::core::LEGACY_HARDCODED_INSTANCE_ID_SMOLFS;
+use fedimint_api::db::Database;
+use fedimint_api::module::ApiError;
+use fedimint_api::task::TaskHandle;
+use secp256k1::{schnorr, KeyPair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::blob::BlobStore;
+use crate::common::{unix_secs, SignedReadCapability};
+use crate::db::{BlobRefKey, UploadedBlobKey, UploadedBlobKeyPrefix};
+use crate::metrics;
+use crate::repair::{shutdown_signal, sleep_until_shutdown};
+
+/// Sessions without any request for this long are dropped
+pub const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Finished uploads that no entry references after this long are removed
+pub const UPLOAD_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+/// Start requests signed longer ago than this are rejected
+pub const UPLOAD_START_REQUEST_VALIDITY: Duration = Duration::from_secs(5 * 60);
+/// Largest range accepted in a single push or returned by a single read
+pub const MAX_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// Prefixed to upload start requests before the owner signs them
+const UPLOAD_START_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-upload-start";
+/// Prefixed to upload receipts before a guardian signs them
+const UPLOAD_RECEIPT_SIGNATURE_TAG: &[u8] = b"fedimint-smolfs-upload-receipt";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadStartRequest {
+    /// Total size of the payload that is going to be uploaded
+    pub size: u64,
+    /// Owner whose prepaid balance has to cover the reserved space
+    pub owner: XOnlyPublicKey,
+    /// Unix timestamp in seconds the request was signed at
+    pub timestamp: u64,
+    /// Signature over the size and timestamp with the owner's key
+    pub signature: schnorr::Signature,
+}
+
+impl UploadStartRequest {
+    pub fn new<C: Signing>(
+        secp: &Secp256k1<C>,
+        owner_key: &KeyPair,
+        size: u64,
+        now: SystemTime,
+    ) -> UploadStartRequest {
+        let timestamp = unix_secs(now);
+        UploadStartRequest {
+            size,
+            owner: owner_key.x_only_public_key().0,
+            timestamp,
+            signature: secp.sign_schnorr(&Self::message(size, timestamp), owner_key),
+        }
+    }
+
+    fn message(size: u64, timestamp: u64) -> Message {
+        let mut engine = sha256::Hash::engine();
+        engine.input(UPLOAD_START_SIGNATURE_TAG);
+        engine.input(&size.to_le_bytes());
+        engine.input(&timestamp.to_le_bytes());
+        Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("hash has right length")
+    }
+
+    /// Whether the request is recent enough and signed by its owner
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, now: SystemTime) -> bool {
+        unix_secs(now).abs_diff(self.timestamp) <= UPLOAD_START_REQUEST_VALIDITY.as_secs()
+            && secp
+                .verify_schnorr(
+                    &self.signature,
+                    &Self::message(self.size, self.timestamp),
+                    &self.owner,
+                )
+                .is_ok()
+    }
+}
+
+/// Message a guardian signs with its root key once it stored an uploaded payload,
+/// see [`crate::SmolFSPayload::Uploaded`]
+pub fn upload_receipt_message(hash: &sha256::Hash, size: u64) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.input(UPLOAD_RECEIPT_SIGNATURE_TAG);
+    engine.input(&hash[..]);
+    engine.input(&size.to_le_bytes());
+    Message::from_slice(&sha256::Hash::from_engine(engine)[..]).expect("hash has right length")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadPushRequest {
+    pub session: u64,
+    /// Offset of `bytes` within the payload, has to continue where the last push ended
+    pub offset: u64,
+    #[serde(with = "fedimint_api::hex::serde")]
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadFinalizeRequest {
+    pub session: u64,
+    /// Hash the client expects the uploaded payload to have
+    pub hash: sha256::Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadRequest {
+    pub key: String,
+    pub offset: u64,
+    /// Number of bytes to read, at most [`MAX_CHUNK_SIZE`]
+    pub len: u64,
+    /// Unlimited capability for the entry, needed if it is private and has an owner
+    #[serde(default)]
+    pub capability: Option<SignedReadCapability>,
+}
+
+#[derive(Debug)]
+struct UploadSession {
+    received: u64,
+    engine: sha256::HashEngine,
+    staging_path: PathBuf,
+    last_activity: Instant,
+}
+
+/// Reservation of a session, the session itself has its own lock so the file
+/// I/O of one session doesn't block the others
+#[derive(Debug)]
+struct SessionEntry {
+    size: u64,
+    owner: XOnlyPublicKey,
+    session: Arc<Mutex<UploadSession>>,
+}
+
+/// Upload sessions currently in progress, shared with the cleanup task
+#[derive(Debug, Clone, Default)]
+pub struct UploadSessions(Arc<Mutex<HashMap<u64, SessionEntry>>>);
+
+impl UploadSessions {
+    /// Starts a new session for a payload of `size` bytes, `max_size` is the
+    /// space the guardian is still willing to hand out and `max_owner_size` the
+    /// space the balance of `owner` pays for
+    pub fn start(
+        &self,
+        blobs: &BlobStore,
+        owner: XOnlyPublicKey,
+        size: u64,
+        max_size: u64,
+        max_owner_size: u64,
+    ) -> Result<u64, ApiError> {
+        let session = rand::random::<u64>();
+        let staging_path = blobs.staging_path(&format!("upload-{session}"));
+        {
+            let mut sessions = self.0.lock().expect("lock poisoned");
+            let reserved: u64 = sessions.values().map(|entry| entry.size).sum();
+            if size.saturating_add(reserved) > max_size {
+                metrics::WRITES_REJECTED
+                    .with_label_values(&["upload", "disk_full"])
+                    .inc();
+                return Err(ApiError::bad_request(format!(
+                    "Upload of {size} bytes exceeds the available space"
+                )));
+            }
+            let reserved_by_owner: u64 = sessions
+                .values()
+                .filter(|entry| entry.owner == owner)
+                .map(|entry| entry.size)
+                .sum();
+            if size.saturating_add(reserved_by_owner) > max_owner_size {
+                metrics::WRITES_REJECTED
+                    .with_label_values(&["upload", "quota"])
+                    .inc();
+                return Err(ApiError::bad_request(format!(
+                    "Upload of {size} bytes exceeds the prepaid balance of the owner"
+                )));
+            }
+            sessions.insert(
+                session,
+                SessionEntry {
+                    size,
+                    owner,
+                    session: Arc::new(Mutex::new(UploadSession {
+                        received: 0,
+                        engine: sha256::Hash::engine(),
+                        staging_path: staging_path.clone(),
+                        last_activity: Instant::now(),
+                    })),
+                },
+            );
+        }
+
+        if let Err(e) = File::create(&staging_path) {
+            self.0.lock().expect("lock poisoned").remove(&session);
+            return Err(internal_error(e));
+        }
+        debug!(session, size, %owner, "Started upload session");
+        Ok(session)
+    }
+
+    /// Appends a range of bytes to the session, returns the number of bytes
+    /// received so far
+    pub fn push(&self, request: UploadPushRequest) -> Result<u64, ApiError> {
+        let (size, session) = self.get(request.session)?;
+        let mut session = lock_session(&session)?;
+
+        if request.offset != session.received {
+            return Err(ApiError::bad_request(format!(
+                "Expected offset {}, got {}",
+                session.received, request.offset
+            )));
+        }
+        let len = request.bytes.len() as u64;
+        if len > MAX_CHUNK_SIZE {
+            return Err(ApiError::bad_request(format!(
+                "Chunk of {len} bytes exceeds the maximum of {MAX_CHUNK_SIZE}"
+            )));
+        }
+        if session.received + len > size {
+            return Err(ApiError::bad_request(format!(
+                "Upload exceeds the announced size of {size} bytes"
+            )));
+        }
+
+        OpenOptions::new()
+            .append(true)
+            .open(&session.staging_path)
+            .and_then(|mut file| file.write_all(&request.bytes))
+            .map_err(internal_error)?;
+        session.engine.input(&request.bytes);
+        session.received += len;
+        session.last_activity = Instant::now();
+
+        Ok(session.received)
+    }
+
+    /// Completes the session, moving the payload into the blob store if it is
+    /// complete and matches the expected hash, returns the size of the payload
+    pub fn finalize(
+        &self,
+        blobs: &BlobStore,
+        request: UploadFinalizeRequest,
+    ) -> Result<u64, ApiError> {
+        let (size, session) = self.get(request.session)?;
+        let session = lock_session(&session)?;
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .remove(&request.session);
+
+        if session.received != size {
+            remove_staged(&session);
+            return Err(ApiError::bad_request(format!(
+                "Upload incomplete, received {} of {size} bytes",
+                session.received
+            )));
+        }
+        let hash = sha256::Hash::from_engine(session.engine.clone());
+        if hash != request.hash {
+            remove_staged(&session);
+            return Err(ApiError::bad_request(format!(
+                "Uploaded payload has hash {hash}, expected {}",
+                request.hash
+            )));
+        }
+
+        blobs
+            .insert_staged(&session.staging_path, &hash)
+            .map_err(internal_error)?;
+        debug!(session = request.session, %hash, "Finished upload session");
+        Ok(size)
+    }
+
+    /// Drops all sessions that timed out, sessions busy with a request are
+    /// active and kept
+    pub fn remove_expired(&self) {
+        let mut expired = vec![];
+        self.0.lock().expect("lock poisoned").retain(|id, entry| {
+            let timed_out = entry.session.try_lock().map_or(false, |session| {
+                session.last_activity.elapsed() > UPLOAD_SESSION_TIMEOUT
+            });
+            if timed_out {
+                debug!(session = id, "Upload session timed out");
+                expired.push(entry.session.clone());
+            }
+            !timed_out
+        });
+        for session in expired {
+            if let Ok(session) = session.lock() {
+                remove_staged(&session);
+            }
+        }
+    }
+
+    fn get(&self, session: u64) -> Result<(u64, Arc<Mutex<UploadSession>>), ApiError> {
+        self.0
+            .lock()
+            .expect("lock poisoned")
+            .get(&session)
+            .map(|entry| (entry.size, entry.session.clone()))
+            .ok_or_else(|| ApiError::not_found(format!("Unknown session {session}")))
+    }
+}
+
+/// Removes timed out upload sessions and unreferenced uploads until the task
+/// group shuts down
+pub async fn run_session_cleanup(
+    sessions: UploadSessions,
+    db: Database,
+    blobs: BlobStore,
+    handle: &TaskHandle,
+) {
+    let mut shutdown = shutdown_signal(handle).await;
+    while !handle.is_shutting_down() {
+        sessions.remove_expired();
+        remove_unreferenced_uploads(&db, &blobs).await;
+        if !sleep_until_shutdown(&mut shutdown, Duration::from_secs(60)).await {
+            break;
+        }
+    }
+}
+
+/// Removes uploads older than [`UPLOAD_RETENTION`] that no entry references
+///
+/// Should a write reference such a blob right as it is removed, the blob is
+/// treated like any other damaged blob and repaired from our peers.
+async fn remove_unreferenced_uploads(db: &Database, blobs: &BlobStore) {
+    let mut dbtx = db.begin_transaction().await;
+    {
+        let mut dbtx = dbtx.with_module_prefix(LEGACY_HARDCODED_INSTANCE_ID_SMOLFS);
+        let now = SystemTime::now();
+        let expired = dbtx
+            .find_by_prefix(&UploadedBlobKeyPrefix)
+            .await
+            .map(|res| res.expect("DB Error"))
+            .filter(|(_, uploaded)| {
+                now.duration_since(*uploaded)
+                    .map_or(false, |age| age > UPLOAD_RETENTION)
+            })
+            .map(|(key, _)| key.0)
+            .collect::<Vec<_>>();
+
+        for hash in expired {
+            let referenced = dbtx
+                .get_value(&BlobRefKey(hash))
+                .await
+                .expect("DB Error")
+                .is_some();
+            if !referenced {
+                if let Err(e) = blobs.remove(&hash) {
+                    warn!(%hash, "Failed to remove unreferenced upload: {}", e);
+                    continue;
+                }
+                debug!(%hash, "Removed unreferenced upload");
+            }
+            dbtx.remove_entry(&UploadedBlobKey(hash))
+                .await
+                .expect("DB Error");
+        }
+    }
+    if let Err(e) = dbtx.commit_tx().await {
+        // Conflicts with an upload of the same payload finishing right now, the
+        // next run tries again
+        warn!("Failed to record removed uploads: {}", e);
+    }
+}
+
+fn lock_session(
+    session: &Mutex<UploadSession>,
+) -> Result<std::sync::MutexGuard<'_, UploadSession>, ApiError> {
+    // Clients push sequentially, a concurrent request to the same session is a client error
+    session
+        .try_lock()
+        .map_err(|_| ApiError::new(409, "Upload session is busy".to_string()))
+}
+
+fn remove_staged(session: &UploadSession) {
+    if let Err(e) = fs::remove_file(&session.staging_path) {
+        warn!(path = ?session.staging_path, "Failed to remove staged upload: {}", e);
+    }
+}
+
+fn internal_error(e: std::io::Error) -> ApiError {
+    ApiError::new(500, format!("Storage error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bitcoin::hashes::{sha256, Hash};
+    use rand::rngs::OsRng;
+    use secp256k1::Secp256k1;
+
+    use super::{UploadFinalizeRequest, UploadPushRequest, UploadSessions};
+    use crate::blob::BlobStore;
+
+    #[test]
+    fn chunked_upload() {
+        let dir = std::env::temp_dir().join(format!("smolfs-upload-{}", rand::random::<u64>()));
+        let blobs = BlobStore::open(&dir).unwrap();
+        let sessions = UploadSessions::default();
+        let payload = b"hello chunked smolfs";
+
+        let (_, owner) = Secp256k1::new().generate_keypair(&mut OsRng);
+        let owner = owner.x_only_public_key().0;
+
+        assert!(sessions.start(&blobs, owner, 21, 20, 1024).is_err());
+        assert!(sessions.start(&blobs, owner, 21, 1024, 20).is_err());
+        let session = sessions
+            .start(&blobs, owner, payload.len() as u64, 1024, 1024)
+            .unwrap();
+
+        let push = |offset: usize, bytes: &[u8]| {
+            sessions.push(UploadPushRequest {
+                session,
+                offset: offset as u64,
+                bytes: bytes.to_vec(),
+            })
+        };
+        assert_eq!(push(0, &payload[..5]).unwrap(), 5);
+        assert!(push(0, &payload[5..]).is_err(), "wrong offset");
+        assert_eq!(push(5, &payload[5..]).unwrap(), payload.len() as u64);
+        assert!(push(payload.len(), b"x").is_err(), "exceeds size");
+
+        let hash = sha256::Hash::hash(payload);
+        assert_eq!(
+            sessions
+                .finalize(&blobs, UploadFinalizeRequest { session, hash })
+                .unwrap(),
+            payload.len() as u64
+        );
+        assert_eq!(blobs.get(&hash).unwrap().unwrap(), payload);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,8 @@
+#!/usr/bin/env bash
+# Runs the client tests that target the browser in a headless browser, needs
+# `wasm-bindgen-test-runner` and `geckodriver` or `chromedriver` in PATH
+
+set -euxo pipefail
+
+export CARGO_TARGET_WASM32_UNKNOWN_UNKNOWN_RUNNER=wasm-bindgen-test-runner
+cargo test -p mint-client --target wasm32-unknown-unknown --test smolfs_wasm